@@ -0,0 +1,149 @@
+// src/admins.rs
+
+//! Кто может управлять ботом.
+//! - Супер-админы из `.env` (`ADMIN_USER_IDS`) — всё и везде.
+//! - Администраторы чата в Telegram (если `DELEGATE_CHAT_ADMINS=true`) —
+//!   whitelist и настройки только своего чата.
+//!
+//! Список админов чата кэшируется на `ADMIN_CACHE_TTL_SEC`, чтобы не дёргать
+//! `get_chat_administrators` на каждую команду.
+
+use crate::api;
+use crate::state::{AppState, WlScope};
+use dashmap::DashMap;
+use log::warn;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+
+/// Уровень доступа пользователя к конкретному чату.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    None,
+    /// Администратор этого чата в Telegram (делегирование).
+    ChatAdmin,
+    /// Глобальный админ бота.
+    SuperAdmin,
+}
+
+impl Access {
+    #[inline]
+    pub fn is_admin(self) -> bool {
+        self != Access::None
+    }
+
+    /// Какой whitelist правит этот уровень доступа в чате `chat`.
+    pub fn scope(self, chat: ChatId) -> WlScope {
+        match self {
+            Access::SuperAdmin => WlScope::Global,
+            _ => WlScope::Chat(chat),
+        }
+    }
+}
+
+/// TTL-кэш: chat -> (когда получили, id админов).
+pub struct AdminCache {
+    ttl: Duration,
+    chats: DashMap<ChatId, (Instant, HashSet<u64>)>,
+}
+
+impl AdminCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            chats: DashMap::new(),
+        }
+    }
+
+    /// `Some(is_admin)`, если запись свежая; `None` — надо спросить API.
+    fn lookup(&self, chat: ChatId, user: UserId) -> Option<bool> {
+        let entry = self.chats.get(&chat)?;
+        let (at, ids) = entry.value();
+        (at.elapsed() < self.ttl).then(|| ids.contains(&user.0))
+    }
+
+    fn store(&self, chat: ChatId, ids: HashSet<u64>) {
+        self.chats.insert(chat, (Instant::now(), ids));
+    }
+
+    /// Сбросить кэш чата (например, кого-то повысили/понизили).
+    pub fn invalidate(&self, chat: ChatId) {
+        self.chats.remove(&chat);
+    }
+}
+
+/// Доступ `user` к чату `chat`.
+pub async fn access(bot: &Bot, state: &AppState, chat: ChatId, user: UserId) -> Access {
+    if state.cfg.is_super_admin(user) {
+        return Access::SuperAdmin;
+    }
+    // В личке «админов чата» нет.
+    if !state.cfg.delegate_chat_admins || chat.is_user() {
+        return Access::None;
+    }
+    if is_chat_admin(bot, state, chat, user).await {
+        Access::ChatAdmin
+    } else {
+        Access::None
+    }
+}
+
+/// Супер-админ или администратор чата в Telegram (независимо от
+/// делегирования): фильтр содержимого их не трогает.
+pub async fn is_moderator(bot: &Bot, state: &AppState, chat: ChatId, user: UserId) -> bool {
+    state.cfg.is_super_admin(user) || is_chat_admin(bot, state, chat, user).await
+}
+
+async fn is_chat_admin(bot: &Bot, state: &AppState, chat: ChatId, user: UserId) -> bool {
+    if let Some(hit) = state.admin_cache.lookup(chat, user) {
+        return hit;
+    }
+    let req = bot.get_chat_administrators(chat);
+    match api::call(state, chat, "get_chat_administrators", req).await {
+        Ok(admins) => {
+            let ids: HashSet<u64> = admins
+                .into_iter()
+                .filter(|m| !m.user.is_bot)
+                .map(|m| m.user.id.0)
+                .collect();
+            let hit = ids.contains(&user.0);
+            state.admin_cache.store(chat, ids);
+            hit
+        }
+        Err(e) => {
+            warn!("get_chat_administrators failed (chat={}): {}", chat.0, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_respects_ttl_and_invalidate() {
+        let cache = AdminCache::new(Duration::from_secs(60));
+        let chat = ChatId(-100);
+        assert_eq!(cache.lookup(chat, UserId(1)), None);
+
+        cache.store(chat, HashSet::from([1]));
+        assert_eq!(cache.lookup(chat, UserId(1)), Some(true));
+        assert_eq!(cache.lookup(chat, UserId(2)), Some(false));
+
+        cache.invalidate(chat);
+        assert_eq!(cache.lookup(chat, UserId(1)), None);
+
+        let expired = AdminCache::new(Duration::ZERO);
+        expired.store(chat, HashSet::from([1]));
+        assert_eq!(expired.lookup(chat, UserId(1)), None);
+    }
+
+    #[test]
+    fn access_scope() {
+        let chat = ChatId(-100);
+        assert_eq!(Access::SuperAdmin.scope(chat), WlScope::Global);
+        assert_eq!(Access::ChatAdmin.scope(chat), WlScope::Chat(chat));
+        assert!(!Access::None.is_admin());
+    }
+}
@@ -0,0 +1,213 @@
+// src/api.rs
+
+//! Вызовы Telegram API с ограничением темпа и повторами.
+//! - Темп: общий лимит бота и лимит на чат (очередь «следующего слота»:
+//!   вызов резервирует время и спит до него, без общего замка на ожидание).
+//! - `RetryAfter` (429) — замораживаем чат на указанное время и повторяем.
+//! - Сеть/IO — повторяем с растущей паузой; ответ Telegram «нельзя» — сразу ошибка.
+//! - `moderate` — для банов/ограничений: окончательный отказ пишется в журнал.
+//! - `delete_messages` — пачками `deleteMessages` вместо удаления по одному.
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::state::AppState;
+use dashmap::DashMap;
+use log::warn;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::requests::Output;
+use teloxide::types::MessageId;
+use teloxide::RequestError;
+
+/// Общий темп бота (Telegram держит ~30 запросов/с).
+const GLOBAL_INTERVAL: Duration = Duration::from_millis(35);
+/// Темп в одном чате: кнопки, удаления, баны в рейд идут пачками.
+const CHAT_INTERVAL: Duration = Duration::from_millis(200);
+/// Повторов после сетевой ошибки или 429.
+const MAX_RETRIES: u32 = 3;
+/// Пауза перед повтором после сетевой ошибки: 1 с, 2 с, 4 с.
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+/// Предел `deleteMessages` за один вызов.
+const DELETE_BATCH: usize = 100;
+/// Больше чатов в карте слотов — выкидываем те, где очередь уже прошла.
+const PRUNE_AT: usize = 1024;
+
+/// Ближайшие свободные слоты: общий и по чатам.
+pub struct Limiter {
+    global: Mutex<Instant>,
+    chats: DashMap<ChatId, Instant>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            global: Mutex::new(Instant::now()),
+            chats: DashMap::new(),
+        }
+    }
+}
+
+impl Limiter {
+    /// Зарезервировать слот; вернуть, сколько ждать до него.
+    fn reserve(&self, chat: ChatId, now: Instant) -> Duration {
+        if self.chats.len() > PRUNE_AT {
+            self.prune(now);
+        }
+        let global = {
+            let mut next = self.global.lock().expect("limiter lock");
+            let slot = (*next).max(now);
+            *next = slot + GLOBAL_INTERVAL;
+            slot
+        };
+        let chat = {
+            let mut next = self.chats.entry(chat).or_insert(now);
+            let slot = (*next).max(now);
+            *next = slot + CHAT_INTERVAL;
+            slot
+        };
+        global.max(chat) - now
+    }
+
+    /// Telegram попросил подождать: ничего не слать в чат до `until`.
+    fn freeze(&self, chat: ChatId, until: Instant) {
+        let mut next = self.chats.entry(chat).or_insert(until);
+        *next = (*next).max(until);
+    }
+
+    /// Забыть чаты без очереди, чтобы карта не росла.
+    fn prune(&self, now: Instant) {
+        self.chats.retain(|_, next| *next > now);
+    }
+}
+
+/// Выполнить запрос в чате `chat` с лимитами и повторами.
+/// Неудача считается в метриках под именем `method`.
+pub async fn call<R>(
+    state: &AppState,
+    chat: ChatId,
+    method: &'static str,
+    req: R,
+) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+{
+    let mut retries = 0;
+    loop {
+        let wait = state.limiter.reserve(chat, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        let err = match req.send_ref().await {
+            Ok(out) => return Ok(out),
+            Err(e) => e,
+        };
+        if retries >= MAX_RETRIES || !is_transient(&err) {
+            state.metrics.api_error(method);
+            return Err(err);
+        }
+        retries += 1;
+        let pause = match &err {
+            RequestError::RetryAfter(secs) => {
+                let pause = secs.duration();
+                state.limiter.freeze(chat, Instant::now() + pause);
+                pause
+            }
+            _ => BACKOFF_BASE * 2u32.pow(retries - 1),
+        };
+        warn!(
+            "{method} failed (chat={}), retry {retries}/{MAX_RETRIES} in {}s: {err}",
+            chat.0,
+            pause.as_secs()
+        );
+        tokio::time::sleep(pause).await;
+    }
+}
+
+/// `call` для действий модерации: если не вышло и после повторов —
+/// запись в журнал (`api_failure`), чтобы админы видели несработавший бан.
+pub async fn moderate<R>(
+    state: &AppState,
+    chat: ChatId,
+    target: impl Into<String>,
+    method: &'static str,
+    req: R,
+) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+{
+    let res = call(state, chat, method, req).await;
+    if let Err(e) = &res {
+        state.record(AuditEntry::new(
+            chat,
+            None,
+            target,
+            AuditAction::ApiFailure,
+            format!("{method}: {e}"),
+        ));
+    }
+    res
+}
+
+/// Удалить сообщения чата пачками по 100. Возвращает (удалено, ошибок) —
+/// по размеру пачек: Telegram отвечает за пачку целиком, а уже удалённые
+/// сообщения молча пропускает.
+pub async fn delete_messages(
+    bot: &Bot,
+    state: &AppState,
+    chat: ChatId,
+    ids: &[MessageId],
+) -> (usize, usize) {
+    let (mut ok, mut err) = (0, 0);
+    for chunk in ids.chunks(DELETE_BATCH) {
+        let req = bot.delete_messages(chat, chunk.iter().copied());
+        match call(state, chat, "delete_messages", req).await {
+            Ok(_) => ok += chunk.len(),
+            Err(e) => {
+                err += chunk.len();
+                warn!(
+                    "delete_messages failed for {} message(s) (chat={}): {e}",
+                    chunk.len(),
+                    chat.0
+                );
+            }
+        }
+    }
+    state.metrics.messages_deleted(ok as u64);
+    (ok, err)
+}
+
+/// Ответ Telegram «нельзя» повтором не исправить; сеть и флуд-лимит — можно.
+pub fn is_transient(e: &RequestError) -> bool {
+    matches!(
+        e,
+        RequestError::Network(_) | RequestError::Io(_) | RequestError::RetryAfter(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_consecutive_slots_per_chat() {
+        let l = Limiter::default();
+        let now = Instant::now();
+        let a = ChatId(-1);
+        assert_eq!(l.reserve(a, now), Duration::ZERO);
+        assert_eq!(l.reserve(a, now), CHAT_INTERVAL);
+        assert_eq!(l.reserve(a, now), CHAT_INTERVAL * 2);
+        // другой чат ждёт только общий лимит
+        assert_eq!(l.reserve(ChatId(-2), now), GLOBAL_INTERVAL * 3);
+    }
+
+    #[test]
+    fn freeze_delays_chat() {
+        let l = Limiter::default();
+        let now = Instant::now();
+        let a = ChatId(-1);
+        l.freeze(a, now + Duration::from_secs(5));
+        assert_eq!(l.reserve(a, now), Duration::from_secs(5));
+        l.prune(now + Duration::from_secs(10));
+        assert_eq!(l.reserve(a, now + Duration::from_secs(10)), Duration::ZERO);
+    }
+}
@@ -1,36 +1,147 @@
-use crate::{captcha, config::Config, handlers, state::AppState};
-use anyhow::Result;
+use crate::{
+    audit, captcha, config::Config, handlers, metrics, raid, scheduler, settings_menu,
+    state::AppState,
+};
+use anyhow::{Context, Result};
 use dotenvy::dotenv;
-use log::info;
+use log::{error, info};
 use std::sync::Arc;
-use teloxide::{dptree, prelude::*};
+use teloxide::{
+    dptree,
+    prelude::*,
+    types::AllowedUpdate,
+    update_listeners::{webhooks, Polling, UpdateListener},
+};
+
+/// Какие апдейты просим у Telegram (и вебхуком, и polling'ом). `chat_member`
+/// по умолчанию не приходит — без него вступления видно только по
+/// сервисным сообщениям.
+const ALLOWED_UPDATES: [AllowedUpdate; 5] = [
+    AllowedUpdate::Message,
+    AllowedUpdate::ChatMember,
+    AllowedUpdate::ChatJoinRequest,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::MyChatMember,
+];
 
 pub async fn run() -> Result<()> {
     dotenv().ok();
     pretty_env_logger::init();
 
     let bot = Bot::from_env();
-    // На всякий случай — polling-only.
-    bot.delete_webhook().await.ok();
 
     let cfg = Config::from_env();
+    let webhook = cfg.webhook.clone();
+    let metrics_listen = cfg.metrics_listen;
     let state = Arc::new(AppState::new(cfg));
 
     info!("Starting telegram-ranger…");
 
+    // Капчи, не завершённые до рестарта: вернуть таймеры.
+    if !state.pending.is_empty() {
+        info!("Restoring {} pending captcha(s)", state.pending.len());
+        captcha::resume_pending(&state);
+    }
+    if !state.jobs.is_empty() {
+        info!("{} scheduled job(s) in queue", state.jobs.len());
+    }
+    raid::resume(&state);
+    scheduler::spawn(bot.clone(), state.clone());
+
+    if let Some(addr) = metrics_listen {
+        metrics::serve(addr, state.clone());
+    }
+
     // Регистрация хендлеров.
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(handlers::on_message))
         .branch(Update::filter_chat_member().endpoint(handlers::on_chat_member_update))
+        .branch(Update::filter_chat_join_request().endpoint(handlers::on_join_request))
+        .branch(
+            Update::filter_callback_query()
+                .filter(|q: CallbackQuery| {
+                    q.data
+                        .as_deref()
+                        .is_some_and(|d| d.starts_with(settings_menu::PREFIX))
+                })
+                .endpoint(settings_menu::on_callback),
+        )
+        .branch(
+            Update::filter_callback_query()
+                .filter(|q: CallbackQuery| {
+                    q.data
+                        .as_deref()
+                        .is_some_and(|d| d.starts_with(audit::PREFIX))
+                })
+                .endpoint(audit::on_callback),
+        )
         .branch(Update::filter_callback_query().endpoint(captcha::on_callback));
 
     // Прокидываем зависимости в дерево.
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![state])
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
+        .dependencies(dptree::deps![state.clone()])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    match webhook {
+        Some(wh) => {
+            info!("Webhook mode: {} (listening on {})", wh.url, wh.listen);
+            // Занять порт до setWebhook: не вышло — падаем сразу, а не
+            // работаем без приёма апдейтов.
+            let tcp = tokio::net::TcpListener::bind(wh.listen)
+                .await
+                .with_context(|| format!("bind WEBHOOK_LISTEN {}", wh.listen))?;
+            let mut opts = webhooks::Options::new(wh.listen, wh.url);
+            if let Some(path) = wh.path {
+                opts = opts.path(path);
+            }
+            if let Some(secret) = wh.secret {
+                opts = opts.secret_token(secret);
+            }
+            // setWebhook — сами: teloxide не передаёт allowed_updates, а без
+            // них Telegram не шлёт chat_member.
+            let secret = opts.get_or_gen_secret_token().to_owned();
+            bot.set_webhook(opts.url.clone())
+                .secret_token(secret)
+                .allowed_updates(ALLOWED_UPDATES)
+                .await?;
+            // Запросы без верного X-Telegram-Bot-Api-Secret-Token отбиваются листенером.
+            let (mut listener, stop_flag, router) = webhooks::axum_no_setup(opts);
+            let stop_token = listener.stop_token();
+            tokio::spawn(async move {
+                let shutdown = async move {
+                    stop_flag.await;
+                    bot.delete_webhook().await.ok();
+                };
+                if let Err(e) = axum::serve(tcp, router)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+                {
+                    error!("Webhook HTTP server error: {e}");
+                    stop_token.stop();
+                }
+            });
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("Webhook listener error"),
+                )
+                .await;
+        }
+        None => {
+            // Polling: снимаем вебхук, иначе getUpdates не работает.
+            bot.delete_webhook().await.ok();
+            let polling = Polling::builder(bot)
+                .allowed_updates(ALLOWED_UPDATES.to_vec())
+                .build();
+            dispatcher
+                .dispatch_with_listener(
+                    polling,
+                    LoggingErrorHandler::with_custom_text("Polling listener error"),
+                )
+                .await;
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,322 @@
+// src/audit.rs
+
+//! Журнал модерации: кто, кого, где, что и почему. Пишется в хранилище
+//! (`Store::append_audit`), смотрится командой `/audit [chat_id] [n]`
+//! с листанием inline-кнопками.
+
+use crate::admins::{self, Access};
+use crate::i18n::{self, tr, Lang};
+use crate::state::AppState;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+use teloxide::utils::html::escape;
+
+/// Префикс callback-данных листания: `aud:{chat|0}:{offset}:{n}`.
+pub const PREFIX: &str = "aud:";
+
+const DEFAULT_PAGE: usize = 10;
+const MAX_PAGE: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    CaptchaPassed,
+    Ban,
+    SoftKick,
+    Mute,
+    BotBanned,
+    WhitelistAdd,
+    WhitelistRemove,
+    SettingsChanged,
+    LockdownOn,
+    LockdownOff,
+    /// Telegram не выполнил действие модерации (и после повторов).
+    ApiFailure,
+    /// Заявка на вступление отклонена (капча в личке не пройдена).
+    JoinDeclined,
+    /// Сработал фильтр содержимого (`reason` — правило и действие).
+    FilterHit,
+    /// Предупреждение участнику (админом или фильтром).
+    Warn,
+    /// Админ снял предупреждение.
+    Unwarn,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::CaptchaPassed => "captcha_passed",
+            AuditAction::Ban => "ban",
+            AuditAction::SoftKick => "soft_kick",
+            AuditAction::Mute => "mute",
+            AuditAction::BotBanned => "bot_banned",
+            AuditAction::WhitelistAdd => "whitelist_add",
+            AuditAction::WhitelistRemove => "whitelist_remove",
+            AuditAction::SettingsChanged => "settings_changed",
+            AuditAction::LockdownOn => "lockdown_on",
+            AuditAction::LockdownOff => "lockdown_off",
+            AuditAction::ApiFailure => "api_failure",
+            AuditAction::JoinDeclined => "join_declined",
+            AuditAction::FilterHit => "filter_hit",
+            AuditAction::Warn => "warn",
+            AuditAction::Unwarn => "unwarn",
+        }
+    }
+}
+
+/// Одна запись журнала.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub ts: DateTime<Utc>,
+    pub chat_id: i64,
+    /// Кто сделал: админ или `None` — сам бот.
+    pub actor: Option<u64>,
+    /// Над кем/чем: user id, `@username`, чат.
+    pub target: String,
+    pub action: AuditAction,
+    pub reason: String,
+}
+
+impl AuditEntry {
+    pub fn new(
+        chat: ChatId,
+        actor: Option<UserId>,
+        target: impl Into<String>,
+        action: AuditAction,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            ts: Utc::now(),
+            chat_id: chat.0,
+            actor: actor.map(|u| u.0),
+            target: target.into(),
+            action,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// `/audit [chat_id] [n]`. В группе по умолчанию — текущий чат; супер-админ
+/// в личке без аргументов видит все чаты. Админ чата — только свой чат.
+pub async fn show(
+    bot: &Bot,
+    state: Arc<AppState>,
+    msg: &Message,
+    access: Access,
+    arg: Option<&str>,
+) -> Result<()> {
+    let lang = i18n::chat_lang(&state, msg.chat.id, msg.from.as_ref());
+    let (chat, n) = match parse_args(arg) {
+        Some(v) => v,
+        None => {
+            let usage = tr!(
+                lang,
+                "commands.usage",
+                syntax = "<code>/audit [chat_id] [n]</code>"
+            );
+            bot.send_message(msg.chat.id, usage)
+                .parse_mode(ParseMode::Html)
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let chat = match (access, chat) {
+        (Access::SuperAdmin, Some(c)) => Some(c),
+        (Access::SuperAdmin, None) if msg.chat.id.is_user() => None,
+        _ => Some(msg.chat.id),
+    };
+
+    let (text, kb) = render_page(&state, lang, chat, 0, n);
+    let mut req = bot
+        .send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html);
+    if let Some(kb) = kb {
+        req = req.reply_markup(kb);
+    }
+    req.await?;
+    Ok(())
+}
+
+/// Кнопки «старее/новее».
+pub async fn on_callback(bot: Bot, state: Arc<AppState>, q: CallbackQuery) -> Result<()> {
+    let _t = state.metrics.time("callback");
+    bot.answer_callback_query(q.id.clone()).await.ok();
+    let Some((chat, offset, n)) = q.data.as_deref().and_then(parse_page) else {
+        return Ok(());
+    };
+    let Some(menu) = &q.message else {
+        return Ok(());
+    };
+
+    // Права проверяем заново: кнопку мог нажать кто угодно в чате.
+    let allowed = match chat {
+        Some(c) => admins::access(&bot, &state, c, q.from.id).await.is_admin(),
+        None => state.cfg.is_super_admin(q.from.id),
+    };
+    if !allowed {
+        return Ok(());
+    }
+
+    let lang = i18n::chat_lang(&state, menu.chat().id, Some(&q.from));
+    let (text, kb) = render_page(&state, lang, chat, offset, n);
+    let mut req = bot
+        .edit_message_text(menu.chat().id, menu.id(), text)
+        .parse_mode(ParseMode::Html);
+    if let Some(kb) = kb {
+        req = req.reply_markup(kb);
+    }
+    req.await.ok();
+    Ok(())
+}
+
+fn render_page(
+    state: &AppState,
+    lang: Lang,
+    chat: Option<ChatId>,
+    offset: usize,
+    n: usize,
+) -> (String, Option<InlineKeyboardMarkup>) {
+    // Берём на одну больше, чтобы понять, есть ли страница дальше.
+    let mut entries = state.audit_log(chat, offset, n + 1);
+    let has_older = entries.len() > n;
+    entries.truncate(n);
+
+    let title = match chat {
+        Some(c) => tr!(lang, "audit.title_chat", chat = c.0),
+        None => tr!(lang, "audit.title_all").to_string(),
+    };
+    let text = if entries.is_empty() {
+        format!("{title}\n{}", tr!(lang, "audit.empty"))
+    } else {
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|e| format_entry(e, chat.is_none()))
+            .collect();
+        format!(
+            "{title} #{}–{}\n{}",
+            offset + 1,
+            offset + entries.len(),
+            lines.join("\n")
+        )
+    };
+
+    let chat_key = chat.map_or(0, |c| c.0);
+    let mut row = Vec::new();
+    if offset > 0 {
+        row.push(InlineKeyboardButton::callback(
+            tr!(lang, "audit.newer"),
+            format!("{PREFIX}{chat_key}:{}:{n}", offset.saturating_sub(n)),
+        ));
+    }
+    if has_older {
+        row.push(InlineKeyboardButton::callback(
+            tr!(lang, "audit.older"),
+            format!("{PREFIX}{chat_key}:{}:{n}", offset + n),
+        ));
+    }
+    let kb = (!row.is_empty()).then(|| InlineKeyboardMarkup::new([row]));
+    (text, kb)
+}
+
+fn format_entry(e: &AuditEntry, with_chat: bool) -> String {
+    let actor = match e.actor {
+        Some(id) => format!(r#"<a href="tg://user?id={id}">{id}</a>"#),
+        None => "bot".to_string(),
+    };
+    let chat = if with_chat {
+        format!(" [{}]", e.chat_id)
+    } else {
+        String::new()
+    };
+    let reason = if e.reason.is_empty() {
+        String::new()
+    } else {
+        format!(" — {}", escape(&e.reason))
+    };
+    format!(
+        "<code>{}</code>{chat} <b>{}</b> {} by {actor}{reason}",
+        e.ts.format("%Y-%m-%d %H:%M"),
+        e.action.as_str(),
+        escape(&e.target),
+    )
+}
+
+/// `[chat_id] [n]`: одно число — `n`, если оно небольшое и положительное,
+/// иначе id чата. `None` — мусор в аргументах.
+fn parse_args(arg: Option<&str>) -> Option<(Option<ChatId>, usize)> {
+    let nums: Vec<i64> = match arg {
+        Some(a) => a
+            .split_whitespace()
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?,
+        None => Vec::new(),
+    };
+    let clamp = |n: i64| (n.max(1) as usize).min(MAX_PAGE);
+    match nums.as_slice() {
+        [] => Some((None, DEFAULT_PAGE)),
+        [n] if (1..=MAX_PAGE as i64).contains(n) => Some((None, *n as usize)),
+        [c] => Some((Some(ChatId(*c)), DEFAULT_PAGE)),
+        [c, n] => Some((Some(ChatId(*c)), clamp(*n))),
+        _ => None,
+    }
+}
+
+/// `aud:{chat|0}:{offset}:{n}`.
+fn parse_page(data: &str) -> Option<(Option<ChatId>, usize, usize)> {
+    let mut it = data.strip_prefix(PREFIX)?.split(':');
+    let chat: i64 = it.next()?.parse().ok()?;
+    let offset = it.next()?.parse().ok()?;
+    let n: usize = it.next()?.parse().ok()?;
+    let chat = (chat != 0).then_some(ChatId(chat));
+    Some((chat, offset, n.clamp(1, MAX_PAGE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_audit_args() {
+        assert_eq!(parse_args(None), Some((None, DEFAULT_PAGE)));
+        assert_eq!(parse_args(Some("20")), Some((None, 20)));
+        assert_eq!(
+            parse_args(Some("-100123")),
+            Some((Some(ChatId(-100123)), DEFAULT_PAGE))
+        );
+        assert_eq!(
+            parse_args(Some("-100123 500")),
+            Some((Some(ChatId(-100123)), MAX_PAGE))
+        );
+        assert_eq!(parse_args(Some("abc")), None);
+    }
+
+    #[test]
+    fn parse_page_payload() {
+        assert_eq!(parse_page("aud:0:10:10"), Some((None, 10, 10)));
+        assert_eq!(
+            parse_page("aud:-100:20:5"),
+            Some((Some(ChatId(-100)), 20, 5))
+        );
+        assert_eq!(parse_page("set:-100:mode:off"), None);
+    }
+
+    #[test]
+    fn entry_formatting_escapes() {
+        let e = AuditEntry::new(
+            ChatId(-1),
+            None,
+            "@evil<b>",
+            AuditAction::Ban,
+            "captcha timeout",
+        );
+        let s = format_entry(&e, false);
+        assert!(s.contains("@evil&lt;b&gt;"));
+        assert!(s.contains("<b>ban</b>"));
+        assert!(s.contains("by bot"));
+    }
+}
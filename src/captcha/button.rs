@@ -14,27 +14,34 @@ impl Captcha for ButtonCaptcha {
         bot: &Bot,
         state: &AppState,
         chat_id: ChatId,
+        to: ChatId,
         user: &User,
     ) -> Result<Challenge> {
-        let mention_text = mention(bot, chat_id, user.id).await;
-
-        let msg = bot
-            .send_message(
-                chat_id,
-                format!(
-                    "{mention_text}, нажмите кнопку за {} секунд",
-                    state.cfg.captcha_timeout_secs
-                ),
-            )
+        let nonce = new_nonce();
+        let settings = state.settings(chat_id);
+        let lang = settings.prompt_lang(user);
+        let task = tr!(
+            lang,
+            "captcha.task_button",
+            secs = settings.captcha_timeout_secs
+        );
+        let text = prompt_text(bot, state, chat_id, user, &task).await;
+
+        let req = bot
+            .send_message(to, text)
             .parse_mode(ParseMode::Html)
             .reply_markup(InlineKeyboardMarkup::new([[
-                InlineKeyboardButton::callback("✅ I’m human", format!("ok:{}", user.id.0)),
-            ]]))
-            .await?;
+                InlineKeyboardButton::callback(
+                    tr!(lang, "captcha.button_label"),
+                    format!("ok:{}:{nonce}", user.id.0),
+                ),
+            ]]));
+        let msg = api::call(state, to, "send_message", req).await?;
 
         Ok(Challenge {
             message: msg,
             expected_answer: None,
+            nonce,
         })
     }
 
@@ -43,8 +50,9 @@ impl Captcha for ButtonCaptcha {
         _bot: &Bot,
         _state: Arc<AppState>,
         q: &CallbackQuery,
+        _pend: &Pending,
     ) -> Result<bool> {
-        Ok(q.data.as_deref().map_or(false, |d| d.starts_with("ok:")))
+        Ok(q.data.as_deref().is_some_and(|d| d.starts_with("ok:")))
     }
 }
 
@@ -0,0 +1,269 @@
+// choice.rs
+//! Капча «выбери правильный вариант»: вопрос + 4–6 inline-кнопок, верна одна.
+//! Ответ проверяется по `Pending.expected_answer` (индекс верной кнопки),
+//! так что в чат ничего писать не нужно.
+
+use super::*;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{rng, Rng};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+/// Префикс callback-данных: `choice:{user_id}:{nonce}:{index}`.
+const PREFIX: &str = "choice:";
+
+/// Категория вопросов: «Что из этого — {label}?» и её представители.
+/// Всё — ключи каталога строк (`choice.category.*`, `choice.item.*`).
+struct Category {
+    label: &'static str,
+    members: &'static [&'static str],
+}
+
+const CATEGORIES: &[Category] = &[
+    Category {
+        label: "choice.category.fruit",
+        members: &[
+            "choice.item.apple",
+            "choice.item.banana",
+            "choice.item.pear",
+            "choice.item.orange",
+            "choice.item.peach",
+        ],
+    },
+    Category {
+        label: "choice.category.animal",
+        members: &[
+            "choice.item.cat",
+            "choice.item.dog",
+            "choice.item.horse",
+            "choice.item.cow",
+            "choice.item.fox",
+        ],
+    },
+    Category {
+        label: "choice.category.transport",
+        members: &[
+            "choice.item.car",
+            "choice.item.bus",
+            "choice.item.bicycle",
+            "choice.item.plane",
+            "choice.item.train",
+        ],
+    },
+    Category {
+        label: "choice.category.tool",
+        members: &[
+            "choice.item.hammer",
+            "choice.item.saw",
+            "choice.item.wrench",
+            "choice.item.screwdriver",
+        ],
+    },
+    Category {
+        label: "choice.category.clothes",
+        members: &[
+            "choice.item.tshirt",
+            "choice.item.jacket",
+            "choice.item.jeans",
+            "choice.item.socks",
+            "choice.item.scarf",
+        ],
+    },
+    Category {
+        label: "choice.category.instrument",
+        members: &[
+            "choice.item.guitar",
+            "choice.item.piano",
+            "choice.item.drum",
+            "choice.item.violin",
+            "choice.item.trumpet",
+        ],
+    },
+];
+
+/// Один сгенерированный вопрос.
+struct Question {
+    label: &'static str,
+    options: Vec<&'static str>,
+    correct: usize,
+}
+
+pub struct ChoiceCaptcha;
+
+#[async_trait]
+impl Captcha for ChoiceCaptcha {
+    fn mode(&self) -> CaptchaMode {
+        CaptchaMode::Choice
+    }
+
+    async fn ask(
+        &self,
+        bot: &Bot,
+        state: &AppState,
+        chat_id: ChatId,
+        to: ChatId,
+        user: &User,
+    ) -> Result<Challenge> {
+        let q = {
+            let mut rng = rng();
+            let n = rng.random_range(4..=6);
+            make_question(&mut rng, n)
+        };
+
+        let nonce = new_nonce();
+        let settings = state.settings(chat_id);
+        let lang = settings.prompt_lang(user);
+
+        let buttons: Vec<InlineKeyboardButton> = q
+            .options
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                InlineKeyboardButton::callback(
+                    tr!(lang, key),
+                    format!("{PREFIX}{}:{nonce}:{i}", user.id.0),
+                )
+            })
+            .collect();
+        let rows: Vec<Vec<InlineKeyboardButton>> = buttons.chunks(2).map(|c| c.to_vec()).collect();
+
+        let task = tr!(
+            lang,
+            "captcha.task_choice",
+            category = tr!(lang, q.label),
+            secs = settings.captcha_timeout_secs,
+        );
+        let text = prompt_text(bot, state, chat_id, user, &task).await;
+
+        let req = bot
+            .send_message(to, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(InlineKeyboardMarkup::new(rows));
+        let msg = api::call(state, to, "send_message", req).await?;
+
+        Ok(Challenge {
+            message: msg,
+            expected_answer: Some(q.correct.to_string()),
+            nonce,
+        })
+    }
+
+    async fn on_callback(
+        &self,
+        _bot: &Bot,
+        _state: Arc<AppState>,
+        q: &CallbackQuery,
+        pend: &Pending,
+    ) -> Result<bool> {
+        Ok(picked_right(q.data.as_deref(), pend))
+    }
+}
+
+/// Вопрос с `n` вариантами: один из выбранной категории, остальные — из других.
+fn make_question(rng: &mut impl Rng, n: usize) -> Question {
+    let cat = CATEGORIES.choose(rng).expect("categories not empty");
+    let answer = *cat.members.choose(rng).expect("members not empty");
+
+    let mut distractors: Vec<&'static str> = CATEGORIES
+        .iter()
+        .filter(|c| !std::ptr::eq(*c, cat))
+        .flat_map(|c| c.members.iter().copied())
+        .collect();
+    distractors.shuffle(rng);
+    distractors.truncate(n.saturating_sub(1));
+
+    let mut options = distractors;
+    options.push(answer);
+    options.shuffle(rng);
+    let correct = options
+        .iter()
+        .position(|o| *o == answer)
+        .expect("answer is in options");
+
+    Question {
+        label: cat.label,
+        options,
+        correct,
+    }
+}
+
+/// Нажата верная кнопка.
+fn picked_right(data: Option<&str>, pend: &Pending) -> bool {
+    match (data.and_then(parse_choice), pend.expected_answer.as_deref()) {
+        (Some(i), Some(expected)) => i.to_string() == expected,
+        _ => false,
+    }
+}
+
+/// `choice:{user_id}:{nonce}:{index}` -> index.
+fn parse_choice(data: &str) -> Option<usize> {
+    let rest = data.strip_prefix(PREFIX)?;
+    let (_target, idx) = rest.rsplit_once(':')?;
+    idx.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choice_mode() {
+        assert_eq!(ChoiceCaptcha.mode(), CaptchaMode::Choice);
+    }
+
+    #[test]
+    fn question_has_single_correct_option() {
+        let mut rng = rng();
+        for n in 4..=6 {
+            let q = make_question(&mut rng, n);
+            assert_eq!(q.options.len(), n);
+            let cat = CATEGORIES.iter().find(|c| c.label == q.label).unwrap();
+            let hits = q.options.iter().filter(|o| cat.members.contains(o)).count();
+            assert_eq!(hits, 1);
+            assert!(cat.members.contains(&q.options[q.correct]));
+        }
+    }
+
+    #[test]
+    fn categories_are_translated() {
+        for lang in Lang::ALL {
+            for c in CATEGORIES {
+                assert_ne!(tr!(lang, c.label), c.label);
+                for m in c.members {
+                    assert_ne!(tr!(lang, m), *m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_pick_uses_an_attempt() {
+        let mut pend = Pending {
+            user: 42,
+            captcha_msg_id: 1,
+            deadline: Utc::now(),
+            user_message_ids: vec![5],
+            captcha_mode: CaptchaMode::Choice,
+            expected_answer: Some("2".into()),
+            attempts: 0,
+            nonce: "a1b2c3d4".into(),
+            via_dm: false,
+            token: String::new(),
+        };
+        assert!(picked_right(Some("choice:42:a1b2c3d4:2"), &pend));
+        assert!(!picked_right(Some("choice:42:a1b2c3d4:0"), &pend));
+
+        count_miss(&mut pend, None);
+        assert_eq!(pend.attempts, 1);
+        assert_eq!(pend.user_message_ids, vec![5]);
+        assert!(attempts_exhausted(pend.attempts, 1));
+        assert!(!attempts_exhausted(pend.attempts, 0));
+    }
+
+    #[test]
+    fn parse_choice_payload() {
+        assert_eq!(parse_choice("choice:42:a1b2c3d4:3"), Some(3));
+        assert_eq!(parse_choice("choice:42:3"), Some(3));
+        assert_eq!(parse_choice("choice:42:x"), None);
+        assert_eq!(parse_choice("ok:42"), None);
+    }
+}
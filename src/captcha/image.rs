@@ -0,0 +1,271 @@
+// image.rs
+//! Картинка-капча: случайные цифры рисуются в PNG прямо в процессе
+//! (встроенный битмап-шрифт + искажения), без внешних сервисов и файлов шрифтов.
+
+use super::*;
+use rand::{rng, Rng};
+use std::f32::consts::TAU;
+use teloxide::types::{InputFile, ParseMode};
+
+/// Telegram: подпись к фото — до 1024 символов.
+const CAPTION_MAX: usize = 1024;
+
+/// Сколько цифр в коде.
+const CODE_LEN: usize = 5;
+
+/// Размер картинки.
+const WIDTH: usize = 220;
+const HEIGHT: usize = 80;
+
+/// Масштаб одного «пикселя» шрифта 5x7.
+const SCALE: usize = 6;
+
+/// Битмап-шрифт 5x7 для цифр: 7 строк, младшие 5 бит — столбцы (старший бит слева).
+const DIGITS: [[u8; 7]; 10] = [
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+];
+
+pub struct ImageCaptcha;
+
+#[async_trait]
+impl Captcha for ImageCaptcha {
+    fn mode(&self) -> CaptchaMode {
+        CaptchaMode::Image
+    }
+
+    async fn ask(
+        &self,
+        bot: &Bot,
+        state: &AppState,
+        chat_id: ChatId,
+        to: ChatId,
+        user: &User,
+    ) -> Result<Challenge> {
+        let (code, png) = {
+            let mut rng = rng();
+            let code: String = (0..CODE_LEN)
+                .map(|_| char::from(b'0' + rng.random_range(0..10u8)))
+                .collect();
+            let png = render_png(&code, &mut rng)?;
+            (code, png)
+        };
+
+        let settings = state.settings(chat_id);
+        let lang = settings.prompt_lang(user);
+        let task = tr!(
+            lang,
+            "captcha.task_image",
+            secs = settings.captcha_timeout_secs
+        );
+        let mut text = prompt_text(bot, state, chat_id, user, &task).await;
+        if text.chars().count() > CAPTION_MAX {
+            // Свой шаблон не влез в подпись — стандартный короткий.
+            let m = mention(bot, chat_id, user.id).await;
+            text = templates::render(
+                tr!(lang, "captcha.prompt"),
+                &[("mention", &m), ("task", &task)],
+            );
+        }
+
+        let req = bot
+            .send_photo(to, InputFile::memory(png).file_name("captcha.png"))
+            .caption(text)
+            .parse_mode(ParseMode::Html);
+        let msg = api::call(state, to, "send_photo", req).await?;
+
+        Ok(Challenge {
+            message: msg,
+            expected_answer: Some(code),
+            nonce: String::new(),
+        })
+    }
+
+    async fn on_text(
+        &self,
+        _bot: &Bot,
+        _state: Arc<AppState>,
+        msg: &Message,
+        pend: &Pending,
+    ) -> Result<bool> {
+        if let (Some(ans), Some(txt)) = (pend.expected_answer.as_ref(), msg.text()) {
+            // Цифры с картинки часто вводят через пробелы — склеиваем.
+            let compact: String = txt.split_whitespace().collect();
+            Ok(math2::is_numeric_equal(&compact, ans))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Рисует код в PNG (RGB, 8 бит): фон с шумом, «пляшущие» цифры,
+/// волновое искажение и перечёркивающие линии.
+fn render_png(code: &str, rng: &mut impl Rng) -> Result<Vec<u8>> {
+    // 1) маска текста (true = чернила)
+    let mut ink = vec![false; WIDTH * HEIGHT];
+    let glyph_w = 5 * SCALE;
+    let glyph_h = 7 * SCALE;
+    let step = (WIDTH - 20) / CODE_LEN.max(1);
+    for (i, ch) in code.chars().enumerate() {
+        let Some(d) = ch.to_digit(10) else {
+            continue;
+        };
+        let x0 = 10 + i * step + rng.random_range(0..=step.saturating_sub(glyph_w).max(1));
+        let y0 = rng.random_range(4..=HEIGHT - glyph_h - 4);
+        // наклон глифа (сдвиг строк по x)
+        let shear: f32 = rng.random_range(-0.35..0.35);
+        draw_glyph(&mut ink, &DIGITS[d as usize], x0, y0, shear);
+    }
+
+    // 2) волновое искажение
+    let (ax, ay) = (rng.random_range(2.0..4.0f32), rng.random_range(2.0..4.0f32));
+    let (px, py) = (rng.random_range(0.0..TAU), rng.random_range(0.0..TAU));
+    let mut warped = vec![false; WIDTH * HEIGHT];
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let sx = x as f32 + ax * ((y as f32) / 9.0 + px).sin();
+            let sy = y as f32 + ay * ((x as f32) / 13.0 + py).sin();
+            if sx < 0.0 || sy < 0.0 {
+                continue;
+            }
+            let (sx, sy) = (sx as usize, sy as usize);
+            if sx < WIDTH && sy < HEIGHT {
+                warped[y * WIDTH + x] = ink[sy * WIDTH + sx];
+            }
+        }
+    }
+
+    // 3) раскраска: светлый шумный фон + тёмные цифры
+    let ink_rgb = [
+        rng.random_range(0..90u8),
+        rng.random_range(0..90u8),
+        rng.random_range(40..140u8),
+    ];
+    let mut rgb = vec![0u8; WIDTH * HEIGHT * 3];
+    for (i, px) in rgb.chunks_exact_mut(3).enumerate() {
+        if warped[i] {
+            px.copy_from_slice(&ink_rgb);
+        } else {
+            let base = rng.random_range(200..=255u8);
+            px.copy_from_slice(&[base, base.saturating_sub(10), rng.random_range(200..=255u8)]);
+        }
+    }
+
+    // 4) шумовые линии и точки поверх
+    for _ in 0..4 {
+        let (x0, y0) = (
+            rng.random_range(0..WIDTH) as i32,
+            rng.random_range(0..HEIGHT) as i32,
+        );
+        let (x1, y1) = (
+            rng.random_range(0..WIDTH) as i32,
+            rng.random_range(0..HEIGHT) as i32,
+        );
+        draw_line(&mut rgb, (x0, y0), (x1, y1), ink_rgb);
+    }
+    for _ in 0..WIDTH * HEIGHT / 40 {
+        let i = rng.random_range(0..WIDTH * HEIGHT) * 3;
+        let v = rng.random_range(0..160u8);
+        rgb[i..i + 3].copy_from_slice(&[v, v, v]);
+    }
+
+    encode_png(&rgb)
+}
+
+fn draw_glyph(ink: &mut [bool], glyph: &[u8; 7], x0: usize, y0: usize, shear: f32) {
+    for (row, bits) in glyph.iter().enumerate() {
+        for col in 0..5 {
+            if bits & (0x10 >> col) == 0 {
+                continue;
+            }
+            for dy in 0..SCALE {
+                let y = y0 + row * SCALE + dy;
+                let dx_shear = (shear * (3.5 * SCALE as f32 - (row * SCALE + dy) as f32)) as i32;
+                for dx in 0..SCALE {
+                    let x = (x0 + col * SCALE + dx) as i32 + dx_shear;
+                    if x >= 0 && (x as usize) < WIDTH && y < HEIGHT {
+                        ink[y * WIDTH + x as usize] = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Брезенхэм, толщина 2px.
+fn draw_line(rgb: &mut [u8], (mut x0, mut y0): (i32, i32), (x1, y1): (i32, i32), color: [u8; 3]) {
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        for (px, py) in [(x0, y0), (x0, y0 + 1)] {
+            if px >= 0 && py >= 0 && (px as usize) < WIDTH && (py as usize) < HEIGHT {
+                let i = (py as usize * WIDTH + px as usize) * 3;
+                rgb[i..i + 3].copy_from_slice(&color);
+            }
+        }
+        if x0 == x1 && y0 == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x0 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y0 += sy;
+        }
+    }
+}
+
+fn encode_png(rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    {
+        let mut enc = png::Encoder::new(&mut out, WIDTH as u32, HEIGHT as u32);
+        enc.set_color(png::ColorType::Rgb);
+        enc.set_depth(png::BitDepth::Eight);
+        let mut writer = enc.write_header()?;
+        writer.write_image_data(rgb)?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_mode() {
+        assert_eq!(ImageCaptcha.mode(), CaptchaMode::Image);
+    }
+
+    #[test]
+    fn render_produces_png() {
+        let png = render_png("01234", &mut rng()).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width as usize, WIDTH);
+        assert_eq!(reader.info().height as usize, HEIGHT);
+    }
+
+    #[test]
+    fn every_digit_has_glyph() {
+        for g in DIGITS {
+            assert!(g.iter().any(|row| *row != 0));
+            assert!(g.iter().all(|row| *row < 0x20));
+        }
+    }
+}
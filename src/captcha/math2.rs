@@ -16,6 +16,7 @@ impl Captcha for Math2Captcha {
         bot: &Bot,
         state: &AppState,
         chat_id: ChatId,
+        to: ChatId,
         user: &User,
     ) -> Result<Challenge> {
         let (a, b) = {
@@ -24,33 +25,33 @@ impl Captcha for Math2Captcha {
         };
         let expected = (a as u16 + b as u16).to_string();
 
-        let m = mention(bot, chat_id, user.id).await;
+        let settings = state.settings(chat_id);
+        let task = tr!(
+            settings.prompt_lang(user),
+            "captcha.task_math2",
+            a = a,
+            b = b,
+            secs = settings.captcha_timeout_secs,
+        );
+        let text = prompt_text(bot, state, chat_id, user, &task).await;
 
-        let msg = bot
-            .send_message(
-                chat_id,
-                format!("{m}, докажите, что вы человек: сколько будет {a} + {b}? Напишите только число за {} секунд.",
-                        state.cfg.captcha_timeout_secs),
-            )
-            .parse_mode(ParseMode::Html)
-            .await?;
+        let req = bot.send_message(to, text).parse_mode(ParseMode::Html);
+        let msg = api::call(state, to, "send_message", req).await?;
 
         Ok(Challenge {
             message: msg,
             expected_answer: Some(expected),
+            nonce: String::new(),
         })
     }
 
-    async fn on_text(&self, _bot: &Bot, state: Arc<AppState>, msg: &Message) -> Result<bool> {
-        let Some(from) = msg.from() else {
-            return Ok(false);
-        };
-        let chat_id = msg.chat.id;
-        let key = AppState::key(chat_id, from.id);
-        let Some((_k, pend)) = state.pending.get(&key).map(|r| (key, r.clone())) else {
-            return Ok(false);
-        };
-
+    async fn on_text(
+        &self,
+        _bot: &Bot,
+        _state: Arc<AppState>,
+        msg: &Message,
+        pend: &Pending,
+    ) -> Result<bool> {
         if let (Some(ans), Some(txt)) = (pend.expected_answer.as_ref(), msg.text()) {
             Ok(is_numeric_equal(txt, ans))
         } else {
@@ -60,7 +61,7 @@ impl Captcha for Math2Captcha {
 }
 
 /// Чистая функция для юнит-тестов и переиспользования.
-fn is_numeric_equal(candidate: &str, expected: &str) -> bool {
+pub(super) fn is_numeric_equal(candidate: &str, expected: &str) -> bool {
     let c = candidate.trim();
     if !c.chars().all(|ch| ch.is_ascii_digit()) {
         return false;
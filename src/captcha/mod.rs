@@ -1,13 +1,21 @@
 //! Капчи-стратегии + общий роутинг. Публичный API сохранён:
 //! - ask_captcha(bot, state, chat_id, user)
 //! - on_callback(bot, state, q)
+//!
 //! Добавлено:
 //! - on_user_message(bot, state, &msg) — нужно дергать из message-хэндлера,
-//!   чтобы math2 могла принять ответ текстом.
+//!   чтобы math2/image могли принять ответ текстом.
+//! - on_join_request(bot, state, &req) — чаты с одобрением заявок: капча
+//!   уходит в личку заявителю, по итогу заявку одобряем или отклоняем.
+//!   Ожидание хранится под ключом группы (`Pending::via_dm`).
+//! - on_verify_start(bot, state, &msg, token) — капча в личке по deep link:
+//!   в группе только кнопка `t.me/<bot>?start=verify_<token>`, задача
+//!   (любого режима) выдаётся после `/start` и решается в личке.
 
 mod button;
+mod choice;
+mod image;
 mod math2;
-// mod image;
 
 fn fmt_until_date(u: &teloxide::types::UntilDate) -> String {
     use teloxide::types::UntilDate as UD;
@@ -18,37 +26,117 @@ fn fmt_until_date(u: &teloxide::types::UntilDate) -> String {
     }
 }
 
-use crate::config::CaptchaMode;
+use crate::api;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::config::{CaptchaMode, FailureAction};
+use crate::i18n::{tr, Lang};
+use crate::probation;
+use crate::scheduler::{delete_later, probation_key, Job};
 use crate::state::{AppState, Pending};
+use crate::templates;
 use crate::utils::mention;
 use anyhow::Result;
 use async_trait::async_trait;
-use chrono::{Duration as ChronoDuration, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 use log::{debug, error, warn};
 // <— info убран
 use std::collections::HashSet;
 use std::sync::Arc;
 use teloxide::prelude::*;
-use teloxide::types::{CallbackQuery, ChatPermissions, Message, MessageId, ParseMode, User};
+use teloxide::types::{
+    CallbackQuery, ChatJoinRequest, ChatPermissions, InlineKeyboardButton, InlineKeyboardMarkup,
+    Message, MessageId, ParseMode, User,
+};
+
+/// Аргумент `/start` из ссылки на капчу в личке: `verify_<token>`.
+pub const VERIFY_PREFIX: &str = "verify_";
 
 pub use button::ButtonCaptcha;
+pub use choice::ChoiceCaptcha;
+pub use image::ImageCaptcha;
 pub use math2::Math2Captcha;
 
 #[derive(Debug)]
 pub struct Challenge {
     pub message: Message,
     pub expected_answer: Option<String>,
+    /// Метка из callback-данных кнопок (`new_nonce`); у текстовых — пусто.
+    pub nonce: String,
+}
+
+/// Текст промпта: шаблон чата (или стандартный) с задачей режима `task`.
+async fn prompt_text(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user: &User,
+    task: &str,
+) -> String {
+    let settings = state.settings(chat_id);
+    let lang = settings.prompt_lang(user);
+    let template = settings
+        .captcha_template
+        .clone()
+        .unwrap_or_else(|| tr!(lang, "captcha.prompt").to_string());
+    let m = mention(bot, chat_id, user.id).await;
+    templates::fill(bot, state, chat_id, lang, &template, &m, task).await
+}
+
+/// Случайная метка задачи: кнопки старой/чужой капчи не подойдут к текущей.
+pub(crate) fn new_nonce() -> String {
+    use rand::distr::{Alphanumeric, SampleString};
+    Alphanumeric.sample_string(&mut rand::rng(), 8)
+}
+
+/// Метка ссылки на капчу в личке (deep link допускает `[A-Za-z0-9_-]`, до 64).
+fn new_token() -> String {
+    use rand::distr::{Alphanumeric, SampleString};
+    Alphanumeric.sample_string(&mut rand::rng(), 16)
+}
+
+fn verify_link(bot_name: &str, token: &str) -> String {
+    format!("https://t.me/{bot_name}?start={VERIFY_PREFIX}{token}")
+}
+
+/// @username бота (для ссылок); спрашиваем Telegram один раз.
+async fn bot_username(bot: &Bot, state: &AppState) -> Option<String> {
+    if let Some(name) = state.bot_username.get() {
+        return Some(name.clone());
+    }
+    match bot.get_me().await {
+        Ok(me) => {
+            let name = me.user.username?;
+            Some(state.bot_username.get_or_init(|| name).clone())
+        }
+        Err(e) => {
+            state.metrics.api_error("get_me");
+            warn!("get_me failed, captcha stays in the chat: {e}");
+            None
+        }
+    }
+}
+
+/// `{prefix}{user_id}:{nonce}[:...]` -> (user_id, nonce).
+/// Старый формат без метки (`ok:{user_id}`) даёт пустую метку.
+fn parse_target(data: &str) -> Option<(u64, &str)> {
+    let (_prefix, rest) = data.split_once(':')?;
+    let mut it = rest.splitn(3, ':');
+    let user = it.next()?.parse().ok()?;
+    Some((user, it.next().unwrap_or("")))
 }
 
 #[async_trait]
 pub trait Captcha: Send + Sync {
     fn mode(&self) -> CaptchaMode;
 
+    /// Выдать задачу: настройки и шаблоны — чата `chat_id`, отправка — в `to`
+    /// (тот же чат или личка заявителя).
     async fn ask(
         &self,
         bot: &Bot,
         state: &AppState,
         chat_id: ChatId,
+        to: ChatId,
         user: &User,
     ) -> Result<Challenge>;
 
@@ -57,11 +145,18 @@ pub trait Captcha: Send + Sync {
         _bot: &Bot,
         _state: Arc<AppState>,
         _q: &CallbackQuery,
+        _pend: &Pending,
     ) -> Result<bool> {
         Ok(false)
     }
 
-    async fn on_text(&self, _bot: &Bot, _state: Arc<AppState>, _msg: &Message) -> Result<bool> {
+    async fn on_text(
+        &self,
+        _bot: &Bot,
+        _state: Arc<AppState>,
+        _msg: &Message,
+        _pend: &Pending,
+    ) -> Result<bool> {
         Ok(false)
     }
 }
@@ -71,10 +166,8 @@ fn provider(mode: CaptchaMode) -> Option<Box<dyn Captcha>> {
         CaptchaMode::Off => None,
         CaptchaMode::Button => Some(Box::new(ButtonCaptcha)),
         CaptchaMode::Math2 => Some(Box::new(Math2Captcha)),
-        CaptchaMode::Image => {
-            // TODO: ImageCaptcha
-            Some(Box::new(ButtonCaptcha))
-        }
+        CaptchaMode::Image => Some(Box::new(ImageCaptcha)),
+        CaptchaMode::Choice => Some(Box::new(ChoiceCaptcha)),
     }
 }
 
@@ -85,230 +178,747 @@ pub async fn ask_captcha(
     chat_id: ChatId,
     user: &User,
 ) -> Result<()> {
-    // Полностью запретить сообщения на время проверки (кроме math2 — ей нужен текст)
-    let until = Utc::now() + ChronoDuration::seconds(state.cfg.captcha_timeout_secs as i64);
-    if !matches!(state.cfg.captcha_mode, CaptchaMode::Math2) {
+    // Вошёл по заявке, одобренной после капчи в личке, — уже проверен.
+    if state.is_join_approved(&AppState::key(chat_id, user.id)) {
+        debug!(
+            "Skip captcha: join request approved (chat={}, user={})",
+            chat_id.0, user.id.0
+        );
+        return Ok(());
+    }
+
+    // Темп вступлений: волна — блокировка (влияет на настройки ниже)
+    crate::raid::on_join(bot, &state, chat_id, user.id).await;
+
+    // Эффективные настройки этого чата
+    let settings = state.settings(chat_id);
+
+    // Полностью запретить сообщения на время проверки (кроме math2/image — им
+    // нужен текст; но не когда капча в личке)
+    let until = Utc::now() + ChronoDuration::seconds(settings.captcha_timeout_secs as i64);
+    if settings.private_captcha || !settings.captcha_mode.typed_answer() {
         let no_send = ChatPermissions::empty();
-        let _ = bot
-            .restrict_chat_member(chat_id, user.id, no_send)
-            .until_date(until)
-            .await;
+        api::moderate(
+            &state,
+            chat_id,
+            user.id.0.to_string(),
+            "restrict_chat_member",
+            bot.restrict_chat_member(chat_id, user.id, no_send)
+                .until_date(until),
+        )
+        .await
+        .ok();
     }
 
     // 1) Боты: если не в whitelist — баним; если в whitelist — пропускаем.
     if user.is_bot {
-        if !state.is_bot_allowed_user(user) {
+        if !state.is_bot_allowed_user(chat_id, user) {
             warn!(
                 "BANNING bot not in whitelist: {} in chat {}",
                 user.id.0, chat_id.0
             );
-            let _ = bot.ban_chat_member(chat_id, user.id).await;
+            api::moderate(
+                &state,
+                chat_id,
+                user.id.0.to_string(),
+                "ban_chat_member",
+                bot.ban_chat_member(chat_id, user.id),
+            )
+            .await
+            .ok();
+            state.metrics.bot_banned();
+            state.record(AuditEntry::new(
+                chat_id,
+                None,
+                user.id.0.to_string(),
+                AuditAction::BotBanned,
+                "bot not in whitelist",
+            ));
         }
         return Ok(());
     }
 
     // 2) Люди из whitelist — пропускаем без капчи (и снимаем ограничения).
-    if state.is_user_allowed(user) || matches!(state.cfg.captcha_mode, CaptchaMode::Off) {
+    if state.is_user_allowed(chat_id, user) || matches!(settings.captcha_mode, CaptchaMode::Off) {
         debug!(
             "Skip captcha: whitelisted or captcha off (user={})",
             user.id.0
         );
-        allow_user(bot, chat_id, user.id).await?;
+        allow_user(bot, &state, chat_id, user.id).await?;
         return Ok(());
     }
 
-    // 3) По режиму .env
-    let Some(strategy) = provider(state.cfg.captcha_mode) else {
-        allow_user(bot, chat_id, user.id).await?;
+    // 3) По режиму чата (или .env по умолчанию)
+    let Some(strategy) = provider(settings.captcha_mode) else {
+        allow_user(bot, &state, chat_id, user.id).await?;
+        return Ok(());
+    };
+
+    issue_challenge(bot, &state, chat_id, chat_id, user, strategy.as_ref()).await
+}
+
+/// Заявка на вступление: капча заявителю в личку, решение — по итогу.
+/// В чат он попадёт только после проверки.
+pub async fn on_join_request(bot: &Bot, state: Arc<AppState>, req: &ChatJoinRequest) -> Result<()> {
+    let chat_id = req.chat.id;
+    let user = &req.from;
+    let settings = state.settings(chat_id);
+
+    // Выключено в чате — заявки разбирают админы.
+    if !settings.join_requests {
+        return Ok(());
+    }
+    let Some(strategy) = provider(settings.captcha_mode) else {
         return Ok(());
     };
+    if state.is_user_allowed(chat_id, user) {
+        debug!(
+            "Approve join request without captcha: whitelisted (chat={}, user={})",
+            chat_id.0, user.id.0
+        );
+        approve_join(bot, &state, chat_id, user.id).await;
+        return Ok(());
+    }
+
+    issue_challenge(
+        bot,
+        &state,
+        chat_id,
+        req.user_chat_id,
+        user,
+        strategy.as_ref(),
+    )
+    .await
+}
 
+/// Показать капчу в `to` и поставить ожидание с таймером (ключ — `chat_id`).
+async fn issue_challenge(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    to: ChatId,
+    user: &User,
+    strategy: &dyn Captcha,
+) -> Result<()> {
     // Дедуп (первичная проверка)
     let key = AppState::key(chat_id, user.id);
     if state.pending.contains_key(&key) {
         return Ok(());
     }
 
-    // Показать капчу
-    let challenge = strategy.ask(bot, &state, chat_id, user).await?;
+    // Показать капчу; при капче в личке — кнопку-ссылку на неё
+    let link = if to == chat_id && state.settings(chat_id).private_captcha {
+        bot_username(bot, state)
+            .await
+            .map(|name| (name, new_token()))
+    } else {
+        None
+    };
+    let shown = match &link {
+        Some((name, token)) => link_challenge(bot, state, chat_id, user, name, token).await,
+        None => strategy.ask(bot, state, chat_id, to, user).await,
+    };
+    let challenge = match shown {
+        Ok(c) => c,
+        Err(e) => {
+            state.metrics.api_error("send_captcha");
+            return Err(e);
+        }
+    };
 
     // Считаем таймер один раз
-    let timeout = state.timeout();
-    let deadline = tokio::time::Instant::now() + timeout;
+    let deadline = Utc::now() + state.timeout(chat_id);
 
     // Сохранить Pending (защита от гонки)
-    let inserted = state.pending.insert(
+    let inserted = state.insert_pending(
         key,
         Pending {
-            _user: user.id.0,
+            user: user.id.0,
             captcha_msg_id: challenge.message.id.0,
-            _deadline: std::time::Instant::now() + timeout,
+            deadline,
             user_message_ids: Vec::new(),
             captcha_mode: strategy.mode(),
             expected_answer: challenge.expected_answer,
+            attempts: 0,
+            nonce: challenge.nonce,
+            via_dm: to != chat_id,
+            token: link.map(|(_, token)| token).unwrap_or_default(),
         },
     );
 
     if inserted.is_some() {
-        let _ = bot
-            .delete_message(chat_id, MessageId(challenge.message.id.0))
-            .await;
+        let dup = bot.delete_message(to, challenge.message.id);
+        let _ = api::call(state, to, "delete_message", dup).await;
         return Ok(());
     }
 
+    state.metrics.captcha_issued(strategy.mode().as_str());
+
     // Таймаут по дедлайну
-    schedule_timeout_cleanup(bot.clone(), state.clone(), chat_id, user.id, deadline);
+    schedule_timeout(state, chat_id, user.id, deadline);
 
     Ok(())
 }
 
+/// Сообщение в чате вместо задачи: кнопка-ссылка в личку с ботом.
+async fn link_challenge(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user: &User,
+    bot_name: &str,
+    token: &str,
+) -> Result<Challenge> {
+    let settings = state.settings(chat_id);
+    let lang = settings.prompt_lang(user);
+    let task = tr!(
+        lang,
+        "captcha.task_private",
+        secs = settings.captcha_timeout_secs
+    );
+    let text = prompt_text(bot, state, chat_id, user, &task).await;
+    let url = url::Url::parse(&verify_link(bot_name, token))?;
+
+    let req = bot
+        .send_message(chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+            tr!(lang, "captcha.private_button"),
+            url,
+        )]]));
+    let msg = api::call(state, chat_id, "send_message", req).await?;
+
+    Ok(Challenge {
+        message: msg,
+        expected_answer: None,
+        nonce: String::new(),
+    })
+}
+
+/// `/start verify_<token>` в личке: выдать задачу по ссылке из чата.
+/// Повторный `/start` заменяет задачу новой (срок прежний).
+pub async fn on_verify_start(
+    bot: &Bot,
+    state: Arc<AppState>,
+    msg: &Message,
+    lang: Lang,
+    token: &str,
+) -> Result<()> {
+    let Some(from) = msg.from.as_ref() else {
+        return Ok(());
+    };
+    let found = state
+        .find_by_token(token)
+        .and_then(|key| state.pending.get(&key).map(|p| (key, p.clone())));
+    let Some((key, pend)) = found else {
+        bot.send_message(msg.chat.id, tr!(lang, "captcha.link_expired"))
+            .await?;
+        return Ok(());
+    };
+    if pend.user != from.id.0 {
+        bot.send_message(msg.chat.id, tr!(lang, "captcha.not_for_you"))
+            .await?;
+        return Ok(());
+    }
+    let Some(strategy) = provider(pend.captcha_mode) else {
+        return Ok(());
+    };
+
+    let chat_id = key.0;
+    let challenge = match strategy.ask(bot, &state, chat_id, msg.chat.id, from).await {
+        Ok(c) => c,
+        Err(e) => {
+            state.metrics.api_error("send_captcha");
+            return Err(e);
+        }
+    };
+    let new_id = challenge.message.id;
+    let replaced = state.update_pending(&key, |p| {
+        p.captcha_msg_id = new_id.0;
+        p.expected_answer = challenge.expected_answer;
+        p.nonce = challenge.nonce;
+        p.via_dm = true;
+    });
+    debug!(
+        "Private captcha issued via deep link (chat={}, user={})",
+        chat_id.0, from.id.0
+    );
+    // Прежнее — кнопка в чате или задача прошлого /start; если капча
+    // успела завершиться — убираем новую.
+    let (stale_chat, stale) = if replaced.is_some() {
+        (captcha_chat(chat_id, &pend), MessageId(pend.captcha_msg_id))
+    } else {
+        (msg.chat.id, new_id)
+    };
+    let delete = bot.delete_message(stale_chat, stale);
+    let _ = api::call(&state, stale_chat, "delete_message", delete).await;
+    Ok(())
+}
+
 /// PUBLIC API (совместим с прежним): обработчик callback'ов.
 pub async fn on_callback(bot: Bot, state: Arc<AppState>, q: CallbackQuery) -> Result<()> {
+    let _t = state.metrics.time("callback");
     let qid = q.id.clone();
-    bot.answer_callback_query(qid).await.ok();
 
+    // Кнопка адресована конкретному участнику — остальным только подсказка.
+    let target = q.data.as_deref().and_then(parse_target);
+    if let Some((user, _)) = target {
+        if user != q.from.id.0 {
+            let lang = match &q.message {
+                Some(m) => state.settings(m.chat().id).prompt_lang(&q.from),
+                None => state.cfg.language,
+            };
+            bot.answer_callback_query(qid)
+                .text(tr!(lang, "captcha.not_for_you"))
+                .show_alert(true)
+                .await
+                .ok();
+            return Ok(());
+        }
+    }
+
+    // Ответ на нажатие — после проверки: неверный вариант — подсказкой.
+    let res = on_pick(&bot, &state, &q, target).await;
+    let answer = bot.answer_callback_query(qid);
+    let answer = match (&res, &q.message) {
+        (Ok(true), Some(m)) => {
+            let lang = state.settings(m.chat().id).prompt_lang(&q.from);
+            answer.text(tr!(lang, "captcha.wrong_pick"))
+        }
+        _ => answer,
+    };
+    answer.await.ok();
+    res.map(|_| ())
+}
+
+/// Нажатие кнопки капчи. `true` — выбран неверный вариант.
+async fn on_pick(
+    bot: &Bot,
+    state: &Arc<AppState>,
+    q: &CallbackQuery,
+    target: Option<(u64, &str)>,
+) -> Result<bool> {
     let Some(msg) = &q.message else {
-        return Ok(());
+        return Ok(false);
     };
-    let chat_id = msg.chat().id;
 
     let from = &q.from;
     if from.is_bot {
-        return Ok(());
+        return Ok(false);
     }
 
-    let key = AppState::key(chat_id, from.id);
-    let Some((_k, pend)) = state.pending.get(&key).map(|r| (key, r.clone())) else {
-        return Ok(());
+    // Капча по заявке — в личке, а ожидание лежит под ключом группы.
+    let key = if msg.chat().is_private() {
+        match state.find_dm_pending(from.id, target.map(|(_, nonce)| nonce)) {
+            Some(key) => key,
+            None => return Ok(false),
+        }
+    } else {
+        AppState::key(msg.chat().id, from.id)
+    };
+    let chat_id = key.0;
+    let Some(pend) = state.pending.get(&key).map(|r| r.clone()) else {
+        return Ok(false);
     };
+    // Метка должна совпасть с текущей задачей (не старая капча того же участника).
+    if target.is_none_or(|(_, nonce)| nonce != pend.nonce) {
+        debug!(
+            "Stale or foreign captcha callback ignored (chat={}, user={})",
+            chat_id.0, from.id.0
+        );
+        return Ok(false);
+    }
 
-    if let Some(strategy) = provider(pend.captcha_mode) {
-        let ok = strategy.on_callback(&bot, state.clone(), &q).await?;
-        if ok {
-            // удалить pending и финализировать
-            let (_k, pend) = state.pending.remove(&key).expect("pending exists");
-            complete_and_greet(&bot, state, chat_id, from, pend).await?;
-        }
+    let Some(strategy) = provider(pend.captcha_mode) else {
+        return Ok(false);
+    };
+    if strategy.on_callback(bot, state.clone(), q, &pend).await? {
+        // удалить pending и финализировать (таймер мог успеть раньше)
+        let Some(pend) = state.remove_pending(&key) else {
+            return Ok(false);
+        };
+        complete_and_greet(bot, state.clone(), chat_id, from, pend).await?;
+        return Ok(false);
     }
-    Ok(())
+    // Неверный вариант (у кнопки «я человек» вариантов нет): попытка, как за
+    // неверный текстовый ответ, — иначе варианты можно перебрать по очереди.
+    if pend.captcha_mode != CaptchaMode::Choice {
+        return Ok(false);
+    }
+    on_wrong_answer(bot, state, chat_id, None, from, strategy.as_ref()).await;
+    Ok(true)
 }
 
 /// Обработка текстов пользователя для стратегий типа math2.
 /// Вызвать в message-хэндлере ДО основной логики.
 pub async fn on_user_message(bot: Bot, state: Arc<AppState>, msg: &Message) -> Result<()> {
-    let Some(from) = msg.from() else {
+    let Some(from) = msg.from.as_ref() else {
         return Ok(());
     };
     if from.is_bot {
         return Ok(());
     }
-    let chat_id = msg.chat.id;
-
-    let key = AppState::key(chat_id, from.id);
-    let Some((_k, pend)) = state.pending.get(&key).map(|r| (key, r.clone())) else {
+    // В личке команды (`/start verify_...`) — не ответы на капчу.
+    if msg.chat.is_private() && msg.text().is_some_and(|t| t.starts_with('/')) {
+        return Ok(());
+    }
+    let key = if msg.chat.is_private() {
+        match state.find_dm_pending(from.id, None) {
+            Some(key) => key,
+            None => return Ok(()),
+        }
+    } else {
+        AppState::key(msg.chat.id, from.id)
+    };
+    let chat_id = key.0;
+    let Some(pend) = state.pending.get(&key).map(|r| r.clone()) else {
         return Ok(());
     };
 
     if let Some(strategy) = provider(pend.captcha_mode) {
-        let ok = strategy.on_text(&bot, state.clone(), msg).await?;
+        let ok = strategy.on_text(&bot, state.clone(), msg, &pend).await?;
         if ok {
-            let (_k, pend) = state
-                .pending
-                .remove(&AppState::key(chat_id, from.id))
-                .expect("pending exists");
+            let Some(pend) = state.remove_pending(&key) else {
+                return Ok(());
+            };
             complete_and_greet(&bot, state, chat_id, from, pend).await?;
+        } else if msg.text().is_some() && pend.captcha_mode.typed_answer() {
+            on_wrong_answer(&bot, &state, chat_id, Some(msg), from, strategy.as_ref()).await;
         }
     }
     Ok(())
 }
 
-// --- общие утилиты для всех стратегий ---
-
-fn schedule_timeout_cleanup(
-    bot: Bot,
-    state: Arc<AppState>,
+/// Неверный ответ (текстом или не той кнопкой): текст удалить, засчитать
+/// попытку; при исчерпании — провал сразу, иначе (если включено) выдать новую
+/// задачу. `chat_id` — чат капчи; ответ мог прийти и в личку (заявка).
+async fn on_wrong_answer(
+    bot: &Bot,
+    state: &Arc<AppState>,
     chat_id: ChatId,
-    user_id: UserId,
-    deadline: tokio::time::Instant,
+    msg: Option<&Message>,
+    from: &User,
+    strategy: &dyn Captcha,
 ) {
-    // тех.лог — только debug
-    let until_in = deadline
-        .checked_duration_since(tokio::time::Instant::now())
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
+    let key = AppState::key(chat_id, from.id);
+
+    if let Some(msg) = msg {
+        let deleted = bot.delete_message(msg.chat.id, msg.id);
+        if api::call(state, msg.chat.id, "delete_message", deleted)
+            .await
+            .is_ok()
+        {
+            state.metrics.messages_deleted(1);
+        }
+    }
+    let Some(pend) = state.update_pending(&key, |p| count_miss(p, msg.map(|m| m.id))) else {
+        return;
+    };
+
+    let settings = state.settings(chat_id);
+    debug!(
+        "Wrong captcha answer {}/{} (chat={}, user={})",
+        pend.attempts, settings.max_attempts, chat_id.0, from.id.0
+    );
+
+    if attempts_exhausted(pend.attempts, settings.max_attempts) {
+        // таймер снимается вместе с ожиданием
+        let Some(pend) = state.remove_pending(&key) else {
+            return;
+        };
+        fail_captcha(bot, state, chat_id, from.id, pend, FailReason::Attempts).await;
+        return;
+    }
+
+    if !settings.new_challenge_on_miss {
+        return;
+    }
+    let to = captcha_chat(chat_id, &pend);
+    let challenge = match strategy.ask(bot, state, chat_id, to, from).await {
+        Ok(c) => c,
+        Err(e) => {
+            state.metrics.api_error("send_captcha");
+            warn!(
+                "Failed to send fresh captcha (chat={}, user={}): {}",
+                chat_id.0, from.id.0, e
+            );
+            return;
+        }
+    };
+    let new_id = challenge.message.id;
+    let replaced = state.update_pending(&key, |p| {
+        p.captcha_msg_id = new_id.0;
+        p.expected_answer = challenge.expected_answer;
+        p.nonce = challenge.nonce;
+    });
+    // Старую задачу убираем; если капча успела завершиться — и новую.
+    let stale = if replaced.is_some() {
+        MessageId(pend.captcha_msg_id)
+    } else {
+        new_id
+    };
+    let _ = api::call(state, to, "delete_message", bot.delete_message(to, stale)).await;
+}
+
+/// Засчитать неверный ответ; сам ответ-сообщение уже удалён — не чистить его потом.
+fn count_miss(p: &mut Pending, answer: Option<MessageId>) {
+    p.attempts += 1;
+    if let Some(id) = answer {
+        p.user_message_ids.retain(|m| *m != id.0);
+    }
+}
+
+/// `max = 0` — попытки не ограничены.
+fn attempts_exhausted(attempts: u32, max: u32) -> bool {
+    max > 0 && attempts >= max
+}
+
+/// После рестарта: таймеры капч из state-файлов старых версий (без
+/// очереди задач). Уже истёкшие сработают сразу; существующие — не задвоятся.
+pub fn resume_pending(state: &AppState) {
+    let restored: Vec<(ChatId, UserId, DateTime<Utc>)> = state
+        .pending
+        .iter()
+        .map(|e| (e.key().0, UserId(e.key().1), e.value().deadline))
+        .collect();
+
+    for (chat_id, user_id, deadline) in restored {
+        schedule_timeout(state, chat_id, user_id, deadline);
+    }
+}
+
+// --- общие утилиты для всех стратегий ---
+
+/// Где висит сообщение-капча: в самом чате или (заявка, deep link) в личке.
+fn captcha_chat(chat_id: ChatId, pend: &Pending) -> ChatId {
+    if pend.via_dm {
+        ChatId(pend.user as i64)
+    } else {
+        chat_id
+    }
+}
+
+fn schedule_timeout(state: &AppState, chat_id: ChatId, user_id: UserId, deadline: DateTime<Utc>) {
     debug!(
         "CAPTCHA timeout scheduled in {}s (chat={}, user={})",
-        until_in, chat_id.0, user_id.0
+        (deadline - Utc::now()).num_seconds().max(0),
+        chat_id.0,
+        user_id.0
+    );
+    state.schedule(
+        deadline,
+        Job::CaptchaTimeout {
+            chat_id: chat_id.0,
+            user: user_id.0,
+        },
     );
+}
 
-    tokio::spawn(async move {
-        tokio::time::sleep_until(deadline).await;
+/// Срабатывание таймера капчи (из очереди задач).
+pub async fn on_timeout(bot: &Bot, state: &AppState, chat_id: ChatId, user_id: UserId) {
+    debug!(
+        "CAPTCHA timeout fired (chat={}, user={})",
+        chat_id.0, user_id.0
+    );
+    let key = AppState::key(chat_id, user_id);
+    let Some(pend) = state.remove_pending(&key) else {
         debug!(
-            "CAPTCHA timeout fired (chat={}, user={})",
+            "No pending found on timeout (likely solved/removed earlier) (chat={}, user={})",
             chat_id.0, user_id.0
         );
+        return;
+    };
+    fail_captcha(bot, state, chat_id, user_id, pend, FailReason::Timeout).await;
+}
 
-        let key = AppState::key(chat_id, user_id);
-        let Some((_key, pend)) = state.pending.remove(&key) else {
-            debug!(
-                "No pending found on timeout (likely solved/removed earlier) (chat={}, user={})",
-                chat_id.0, user_id.0
-            );
-            return;
-        };
+/// Почему капча провалена: влияет на текст уведомления и запись в журнале.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FailReason {
+    Timeout,
+    /// Исчерпаны попытки ответа (текстовые капчи).
+    Attempts,
+}
 
-        // 1) удаляем сообщение-капчу
-        match bot
-            .delete_message(chat_id, MessageId(pend.captcha_msg_id))
-            .await
-        {
-            Ok(_) => debug!(
+impl FailReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            FailReason::Timeout => "captcha timeout",
+            FailReason::Attempts => "captcha attempts exhausted",
+        }
+    }
+
+    fn notice(self, lang: Lang) -> &'static str {
+        match self {
+            FailReason::Timeout => tr!(lang, "captcha.failed_timeout"),
+            FailReason::Attempts => tr!(lang, "captcha.failed_attempts"),
+        }
+    }
+}
+
+/// Провал капчи: убрать капчу (и сообщения), наказать по политике чата,
+/// сообщить в чат. `pend` уже снят из ожидания вызывающим.
+async fn fail_captcha(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user_id: UserId,
+    pend: Pending,
+    reason: FailReason,
+) {
+    let key = AppState::key(chat_id, user_id);
+    state.metrics.captcha_failed(pend.captcha_mode.as_str());
+
+    // 1) удаляем сообщение-капчу
+    let shown_in = captcha_chat(chat_id, &pend);
+    let delete = bot.delete_message(shown_in, MessageId(pend.captcha_msg_id));
+    match api::call(state, shown_in, "delete_message", delete).await {
+        Ok(_) => {
+            state.metrics.messages_deleted(1);
+            debug!(
                 "Deleted captcha message id={} (chat={}, user={})",
                 pend.captcha_msg_id, chat_id.0, user_id.0
-            ),
-            Err(e) => warn!(
+            )
+        }
+        Err(e) => {
+            warn!(
                 "Failed to delete captcha message id={} (chat={}, user={}): {}",
                 pend.captcha_msg_id, chat_id.0, user_id.0, e
-            ),
+            )
         }
+    }
 
-        // 1b) удалить все сообщения пользователя (если включено)
-        if state.cfg.delete_unverified_messages {
-            let mut ok_cnt = 0usize;
-            let mut err_cnt = 0usize;
-            for mid in pend.user_message_ids {
-                match bot.delete_message(chat_id, MessageId(mid)).await {
-                    Ok(_) => ok_cnt += 1,
-                    Err(_) => err_cnt += 1,
-                }
-            }
+    // Заявитель в чат ещё не попал: наказывать некого, заявку отклоняем.
+    if pend.is_join_request() {
+        decline_join(bot, state, chat_id, user_id, reason).await;
+        return;
+    }
+
+    // Настройки чата читаем на момент срабатывания (могли поменяться)
+    let settings = state.settings(chat_id);
+
+    // 1b) удалить все сообщения пользователя (если включено)
+    if settings.delete_unverified_messages {
+        let ids: Vec<MessageId> = pend.user_message_ids.into_iter().map(MessageId).collect();
+        let (ok_cnt, err_cnt) = api::delete_messages(bot, state, chat_id, &ids).await;
+        debug!(
+            "Deleted user messages on failure: ok={}, err={} (chat={}, user={})",
+            ok_cnt, err_cnt, chat_id.0, user_id.0
+        );
+    }
+
+    // 2) наказание по политике чата (эскалация — по числу провалов)
+    let failures = state.record_failure(key);
+    let p = punishment(settings.failure_action, settings.kick_ban_minutes, failures);
+    debug!(
+        "Applying failure action: {:?} ({}, policy={}, failures={}) (chat={}, user={})",
+        p,
+        reason.as_str(),
+        settings.failure_action.as_str(),
+        failures,
+        chat_id.0,
+        user_id.0
+    );
+    apply_punishment(bot, state, chat_id, user_id, p, reason.as_str()).await;
+
+    // 3) сервисное уведомление в чат
+    let lang = settings.language;
+    let outcome = if p == Punishment::Mute {
+        tr!(lang, "captcha.outcome_muted")
+    } else {
+        tr!(lang, "captcha.outcome_removed")
+    };
+    let notice = bot
+        .send_message(
+            chat_id,
+            tr!(
+                lang,
+                "captcha.failed_notice",
+                reason = reason.notice(lang),
+                outcome = format!("<a href=\"tg://user?id={}\">{outcome}</a>", user_id.0),
+            ),
+        )
+        .parse_mode(ParseMode::Html);
+    match api::call(state, chat_id, "send_message", notice).await {
+        Ok(m) => {
             debug!(
-                "Deleted user messages on timeout: ok={}, err={} (chat={}, user={})",
-                ok_cnt, err_cnt, chat_id.0, user_id.0
+                "Posted failure notice (chat={}, user={})",
+                chat_id.0, user_id.0
             );
+            delete_later(state, chat_id, m.id, settings.notice_delete_secs);
+        }
+        Err(e) => {
+            warn!(
+                "Failed to post failure notice (chat={}, user={}): {}",
+                chat_id.0, user_id.0, e
+            )
         }
+    }
+}
 
-        // 2) кик/бан
-        let minutes = state.cfg.kick_ban_minutes;
-        debug!(
-            "Applying timeout action: minutes={} (chat={}, user={})",
-            minutes, chat_id.0, user_id.0
-        );
+/// Конкретное действие после провала капчи (или срабатывания фильтра).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Punishment {
+    SoftKick,
+    /// `None` — навсегда.
+    Ban(Option<i64>),
+    Mute,
+}
+
+/// Политика чата -> действие. `failures` — провалы с учётом текущего.
+fn punishment(action: FailureAction, minutes: i64, failures: u32) -> Punishment {
+    // Для эскалации без настроенного бана — час.
+    let ban_minutes = if minutes > 0 { minutes } else { 60 };
+    match action {
+        FailureAction::SoftKick => Punishment::SoftKick,
+        // KICK_BAN_MINUTES=0 исторически значит мягкий кик
+        FailureAction::TempBan if minutes <= 0 => Punishment::SoftKick,
+        FailureAction::TempBan => Punishment::Ban(Some(minutes)),
+        FailureAction::PermBan => Punishment::Ban(None),
+        FailureAction::Mute => Punishment::Mute,
+        FailureAction::Escalate => match failures {
+            0 | 1 => Punishment::SoftKick,
+            2 => Punishment::Ban(Some(ban_minutes)),
+            _ => Punishment::Ban(None),
+        },
+    }
+}
 
-        if minutes <= 0 {
+/// Наказать участника; `reason` — в журнал.
+pub(crate) async fn apply_punishment(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user_id: UserId,
+    p: Punishment,
+    reason: &str,
+) {
+    let target = user_id.0.to_string();
+    // Иначе конец испытательного срока вернул бы наказанному права.
+    state.cancel_job(&probation_key(chat_id, user_id.0));
+    state.probation_sent.remove(&(chat_id, user_id.0));
+    match p {
+        Punishment::SoftKick => {
             // “мягкий кик”
             let until = Utc::now() + ChronoDuration::minutes(1);
-            match bot
-                .ban_chat_member(chat_id, user_id)
-                .until_date(until)
-                .await
-            {
+            let ban = bot.ban_chat_member(chat_id, user_id).until_date(until);
+            match api::moderate(state, chat_id, target.clone(), "ban_chat_member", ban).await {
                 Ok(_) => {
                     debug!(
                         "Soft kick applied (ban+unban) (chat={}, user={})",
                         chat_id.0, user_id.0
                     );
-                    if let Err(e) = bot.unban_chat_member(chat_id, user_id).await {
+                    state.metrics.soft_kick();
+                    state.record(AuditEntry::new(
+                        chat_id,
+                        None,
+                        target.clone(),
+                        AuditAction::SoftKick,
+                        reason,
+                    ));
+                    let unban = bot.unban_chat_member(chat_id, user_id);
+                    if let Err(e) =
+                        api::moderate(state, chat_id, target, "unban_chat_member", unban).await
+                    {
                         warn!(
                             "Soft kick unban failed (chat={}, user={}): {}",
                             chat_id.0, user_id.0, e
@@ -322,140 +932,299 @@ fn schedule_timeout_cleanup(
                     );
                 }
             }
-        } else {
-            // Жёсткий вариант: бан на N минут
-            let until = Utc::now() + ChronoDuration::minutes(minutes);
-            match bot
-                .ban_chat_member(chat_id, user_id)
-                .until_date(until)
-                .await
-            {
+        }
+        Punishment::Ban(minutes) => {
+            let until = minutes.map(|m| Utc::now() + ChronoDuration::minutes(m));
+            let req = bot.ban_chat_member(chat_id, user_id);
+            let req = match until {
+                Some(u) => req.until_date(u),
+                None => req,
+            };
+            let reason = match minutes {
+                Some(m) => format!("{reason}, {m} min"),
+                None => format!("{reason}, permanent"),
+            };
+            match api::moderate(state, chat_id, target.clone(), "ban_chat_member", req).await {
                 Ok(_) => {
-                    debug!(
-                        "Temp BAN OK for {} minutes (until {}), chat={}, user={}",
-                        minutes,
-                        until.to_rfc3339(),
-                        chat_id.0,
-                        user_id.0
-                    );
-
-                    // Контрольный статус после бана
-                    match bot.get_chat_member(chat_id, user_id).await {
-                        Ok(cm) => {
-                            use teloxide::types::ChatMemberKind as CMK;
-                            match cm.kind {
-                                CMK::Owner(_) => {
-                                    warn!("POST-BAN STATUS: OWNER (cannot be banned), chat={}, user={}", chat_id.0, user_id.0);
-                                }
-                                CMK::Administrator(_) => {
-                                    warn!("POST-BAN STATUS: ADMIN (cannot be banned), chat={}, user={}", chat_id.0, user_id.0);
-                                }
-                                CMK::Member(_) => {
-                                    warn!("POST-BAN STATUS: still MEMBER — ban didn't stick, chat={}, user={}", chat_id.0, user_id.0);
-                                }
-                                CMK::Left => {
-                                    debug!(
-                                        "POST-BAN STATUS: LEFT (user is out), chat={}, user={}",
-                                        chat_id.0, user_id.0
-                                    );
-                                }
-                                CMK::Restricted(r) => {
-                                    debug!(
-                                        "POST-BAN STATUS: RESTRICTED until {}, chat={}, user={}",
-                                        fmt_until_date(&r.until_date),
-                                        chat_id.0,
-                                        user_id.0
-                                    );
-                                }
-                                CMK::Banned(b) => {
-                                    debug!(
-                                        "POST-BAN STATUS: BANNED until {}, chat={}, user={}",
-                                        fmt_until_date(&b.until_date),
-                                        chat_id.0,
-                                        user_id.0
-                                    );
-                                }
-                                #[allow(unreachable_patterns)]
-                                other => {
-                                    debug!(
-                                        "POST-BAN STATUS: {:?}, chat={}, user={}",
-                                        other, chat_id.0, user_id.0
-                                    );
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            warn!(
-                                "get_chat_member after ban failed (chat={}, user={}): {}",
-                                chat_id.0, user_id.0, e
-                            );
-                        }
+                    debug!("BAN OK ({}) chat={}, user={}", reason, chat_id.0, user_id.0);
+                    state.metrics.ban();
+                    // Telegram снимет бан и сам, но until_date дальше 366 дней
+                    // он считает «навсегда» — снимаем явно, по очереди задач.
+                    if let Some(u) = until {
+                        state.schedule(
+                            u,
+                            Job::Unban {
+                                chat_id: chat_id.0,
+                                user: user_id.0,
+                            },
+                        );
                     }
+                    state.record(AuditEntry::new(
+                        chat_id,
+                        None,
+                        target,
+                        AuditAction::Ban,
+                        reason,
+                    ));
                 }
                 Err(e) => {
                     error!(
-                        "Temp BAN failed ({} min) (chat={}, user={}): {}",
-                        minutes, chat_id.0, user_id.0, e
+                        "BAN failed ({}) (chat={}, user={}): {}",
+                        reason, chat_id.0, user_id.0, e
                     );
                 }
             }
         }
+        Punishment::Mute => {
+            // Без until_date — пока админ не снимет.
+            let mute = bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty());
+            match api::moderate(state, chat_id, target.clone(), "restrict_chat_member", mute).await
+            {
+                Ok(_) => {
+                    debug!("Muted (chat={}, user={})", chat_id.0, user_id.0);
+                    state.metrics.mute();
+                    state.record(AuditEntry::new(
+                        chat_id,
+                        None,
+                        target,
+                        AuditAction::Mute,
+                        reason,
+                    ));
+                }
+                Err(e) => {
+                    error!(
+                        "Mute failed (chat={}, user={}): {}",
+                        chat_id.0, user_id.0, e
+                    );
+                }
+            }
+        }
+    }
 
-        // 3) сервисное уведомление в чат
-        match bot
-            .send_message(
-                chat_id,
-                format!(
-                    "⏳ Время на подтверждение истекло — участник <a href=\"tg://user?id={}\">удалён</a>.",
-                    user_id.0
-                ),
-            )
-            .parse_mode(ParseMode::Html)
-            .await
-        {
-            Ok(_) => debug!("Posted timeout notice (chat={}, user={})", chat_id.0, user_id.0),
-            Err(e) => warn!("Failed to post timeout notice (chat={}, user={}): {}", chat_id.0, user_id.0, e),
+    // Контрольный статус — для любого действия
+    verify_member_status(bot, state, chat_id, user_id, p).await;
+}
+
+/// Проверить, что действие «прилипло»: спросить статус участника после него.
+async fn verify_member_status(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user_id: UserId,
+    p: Punishment,
+) {
+    use teloxide::types::ChatMemberKind as CMK;
+    let req = bot.get_chat_member(chat_id, user_id);
+    let cm = match api::call(state, chat_id, "get_chat_member", req).await {
+        Ok(cm) => cm,
+        Err(e) => {
+            warn!(
+                "get_chat_member after {:?} failed (chat={}, user={}): {}",
+                p, chat_id.0, user_id.0, e
+            );
+            return;
         }
-    });
+    };
+    match cm.kind {
+        CMK::Owner(_) => {
+            warn!(
+                "POST-{:?} STATUS: OWNER (cannot be punished), chat={}, user={}",
+                p, chat_id.0, user_id.0
+            );
+        }
+        CMK::Administrator(_) => {
+            warn!(
+                "POST-{:?} STATUS: ADMIN (cannot be punished), chat={}, user={}",
+                p, chat_id.0, user_id.0
+            );
+        }
+        CMK::Member(_) => {
+            warn!(
+                "POST-{:?} STATUS: still MEMBER — action didn't stick, chat={}, user={}",
+                p, chat_id.0, user_id.0
+            );
+        }
+        CMK::Left => {
+            debug!(
+                "POST-{:?} STATUS: LEFT (user is out), chat={}, user={}",
+                p, chat_id.0, user_id.0
+            );
+        }
+        CMK::Restricted(r) => {
+            debug!(
+                "POST-{:?} STATUS: RESTRICTED until {}, chat={}, user={}",
+                p,
+                fmt_until_date(&r.until_date),
+                chat_id.0,
+                user_id.0
+            );
+        }
+        CMK::Banned(b) => {
+            debug!(
+                "POST-{:?} STATUS: BANNED until {}, chat={}, user={}",
+                p,
+                fmt_until_date(&b.until_date),
+                chat_id.0,
+                user_id.0
+            );
+        }
+        #[allow(unreachable_patterns)]
+        other => {
+            debug!(
+                "POST-{:?} STATUS: {:?}, chat={}, user={}",
+                p, other, chat_id.0, user_id.0
+            );
+        }
+    }
 }
 
-async fn allow_user(bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<()> {
-    let allow = ChatPermissions::SEND_MESSAGES
+/// Права проверенного участника.
+pub(crate) fn member_permissions() -> ChatPermissions {
+    ChatPermissions::SEND_MESSAGES
         | ChatPermissions::SEND_MEDIA_MESSAGES
         | ChatPermissions::SEND_POLLS
         | ChatPermissions::SEND_OTHER_MESSAGES
-        | ChatPermissions::ADD_WEB_PAGE_PREVIEWS;
-    let _ = bot.restrict_chat_member(chat_id, user_id, allow).await;
+        | ChatPermissions::ADD_WEB_PAGE_PREVIEWS
+}
+
+async fn allow_user(bot: &Bot, state: &AppState, chat_id: ChatId, user_id: UserId) -> Result<()> {
+    let restrict = bot.restrict_chat_member(chat_id, user_id, member_permissions());
+    let _ = api::moderate(
+        state,
+        chat_id,
+        user_id.0.to_string(),
+        "restrict_chat_member",
+        restrict,
+    )
+    .await;
     Ok(())
 }
 
+/// Одобрить заявку. Вступление по ней придёт следом — капчу не повторяем.
+async fn approve_join(bot: &Bot, state: &AppState, chat_id: ChatId, user_id: UserId) {
+    // Отмечаем до вызова: апдейт о вступлении может обогнать ответ API.
+    state.mark_join_approved(AppState::key(chat_id, user_id));
+    let approve = bot.approve_chat_join_request(chat_id, user_id);
+    let _ = api::moderate(
+        state,
+        chat_id,
+        user_id.0.to_string(),
+        "approve_chat_join_request",
+        approve,
+    )
+    .await;
+}
+
+/// Капча по заявке провалена: отклонить и сообщить заявителю в личку.
+async fn decline_join(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    user_id: UserId,
+    reason: FailReason,
+) {
+    let target = user_id.0.to_string();
+    let decline = bot.decline_chat_join_request(chat_id, user_id);
+    match api::moderate(
+        state,
+        chat_id,
+        target.clone(),
+        "decline_chat_join_request",
+        decline,
+    )
+    .await
+    {
+        Ok(_) => {
+            debug!(
+                "Join request declined ({}) (chat={}, user={})",
+                reason.as_str(),
+                chat_id.0,
+                user_id.0
+            );
+            state.record(AuditEntry::new(
+                chat_id,
+                None,
+                target,
+                AuditAction::JoinDeclined,
+                reason.as_str(),
+            ));
+        }
+        Err(e) => {
+            warn!(
+                "Decline join request failed (chat={}, user={}): {}",
+                chat_id.0, user_id.0, e
+            );
+        }
+    }
+
+    let lang = state.settings(chat_id).language;
+    let dm = ChatId(user_id.0 as i64);
+    let notice = bot.send_message(
+        dm,
+        tr!(lang, "captcha.join_declined", reason = reason.notice(lang)),
+    );
+    let _ = api::call(state, dm, "send_message", notice).await;
+}
+
 async fn complete_and_greet(
     bot: &Bot,
-    _state: Arc<AppState>,
+    state: Arc<AppState>,
     chat_id: ChatId,
     user: &User,
     pend: Pending,
 ) -> Result<()> {
+    state.metrics.captcha_passed(pend.captcha_mode.as_str());
+    state.record(AuditEntry::new(
+        chat_id,
+        None,
+        user.id.0.to_string(),
+        AuditAction::CaptchaPassed,
+        pend.captcha_mode.as_str(),
+    ));
+
     // 1) удалить сообщение-капчу
-    let _ = bot
-        .delete_message(chat_id, MessageId(pend.captcha_msg_id))
-        .await;
+    let shown_in = captcha_chat(chat_id, &pend);
+    let delete = bot.delete_message(shown_in, MessageId(pend.captcha_msg_id));
+    if api::call(&state, shown_in, "delete_message", delete)
+        .await
+        .is_ok()
+    {
+        state.metrics.messages_deleted(1);
+    }
 
     // 2) удалить все сообщения пользователя, накопленные во время ожидания
     if !pend.user_message_ids.is_empty() {
         let mut seen = HashSet::new();
-        for mid in pend
+        let ids: Vec<MessageId> = pend
             .user_message_ids
-            .into_iter()
-            .filter(|m| *m > pend.captcha_msg_id)
-        {
-            if seen.insert(mid) {
-                let _ = bot.delete_message(chat_id, MessageId(mid)).await;
-            }
-        }
+            .iter()
+            .copied()
+            .filter(|m| (pend.via_dm || *m > pend.captcha_msg_id) && seen.insert(*m))
+            .map(MessageId)
+            .collect();
+        let (ok_cnt, err_cnt) = api::delete_messages(bot, &state, chat_id, &ids).await;
+        debug!(
+            "Deleted user messages on pass: ok={}, err={} (chat={}, user={})",
+            ok_cnt, err_cnt, chat_id.0, user.id.0
+        );
     }
 
-    allow_user(bot, chat_id, user.id).await?;
+    let lang = state.settings(chat_id).language;
+    if pend.is_join_request() {
+        approve_join(bot, &state, chat_id, user.id).await;
+        probation::start(bot, &state, chat_id, user.id).await;
+        let done = bot.send_message(shown_in, tr!(lang, "captcha.join_approved"));
+        let _ = api::call(&state, shown_in, "send_message", done).await;
+    } else {
+        // На испытательном сроке права ещё урезаны; полные вернёт планировщик.
+        if !probation::start(bot, &state, chat_id, user.id).await {
+            allow_user(bot, &state, chat_id, user.id).await?;
+        }
+        if pend.via_dm {
+            let done = bot.send_message(shown_in, tr!(lang, "captcha.private_passed"));
+            let _ = api::call(&state, shown_in, "send_message", done).await;
+        }
+    }
 
     let display = user
         .username
@@ -463,16 +1232,22 @@ async fn complete_and_greet(
         .map(|u| format!("@{u}"))
         .unwrap_or_else(|| format!("user {}", user.id.0));
 
-    let _ = bot
-        .send_message(
-            chat_id,
-            format!(
-                "Добро пожаловать, <a href=\"tg://user?id={}\">{}</a>!",
-                user.id.0, display
-            ),
-        )
-        .parse_mode(ParseMode::Html)
-        .await;
+    let settings = state.settings(chat_id);
+    if !settings.welcome_enabled {
+        return Ok(());
+    }
+    let lang = settings.language;
+    let template = settings
+        .welcome_template
+        .clone()
+        .unwrap_or_else(|| tr!(lang, "captcha.welcome").to_string());
+    let mention = format!("<a href=\"tg://user?id={}\">{display}</a>", user.id.0);
+    let text = templates::fill(bot, &state, chat_id, lang, &template, &mention, "").await;
+
+    let welcome = bot.send_message(chat_id, text).parse_mode(ParseMode::Html);
+    if let Ok(m) = api::call(&state, chat_id, "send_message", welcome).await {
+        delete_later(&state, chat_id, m.id, settings.welcome_delete_secs);
+    }
 
     Ok(())
 }
@@ -493,15 +1268,102 @@ mod tests {
         assert_eq!(CaptchaMode::from_str("MATH2").unwrap(), CaptchaMode::Math2);
         assert_eq!(CaptchaMode::from_str("off").unwrap(), CaptchaMode::Off);
         assert_eq!(CaptchaMode::from_str("image").unwrap(), CaptchaMode::Image);
+        assert_eq!(CaptchaMode::from_str("quiz").unwrap(), CaptchaMode::Choice);
         assert_eq!(CaptchaMode::from_str("???").unwrap(), CaptchaMode::Button); // default
     }
 
+    #[test]
+    fn only_text_modes_take_typed_answers() {
+        assert!(CaptchaMode::Math2.typed_answer());
+        assert!(CaptchaMode::Image.typed_answer());
+        // у choice тоже есть expected_answer, но отвечают кнопкой
+        assert!(!CaptchaMode::Choice.typed_answer());
+        assert!(!CaptchaMode::Button.typed_answer());
+    }
+
     #[test]
     fn provider_matches_mode() {
         assert!(provider(CaptchaMode::Off).is_none());
         assert!(provider(CaptchaMode::Button).is_some());
         assert!(provider(CaptchaMode::Math2).is_some());
-        assert!(provider(CaptchaMode::Image).is_some()); // пока Image -> Button
+        assert!(provider(CaptchaMode::Image).is_some());
+        assert!(provider(CaptchaMode::Choice).is_some());
+    }
+
+    #[test]
+    fn failure_policy_to_punishment() {
+        use FailureAction as F;
+        assert_eq!(punishment(F::SoftKick, 30, 1), Punishment::SoftKick);
+        assert_eq!(punishment(F::TempBan, 30, 1), Punishment::Ban(Some(30)));
+        assert_eq!(punishment(F::TempBan, 0, 1), Punishment::SoftKick);
+        assert_eq!(punishment(F::PermBan, 0, 1), Punishment::Ban(None));
+        assert_eq!(punishment(F::Mute, 30, 5), Punishment::Mute);
+
+        assert_eq!(punishment(F::Escalate, 0, 1), Punishment::SoftKick);
+        assert_eq!(punishment(F::Escalate, 0, 2), Punishment::Ban(Some(60)));
+        assert_eq!(punishment(F::Escalate, 10, 2), Punishment::Ban(Some(10)));
+        assert_eq!(punishment(F::Escalate, 10, 3), Punishment::Ban(None));
+    }
+
+    #[test]
+    fn callback_target_parsing() {
+        assert_eq!(parse_target("ok:42:a1b2c3d4"), Some((42, "a1b2c3d4")));
+        assert_eq!(parse_target("choice:42:a1b2c3d4:3"), Some((42, "a1b2c3d4")));
+        assert_eq!(parse_target("ok:42"), Some((42, "")));
+        assert_eq!(parse_target("ok:x:abc"), None);
+        assert_eq!(parse_target("garbage"), None);
+    }
+
+    #[test]
+    fn nonce_is_random_and_short() {
+        let a = new_nonce();
+        assert_eq!(a.len(), 8);
+        assert!(a.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(a, new_nonce());
+    }
+
+    #[test]
+    fn join_request_captcha_lives_in_dm() {
+        let mut pend = Pending {
+            user: 42,
+            captcha_msg_id: 1,
+            deadline: Utc::now(),
+            user_message_ids: vec![],
+            captcha_mode: CaptchaMode::Button,
+            expected_answer: None,
+            attempts: 0,
+            nonce: String::new(),
+            via_dm: false,
+            token: String::new(),
+        };
+        assert_eq!(captcha_chat(ChatId(-100), &pend), ChatId(-100));
+        pend.via_dm = true;
+        assert_eq!(captcha_chat(ChatId(-100), &pend), ChatId(42));
+        assert!(pend.is_join_request());
+        // deep link: тоже в личке, но участник уже в чате
+        pend.token = new_token();
+        assert!(!pend.is_join_request());
+    }
+
+    #[test]
+    fn verify_link_fits_deep_link_payload() {
+        let token = new_token();
+        let link = verify_link("ranger_bot", &token);
+        let (base, payload) = link.split_once("?start=").unwrap();
+        assert_eq!(base, "https://t.me/ranger_bot");
+        assert!(payload.len() <= 64);
+        assert!(payload
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+        assert_eq!(payload.strip_prefix(VERIFY_PREFIX), Some(token.as_str()));
+    }
+
+    #[test]
+    fn attempt_limit() {
+        assert!(!attempts_exhausted(2, 3));
+        assert!(attempts_exhausted(3, 3));
+        assert!(attempts_exhausted(1, 1));
+        assert!(!attempts_exhausted(100, 0));
     }
 
     #[test]
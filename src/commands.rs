@@ -3,13 +3,24 @@
 //! Команды бота.
 //! - Админу показываем подробную шпаргалку с описаниями и примерами.
 //! - Обычным пользователям — краткое описание и ссылка на установку/README.
-//! В дальнейшем строки легко вынести в i18n.
-
-use crate::state::AppState;
-use crate::utils::normalize_username;
+//!
+//! Тексты — из каталогов `i18n` (язык чата, в личке — язык пользователя).
+
+use crate::admins::{self, Access};
+use crate::audit::{self, AuditAction, AuditEntry};
+use crate::captcha;
+use crate::filter;
+use crate::i18n::{self, tr, Lang};
+use crate::settings_menu;
+use crate::state::{AppState, WlScope};
+use crate::templates;
+use crate::utils::{mention, normalize_username};
+use crate::warns;
 use anyhow::Result;
 use std::sync::Arc;
 use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::utils::html::escape;
 
 // Ссылка на README проекта (отображается в /help для всех)
 const README_URL: &str = "https://github.com/filippovle/telegram-ranger#readme";
@@ -26,10 +37,18 @@ pub async fn handle_command(
     };
 
     let (cmd, arg) = parse_command(text);
-    if from.id == state.cfg.admin_id {
-        handle_admin_command(bot, state, msg, cmd, arg).await
+    let lang = i18n::chat_lang(&state, msg.chat.id, Some(from));
+    // Ссылка из чата на капчу в личке — для всех, админы тоже её проходят.
+    if cmd == "start" && msg.chat.is_private() {
+        if let Some(token) = arg.and_then(|a| a.strip_prefix(captcha::VERIFY_PREFIX)) {
+            return captcha::on_verify_start(bot, state, msg, lang, token).await;
+        }
+    }
+    let access = admins::access(bot, &state, msg.chat.id, from.id).await;
+    if access.is_admin() {
+        handle_admin_command(bot, state, msg, lang, access, cmd, arg).await
     } else {
-        handle_user_command(bot, msg, cmd).await
+        handle_user_command(bot, msg, lang, cmd).await
     }
 }
 
@@ -39,12 +58,17 @@ async fn handle_admin_command(
     bot: &Bot,
     state: Arc<AppState>,
     msg: &Message,
+    lang: Lang,
+    access: Access,
     cmd: &str,
     arg: Option<&str>,
 ) -> Result<()> {
+    // Супер-админ правит общий whitelist, админ чата — только список своего чата.
+    let scope = access.scope(msg.chat.id);
+    let note = scope_note(lang, scope);
     match cmd {
         "start" | "help" => {
-            bot.send_message(msg.chat.id, admin_help_text())
+            bot.send_message(msg.chat.id, admin_help_text(lang))
                 .parse_mode(teloxide::types::ParseMode::Html)
                 .await?;
         }
@@ -55,37 +79,74 @@ async fn handle_admin_command(
                 return send_usage(
                     bot,
                     msg,
-                    "Usage: <code>/allowbot &lt;id|@username&gt;</code>",
+                    lang,
+                    "<code>/allowbot &lt;id|@username&gt;</code>",
                 )
                 .await;
             };
             if let Some(id) = parse_numeric(a) {
-                state.allow_bot_id(id);
-                bot.send_message(msg.chat.id, format!("✅ Bot {id} allowed (id)"))
-                    .await?;
+                state.allow_bot_id(scope, id);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistAdd,
+                    format!("bot {id}"),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.bot_allowed_id", id = id, note = note),
+                )
+                .await?;
             } else {
-                state.allow_bot_username(a);
-                bot.send_message(msg.chat.id, format!("✅ Bot {a} allowed (username)"))
-                    .await?;
+                state.allow_bot_username(scope, a);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistAdd,
+                    format!("bot @{}", normalize_username(a)),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.bot_allowed_name", name = a, note = note),
+                )
+                .await?;
             }
         }
         "denybot" => {
             let Some(a) = arg else {
-                return send_usage(
-                    bot,
-                    msg,
-                    "Usage: <code>/denybot &lt;id|@username&gt;</code>",
-                )
-                .await;
+                return send_usage(bot, msg, lang, "<code>/denybot &lt;id|@username&gt;</code>")
+                    .await;
             };
             if let Some(id) = parse_numeric(a) {
-                state.deny_bot_id(id);
-                bot.send_message(msg.chat.id, format!("⛔ Bot {id} denied (id)"))
-                    .await?;
+                state.deny_bot_id(scope, id);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistRemove,
+                    format!("bot {id}"),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.bot_denied_id", id = id, note = note),
+                )
+                .await?;
             } else {
-                state.deny_bot_username(a);
-                bot.send_message(msg.chat.id, format!("⛔ Bot {a} denied (username)"))
-                    .await?;
+                state.deny_bot_username(scope, a);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistRemove,
+                    format!("bot @{}", normalize_username(a)),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.bot_denied_name", name = a, note = note),
+                )
+                .await?;
             }
         }
 
@@ -95,27 +156,54 @@ async fn handle_admin_command(
                 return send_usage(
                     bot,
                     msg,
-                    "Usage: <code>/allowuser &lt;id|@username&gt;</code>",
+                    lang,
+                    "<code>/allowuser &lt;id|@username&gt;</code>",
                 )
                 .await;
             };
             if let Some(id) = parse_numeric(a) {
-                state.allow_user_id(id);
-                bot.send_message(msg.chat.id, format!("✅ User {id} allowed"))
-                    .await?;
+                state.allow_user_id(scope, id);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistAdd,
+                    id.to_string(),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.user_allowed", user = id, note = note),
+                )
+                .await?;
             } else {
                 let uname = normalize_username(a);
                 if uname.is_empty() {
                     return send_usage(
                         bot,
                         msg,
-                        "Usage: <code>/allowuser &lt;id|@username&gt;</code>",
+                        lang,
+                        "<code>/allowuser &lt;id|@username&gt;</code>",
                     )
                     .await;
                 }
-                state.allow_username(&uname);
-                bot.send_message(msg.chat.id, format!("✅ User @{uname} allowed"))
-                    .await?;
+                state.allow_username(scope, &uname);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistAdd,
+                    format!("@{uname}"),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(
+                        lang,
+                        "commands.user_allowed",
+                        user = format!("@{uname}"),
+                        note = note
+                    ),
+                )
+                .await?;
             }
         }
         "denyuser" => {
@@ -123,27 +211,54 @@ async fn handle_admin_command(
                 return send_usage(
                     bot,
                     msg,
-                    "Usage: <code>/denyuser &lt;id|@username&gt;</code>",
+                    lang,
+                    "<code>/denyuser &lt;id|@username&gt;</code>",
                 )
                 .await;
             };
             if let Some(id) = parse_numeric(a) {
-                state.deny_user_id(id);
-                bot.send_message(msg.chat.id, format!("⛔ User {id} denied"))
-                    .await?;
+                state.deny_user_id(scope, id);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistRemove,
+                    id.to_string(),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(lang, "commands.user_denied", user = id, note = note),
+                )
+                .await?;
             } else {
                 let uname = normalize_username(a);
                 if uname.is_empty() {
                     return send_usage(
                         bot,
                         msg,
-                        "Usage: <code>/denyuser &lt;id|@username&gt;</code>",
+                        lang,
+                        "<code>/denyuser &lt;id|@username&gt;</code>",
                     )
                     .await;
                 }
-                state.deny_username(&uname);
-                bot.send_message(msg.chat.id, format!("⛔ User @{uname} denied"))
-                    .await?;
+                state.deny_username(scope, &uname);
+                record_wl(
+                    &state,
+                    msg,
+                    scope,
+                    AuditAction::WhitelistRemove,
+                    format!("@{uname}"),
+                );
+                bot.send_message(
+                    msg.chat.id,
+                    tr!(
+                        lang,
+                        "commands.user_denied",
+                        user = format!("@{uname}"),
+                        note = note
+                    ),
+                )
+                .await?;
             }
         }
 
@@ -169,37 +284,85 @@ async fn handle_admin_command(
                 .map(|s| format!("@{}", s.clone()))
                 .collect();
 
-            let msg_text = format!(
-                "<b>Whitelists</b>\n\
-                 <b>Bots (ids)</b>: {}\n\
-                 <b>Bots (names)</b>: {}\n\
-                 <b>Users (ids)</b>: {}\n\
-                 <b>Users (names)</b>: {}",
-                list_or_none(&bots),
-                list_or_none(&bot_names),
-                list_or_none(&uids),
-                list_or_none(&unames),
+            let mut msg_text = format!(
+                "<b>{}</b>\n{}",
+                tr!(lang, "commands.whitelists"),
+                whitelist_lines(lang, &bots, &bot_names, &uids, &unames),
             );
 
+            // Список текущего чата (в группах)
+            if !msg.chat.id.is_user() {
+                let wl = state
+                    .chat_whitelists
+                    .get(&msg.chat.id)
+                    .map(|r| r.clone())
+                    .unwrap_or_default();
+                let ids = |v: &std::collections::BTreeSet<u64>| -> Vec<String> {
+                    v.iter().map(|x| x.to_string()).collect()
+                };
+                let names = |v: &std::collections::BTreeSet<String>| -> Vec<String> {
+                    v.iter().map(|s| format!("@{s}")).collect()
+                };
+                msg_text.push_str(&format!(
+                    "\n\n<b>{}</b>\n{}",
+                    tr!(lang, "commands.this_chat"),
+                    whitelist_lines(
+                        lang,
+                        &ids(&wl.bot_ids),
+                        &names(&wl.bot_names),
+                        &ids(&wl.user_ids),
+                        &names(&wl.user_names),
+                    ),
+                ));
+            }
+
             bot.send_message(msg.chat.id, msg_text)
                 .parse_mode(teloxide::types::ParseMode::Html)
                 .await?;
         }
 
+        // ---- НАСТРОЙКИ ЧАТА ----
+        "settings" => {
+            // В группе — текущий чат; в личке супер-админ может указать id группы.
+            let target = match arg.filter(|_| access == Access::SuperAdmin) {
+                Some(a) => match a.trim().parse::<i64>() {
+                    Ok(id) => ChatId(id),
+                    Err(_) => {
+                        return send_usage(bot, msg, lang, "<code>/settings [chat_id]</code>").await
+                    }
+                },
+                None => msg.chat.id,
+            };
+            settings_menu::open(bot, state, msg, target).await?;
+        }
+
+        // ---- ЖУРНАЛ ----
+        "audit" => {
+            audit::show(bot, state, msg, access, arg).await?;
+        }
+
+        // ---- ШАБЛОНЫ ----
+        "welcome" => template_command(bot, &state, msg, lang, Template::Welcome, arg).await?,
+        "captchatext" => template_command(bot, &state, msg, lang, Template::Captcha, arg).await?,
+        "rules" => rules_command(bot, &state, msg, lang, arg).await?,
+
+        // ---- ФИЛЬТР ----
+        "filter" => filter::command(bot, &state, msg, lang, arg).await?,
+
+        // ---- ПРЕДУПРЕЖДЕНИЯ ----
+        "warn" | "unwarn" | "warns" => warns::command(bot, &state, msg, lang, cmd, arg).await?,
+
         "about" => {
-            bot.send_message(msg.chat.id, about_text())
+            bot.send_message(msg.chat.id, about_text(lang))
                 .parse_mode(teloxide::types::ParseMode::Html)
                 .await?;
         }
 
         _ => {
             // Нестрогий fallback: подскажем /help
-            bot.send_message(
-                msg.chat.id,
-                "Неизвестная команда. Посмотри <b>/help</b> для списка и примеров.",
-            )
-            .parse_mode(teloxide::types::ParseMode::Html)
-            .await?;
+            bot.send_message(msg.chat.id, tr!(lang, "commands.unknown"))
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .await?;
         }
     }
     Ok(())
@@ -207,15 +370,15 @@ async fn handle_admin_command(
 
 /* ======================== Пользователь ======================== */
 
-async fn handle_user_command(bot: &Bot, msg: &Message, cmd: &str) -> Result<()> {
+async fn handle_user_command(bot: &Bot, msg: &Message, lang: Lang, cmd: &str) -> Result<()> {
     match cmd {
         "start" | "help" => {
-            bot.send_message(msg.chat.id, user_help_text())
+            bot.send_message(msg.chat.id, user_help_text(lang))
                 .parse_mode(teloxide::types::ParseMode::Html)
                 .await?;
         }
         "about" => {
-            bot.send_message(msg.chat.id, about_text())
+            bot.send_message(msg.chat.id, about_text(lang))
                 .parse_mode(teloxide::types::ParseMode::Html)
                 .await?;
         }
@@ -226,70 +389,242 @@ async fn handle_user_command(bot: &Bot, msg: &Message, cmd: &str) -> Result<()>
 
 /* ========================== Тексты ========================== */
 
-fn admin_help_text() -> String {
-    r#"<b>Telegram Ranger — справка (админ)</b>
+fn admin_help_text(lang: Lang) -> &'static str {
+    tr!(lang, "commands.admin_help")
+}
 
-<b>Что делает бот</b>
-• Капча для новых участников (кнопка с таймаутом).
-• По таймауту — кик/бан (настраивается).
-• Whitelist пользователей и ботов (по ID или @username).
-• Можно включить удаление сообщений непроверенных пользователей.
+fn user_help_text(lang: Lang) -> String {
+    tr!(lang, "commands.user_help", url = README_URL)
+}
 
-<b>Быстрые команды</b>
-<pre>/allowbot &lt;id|@username&gt;</pre>
-Добавить бота в белый список. Идентификатор — числовой ID или @username (без учёта регистра).
+fn about_text(lang: Lang) -> String {
+    tr!(lang, "commands.about", url = README_URL)
+}
 
-<pre>/denybot &lt;id|@username&gt;</pre>
-Убрать бота из белого списка.
+/// Четыре строки списка: боты/люди по id и по именам.
+fn whitelist_lines(
+    lang: Lang,
+    bot_ids: &[String],
+    bot_names: &[String],
+    user_ids: &[String],
+    user_names: &[String],
+) -> String {
+    [
+        ("commands.bots_ids", bot_ids),
+        ("commands.bots_names", bot_names),
+        ("commands.users_ids", user_ids),
+        ("commands.users_names", user_names),
+    ]
+    .iter()
+    .map(|(key, items)| format!("<b>{}</b>: {}", tr!(lang, key), list_or_none(lang, items)))
+    .collect::<Vec<_>>()
+    .join("\n")
+}
 
-<pre>/allowuser &lt;id|@username&gt;</pre>
-Добавить человека в белый список (пройдёт без капчи).
+/* ======================== Шаблоны ======================== */
 
-<pre>/denyuser &lt;id|@username&gt;</pre>
-Убрать человека из белого списка.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Template {
+    Welcome,
+    Captcha,
+}
 
-<pre>/listallow</pre>
-Показать текущие whitelist'ы.
+impl Template {
+    fn placeholders(self) -> &'static [&'static str] {
+        match self {
+            Template::Welcome => templates::WELCOME,
+            Template::Captcha => templates::CAPTCHA,
+        }
+    }
 
-<pre>/about</pre>
-Информация о проекте и ссылка на README.
+    /// Имя поля в журнале.
+    fn field(self) -> &'static str {
+        match self {
+            Template::Welcome => "welcome_template",
+            Template::Captcha => "captcha_template",
+        }
+    }
 
-<b>Полезно знать</b>
-• @username обрабатывается без учёта регистра и без «@».
-• Для кика по таймауту у бота должны быть права администратора на «Удаление участников» и «Ограничение участников».
-• Настройки берутся из <code>.env</code>.
-"#.to_string()
+    fn default_key(self) -> &'static str {
+        match self {
+            Template::Welcome => "captcha.welcome",
+            Template::Captcha => "captcha.prompt",
+        }
+    }
 }
 
-fn user_help_text() -> String {
-    format!(
-        r#"<b>Telegram Ranger</b>
-Я помогаю защищать группы от спама: прошу новичков подтвердить, что они человек.
+/// `/welcome [on|off|reset|шаблон]`, `/captchatext [reset|шаблон]` — для текущего чата.
+async fn template_command(
+    bot: &Bot,
+    state: &AppState,
+    msg: &Message,
+    lang: Lang,
+    kind: Template,
+    arg: Option<&str>,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let settings = state.settings(chat);
+    let current = match kind {
+        Template::Welcome => settings.welcome_template.clone(),
+        Template::Captcha => settings.captcha_template.clone(),
+    };
+    let set = |t: Option<String>| {
+        state.update_chat_settings(chat, |cs| match kind {
+            Template::Welcome => cs.welcome_template = t,
+            Template::Captcha => cs.captcha_template = t,
+        })
+    };
+    let available = tr!(
+        lang,
+        "templates.available",
+        list = kind
+            .placeholders()
+            .iter()
+            .map(|p| format!("<code>{{{p}}}</code>"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let reply = match arg {
+        None => {
+            let (title, text) = match &current {
+                Some(t) => (tr!(lang, "templates.current_custom"), t.as_str()),
+                None => (
+                    tr!(lang, "templates.current_default"),
+                    tr!(lang, kind.default_key()),
+                ),
+            };
+            let mut reply = format!("{title}\n<pre>{}</pre>\n{available}", escape(text));
+            if kind == Template::Welcome && !settings.welcome_enabled {
+                reply.push_str(&format!(
+                    "\n{}",
+                    tr!(lang, "templates.welcome_disabled_note")
+                ));
+            }
+            reply
+        }
+        Some("on" | "off") if kind == Template::Welcome => {
+            let on = arg == Some("on");
+            state.update_chat_settings(chat, |cs| cs.welcome_enabled = Some(on));
+            record_settings(state, msg, format!("welcome_enabled={on}"));
+            tr!(
+                lang,
+                if on {
+                    "templates.welcome_on"
+                } else {
+                    "templates.welcome_off"
+                }
+            )
+            .to_string()
+        }
+        Some("reset") => {
+            set(None);
+            record_settings(state, msg, format!("{}=default", kind.field()));
+            tr!(lang, "templates.reset").to_string()
+        }
+        Some(src) => match templates::sanitize(src, kind.placeholders()) {
+            Err(e) => format!("{}\n{available}", e.describe(lang)),
+            Ok(t) => {
+                set(Some(t.clone()));
+                record_settings(state, msg, format!("{}=custom", kind.field()));
+                // Превью — с упоминанием самого админа и задачей кнопочной капчи.
+                let settings = state.settings(chat);
+                let who = match &msg.from {
+                    Some(u) => mention(bot, chat, u.id).await,
+                    None => String::new(),
+                };
+                let task = tr!(
+                    lang,
+                    "captcha.task_button",
+                    secs = settings.captcha_timeout_secs
+                );
+                let preview = templates::fill(bot, state, chat, lang, &t, &who, &task).await;
+                format!("{}\n\n{preview}", tr!(lang, "templates.saved"))
+            }
+        },
+    };
+
+    bot.send_message(chat, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
 
-Если хочешь установить такого же бота к себе — смотри инструкцию:
-<a href="{url}">{url}</a>
+/// `/rules [ссылка|off]` — ссылка для `{rules_link}`.
+async fn rules_command(
+    bot: &Bot,
+    state: &AppState,
+    msg: &Message,
+    lang: Lang,
+    arg: Option<&str>,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let reply = match arg {
+        None => match state.settings(chat).rules_link {
+            Some(link) => tr!(lang, "templates.rules_current", link = escape(&link)),
+            None => tr!(lang, "templates.rules_none").to_string(),
+        },
+        Some("off" | "reset") => {
+            state.update_chat_settings(chat, |cs| cs.rules_link = None);
+            record_settings(state, msg, "rules_link=none".to_string());
+            tr!(lang, "templates.rules_cleared").to_string()
+        }
+        Some(a) => match parse_link(a) {
+            Some(link) => {
+                state.update_chat_settings(chat, |cs| cs.rules_link = Some(link.clone()));
+                record_settings(state, msg, format!("rules_link={link}"));
+                tr!(lang, "templates.rules_saved").to_string()
+            }
+            None => tr!(lang, "templates.bad_url").to_string(),
+        },
+    };
+    bot.send_message(chat, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
 
-Доступные команды в этом чате:
-• <b>/help</b> — краткая справка.
-• Остальные команды доступны администратору группы."#,
-        url = README_URL
-    )
+/// Ссылка `http(s)://` или `tg://`, уже нормализованная.
+fn parse_link(s: &str) -> Option<String> {
+    let url = url::Url::parse(s.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https" | "tg").then(|| url.to_string())
 }
 
-fn about_text() -> String {
-    format!(
-        r#"<b>Telegram Ranger</b>
-Исходники и руководство: <a href="{url}">{url}</a>
-Автор: Lev Filippov
-Лицензия: MIT"#,
-        url = README_URL
-    )
+/// Записать правку настроек чата в журнал.
+pub(crate) fn record_settings(state: &AppState, msg: &Message, change: String) {
+    let actor = msg.from.as_ref().map(|u| u.id);
+    state.record(AuditEntry::new(
+        msg.chat.id,
+        actor,
+        format!("chat {}", msg.chat.id.0),
+        AuditAction::SettingsChanged,
+        change,
+    ));
 }
 
 /* ======================== Утилиты ======================== */
 
-async fn send_usage(bot: &Bot, msg: &Message, usage_html: &str) -> Result<()> {
-    bot.send_message(msg.chat.id, usage_html)
+/// Записать правку whitelist'а в журнал.
+fn record_wl(state: &AppState, msg: &Message, scope: WlScope, action: AuditAction, target: String) {
+    let reason = match scope {
+        WlScope::Global => "global list",
+        WlScope::Chat(_) => "chat list",
+    };
+    let actor = msg.from.as_ref().map(|u| u.id);
+    state.record(AuditEntry::new(msg.chat.id, actor, target, action, reason));
+}
+
+/// Пометка к подтверждению: правка ушла в список этого чата.
+fn scope_note(lang: Lang, scope: WlScope) -> &'static str {
+    match scope {
+        WlScope::Global => "",
+        WlScope::Chat(_) => tr!(lang, "commands.chat_only"),
+    }
+}
+
+/// «Usage: …» на языке чата; `syntax` — HTML.
+async fn send_usage(bot: &Bot, msg: &Message, lang: Lang, syntax: &str) -> Result<()> {
+    bot.send_message(msg.chat.id, tr!(lang, "commands.usage", syntax = syntax))
         .parse_mode(teloxide::types::ParseMode::Html)
         .await?;
     Ok(())
@@ -315,9 +650,9 @@ fn parse_numeric(s: &str) -> Option<u64> {
     s.trim().parse::<u64>().ok()
 }
 
-fn list_or_none(items: &[String]) -> String {
+fn list_or_none(lang: Lang, items: &[String]) -> String {
     if items.is_empty() {
-        "(none)".into()
+        tr!(lang, "commands.none").into()
     } else {
         items.join(", ")
     }
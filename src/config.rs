@@ -1,13 +1,46 @@
 use std::str::FromStr;
 // src/config.rs
-use teloxide::types::UserId;
+use crate::filter::FilterRules;
+use crate::i18n::Lang;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use teloxide::types::{User, UserId};
+use url::Url;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CaptchaMode {
     Off,
     Button,
     Math2,
     Image,
+    Choice,
+}
+
+impl CaptchaMode {
+    /// Все режимы (для меню настроек).
+    pub const ALL: [CaptchaMode; 5] = [
+        CaptchaMode::Off,
+        CaptchaMode::Button,
+        CaptchaMode::Math2,
+        CaptchaMode::Image,
+        CaptchaMode::Choice,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CaptchaMode::Off => "off",
+            CaptchaMode::Button => "button",
+            CaptchaMode::Math2 => "math2",
+            CaptchaMode::Image => "image",
+            CaptchaMode::Choice => "choice",
+        }
+    }
+
+    /// Ответ пишут текстом в чат (math2/image), а не жмут кнопку.
+    pub fn typed_answer(self) -> bool {
+        matches!(self, CaptchaMode::Math2 | CaptchaMode::Image)
+    }
 }
 
 impl FromStr for CaptchaMode {
@@ -18,18 +51,267 @@ impl FromStr for CaptchaMode {
             "button" | "inline" => Ok(CaptchaMode::Button),
             "math2" | "math" => Ok(CaptchaMode::Math2),
             "image" | "img" => Ok(CaptchaMode::Image),
+            "choice" | "quiz" => Ok(CaptchaMode::Choice),
             _ => Ok(CaptchaMode::Button),
         }
     }
 }
 
+/// Что делать с тем, кто не прошёл капчу.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureAction {
+    /// Бан+разбан: удалить из чата, но дать зайти снова.
+    SoftKick,
+    /// Бан на `kick_ban_minutes` (0 — как мягкий кик, прежнее поведение).
+    TempBan,
+    PermBan,
+    /// Оставить в чате без права писать.
+    Mute,
+    /// По числу прошлых провалов: кик → временный бан → навсегда.
+    Escalate,
+}
+
+impl FailureAction {
+    pub const ALL: [FailureAction; 5] = [
+        FailureAction::SoftKick,
+        FailureAction::TempBan,
+        FailureAction::PermBan,
+        FailureAction::Mute,
+        FailureAction::Escalate,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureAction::SoftKick => "soft_kick",
+            FailureAction::TempBan => "temp_ban",
+            FailureAction::PermBan => "perm_ban",
+            FailureAction::Mute => "mute",
+            FailureAction::Escalate => "escalate",
+        }
+    }
+}
+
+impl FromStr for FailureAction {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "soft_kick" | "kick" => Ok(FailureAction::SoftKick),
+            "temp_ban" | "ban" => Ok(FailureAction::TempBan),
+            "perm_ban" | "permanent" | "permban" => Ok(FailureAction::PermBan),
+            "mute" | "restrict" => Ok(FailureAction::Mute),
+            "escalate" => Ok(FailureAction::Escalate),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Что делать, когда предупреждений набралось до порога.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarnAction {
+    /// Оставить в чате без права писать.
+    Mute,
+    /// Забанить навсегда.
+    Ban,
+}
+
+impl WarnAction {
+    pub const ALL: [WarnAction; 2] = [WarnAction::Mute, WarnAction::Ban];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarnAction::Mute => "mute",
+            WarnAction::Ban => "ban",
+        }
+    }
+}
+
+impl FromStr for WarnAction {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "mute" | "restrict" => Ok(WarnAction::Mute),
+            "ban" | "perm_ban" => Ok(WarnAction::Ban),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub captcha_timeout_secs: u64,
-    pub admin_id: UserId,
+    /// Глобальные супер-админы (ADMIN_USER_IDS и/или ADMIN_USER_ID).
+    pub admin_ids: Vec<UserId>,
+    /// Пускать администраторов чата в Telegram к управлению этим чатом.
+    pub delegate_chat_admins: bool,
+    /// Сколько кэшировать список админов чата.
+    pub admin_cache_ttl_secs: u64,
     pub kick_ban_minutes: i64,
+    pub failure_action: FailureAction,
+    /// Неверных ответов до провала (текстовые капчи); 0 — без лимита.
+    pub max_attempts: u32,
+    /// После неверного ответа выдавать новую задачу.
+    pub new_challenge_on_miss: bool,
     pub delete_unverified_messages: bool,
     pub captcha_mode: CaptchaMode,
+    /// Язык бота в чатах по умолчанию.
+    pub language: Lang,
+    /// Промпт капчи — на языке вступившего (`language_code`), если он известен.
+    pub captcha_user_language: bool,
+    /// Приветствовать прошедших капчу.
+    pub welcome_enabled: bool,
+    /// Через сколько секунд удалять приветствие; 0 — оставлять.
+    pub welcome_delete_secs: u64,
+    /// Через сколько секунд удалять уведомления о провале капчи; 0 — оставлять.
+    pub notice_delete_secs: u64,
+    /// Удалять сервисные «вступил/вышел» от Telegram.
+    pub delete_service_messages: bool,
+    /// Заявки на вступление (чаты с одобрением): капча в личке, затем
+    /// одобрить/отклонить. `false` (по умолчанию) — заявки остаются админам.
+    pub join_requests: bool,
+    /// Капча в личке: в чате — только кнопка-ссылка `?start=verify_<token>`.
+    pub private_captcha: bool,
+    /// Испытательный срок после капчи, часы (только текст); 0 — без него.
+    pub probation_hours: u64,
+    /// Испытательный срок заканчивается раньше после стольких сообщений; 0 — только по времени.
+    /// Сам по себе срок не включает: нужен `probation_hours`.
+    pub probation_messages: u32,
+    /// Предупреждений до наказания; 0 — только считать.
+    pub warn_limit: u32,
+    pub warn_action: WarnAction,
+    /// Через сколько часов предупреждение сгорает; 0 — никогда.
+    pub warn_expire_hours: u64,
+    /// `Some` — принимаем апдейты вебхуком, `None` — long-polling.
+    pub webhook: Option<WebhookConfig>,
+    /// Адрес для `GET /metrics`. Без него метрики не отдаются.
+    pub metrics_listen: Option<SocketAddr>,
+    pub raid: RaidConfig,
+}
+
+/// Параметры детектора рейдов (см. `raid.rs`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RaidConfig {
+    /// Сколько вступлений за окно считаем рейдом; 0 — детектор выключен.
+    pub join_threshold: usize,
+    pub window_secs: u64,
+    /// Сколько держать блокировку после последней волны.
+    pub cooldown_secs: u64,
+    /// Капча на время блокировки.
+    pub captcha_mode: CaptchaMode,
+    /// Бан за провал капчи во время блокировки (не меньше обычного).
+    pub ban_minutes: i64,
+    /// Снять права на отправку у всех (`set_chat_permissions`).
+    pub restrict_chat: bool,
+}
+
+impl RaidConfig {
+    pub fn enabled(&self) -> bool {
+        self.join_threshold > 0
+    }
+
+    /// Ужесточить эффективные настройки чата на время блокировки.
+    pub fn apply(&self, s: &mut Settings) {
+        s.captcha_mode = self.captcha_mode;
+        s.kick_ban_minutes = s.kick_ban_minutes.max(self.ban_minutes);
+        if s.failure_action == FailureAction::SoftKick {
+            s.failure_action = FailureAction::TempBan;
+        }
+    }
+}
+
+impl RaidConfig {
+    fn from_env() -> Self {
+        let num = |k: &str, d: u64| {
+            std::env::var(k)
+                .ok()
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .unwrap_or(d)
+        };
+        Self {
+            join_threshold: num("RAID_JOIN_THRESHOLD", 0) as usize,
+            window_secs: num("RAID_WINDOW_SEC", 60),
+            cooldown_secs: num("RAID_COOLDOWN_MIN", 15) * 60,
+            captcha_mode: std::env::var("RAID_CAPTCHA_MODE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(CaptchaMode::Image),
+            ban_minutes: num("RAID_BAN_MINUTES", 1440) as i64,
+            restrict_chat: std::env::var("RAID_RESTRICT_CHAT")
+                .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Настройки вебхука (`WEBHOOK_URL` включает режим).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// Где слушать HTTP (за reverse proxy обычно 127.0.0.1:8080).
+    pub listen: SocketAddr,
+    /// Публичный URL, который получит Telegram в `setWebhook`.
+    pub url: Url,
+    /// Локальный путь, если прокси его переписывает; иначе путь из `url`.
+    pub path: Option<String>,
+    /// Значение заголовка `X-Telegram-Bot-Api-Secret-Token`;
+    /// `None` — teloxide сгенерирует случайный на каждый запуск.
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    pub fn parse(
+        url: &str,
+        listen: Option<&str>,
+        path: Option<&str>,
+        secret: Option<&str>,
+    ) -> Result<Self, String> {
+        let url = Url::parse(url.trim()).map_err(|e| format!("WEBHOOK_URL: {e}"))?;
+        let listen = listen
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or("0.0.0.0:8080")
+            .parse()
+            .map_err(|e| format!("WEBHOOK_LISTEN: {e}"))?;
+        let path = path.map(str::trim).filter(|s| !s.is_empty()).map(|p| {
+            if p.starts_with('/') {
+                p.to_string()
+            } else {
+                format!("/{p}")
+            }
+        });
+        let secret = secret.map(str::trim).filter(|s| !s.is_empty());
+        if let Some(s) = secret {
+            // Ограничения Telegram: 1-256 символов A-Z a-z 0-9 _ -
+            let ok = s.len() <= 256
+                && s.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+            if !ok {
+                return Err("WEBHOOK_SECRET: 1-256 chars of A-Z, a-z, 0-9, _ and -".into());
+            }
+        }
+        Ok(Self {
+            listen,
+            url,
+            path,
+            secret: secret.map(str::to_string),
+        })
+    }
+
+    fn from_env() -> Option<Self> {
+        let url = std::env::var("WEBHOOK_URL")
+            .ok()
+            .filter(|s| !s.trim().is_empty())?;
+        let var = |k| std::env::var(k).ok();
+        match Self::parse(
+            &url,
+            var("WEBHOOK_LISTEN").as_deref(),
+            var("WEBHOOK_PATH").as_deref(),
+            var("WEBHOOK_SECRET").as_deref(),
+        ) {
+            Ok(wh) => Some(wh),
+            Err(e) => panic!("Invalid webhook config: {e}"),
+        }
+    }
 }
 
 impl Config {
@@ -39,17 +321,51 @@ impl Config {
             .and_then(|s| s.parse::<u64>().ok())
             .unwrap_or(60);
 
-        let admin_id = std::env::var("ADMIN_USER_ID")
+        let mut admin_ids = std::env::var("ADMIN_USER_IDS")
+            .map(|s| parse_id_list(&s))
+            .unwrap_or_default();
+        if let Some(id) = std::env::var("ADMIN_USER_ID")
             .ok()
-            .and_then(|s| s.parse::<i64>().ok())
-            .map(|id| UserId(id as u64))
-            .expect("Set ADMIN_USER_ID=<numeric Telegram user id> in .env");
+            .and_then(|s| s.trim().parse::<u64>().ok())
+        {
+            if !admin_ids.contains(&UserId(id)) {
+                admin_ids.push(UserId(id));
+            }
+        }
+        assert!(
+            !admin_ids.is_empty(),
+            "Set ADMIN_USER_IDS=<id,id,...> (or ADMIN_USER_ID=<id>) in .env"
+        );
+
+        let delegate_chat_admins = std::env::var("DELEGATE_CHAT_ADMINS")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
+        let admin_cache_ttl_secs = std::env::var("ADMIN_CACHE_TTL_SEC")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(300);
 
         let kick_ban_minutes = std::env::var("KICK_BAN_MINUTES")
             .ok()
             .and_then(|s| s.parse::<i64>().ok())
             .unwrap_or(0);
 
+        // Без FAILURE_ACTION — как раньше: бан на KICK_BAN_MINUTES, 0 — мягкий кик.
+        let failure_action = std::env::var("FAILURE_ACTION")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(FailureAction::TempBan);
+
+        let max_attempts = std::env::var("CAPTCHA_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+
+        let new_challenge_on_miss = std::env::var("CAPTCHA_NEW_ON_MISS")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
         let delete_unverified_messages = std::env::var("DELETE_UNVERIFIED_MESSAGES")
             .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
             .unwrap_or(false);
@@ -59,12 +375,415 @@ impl Config {
             .and_then(|s| s.parse().ok())
             .unwrap_or(CaptchaMode::Button);
 
+        let language = std::env::var("LANGUAGE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(Lang::Ru);
+
+        let captcha_user_language = std::env::var("CAPTCHA_USER_LANGUAGE")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
+        let welcome_enabled = std::env::var("WELCOME_ENABLED")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(true);
+
+        let welcome_delete_secs = std::env::var("WELCOME_DELETE_SEC")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+
+        let notice_delete_secs = std::env::var("NOTICE_DELETE_SEC")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+
+        let delete_service_messages = std::env::var("DELETE_SERVICE_MESSAGES")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
+        let join_requests = std::env::var("JOIN_REQUESTS")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
+        let private_captcha = std::env::var("CAPTCHA_IN_PRIVATE")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+            .unwrap_or(false);
+
+        let probation_hours = std::env::var("PROBATION_HOURS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+
+        let probation_messages = std::env::var("PROBATION_MESSAGES")
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+
+        let warn_limit = std::env::var("WARN_LIMIT")
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(3);
+
+        let warn_action = std::env::var("WARN_ACTION")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(WarnAction::Mute);
+
+        let warn_expire_hours = std::env::var("WARN_EXPIRE_HOURS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(168);
+
+        let raid = RaidConfig::from_env();
+        let webhook = WebhookConfig::from_env();
+
+        let metrics_listen = std::env::var("METRICS_LISTEN")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| {
+                s.trim()
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid METRICS_LISTEN {s:?}: {e}"))
+            });
+
         Self {
             captcha_timeout_secs,
-            admin_id,
+            admin_ids,
+            delegate_chat_admins,
+            admin_cache_ttl_secs,
             kick_ban_minutes,
+            failure_action,
+            max_attempts,
+            new_challenge_on_miss,
             delete_unverified_messages,
             captcha_mode,
+            language,
+            captcha_user_language,
+            welcome_enabled,
+            welcome_delete_secs,
+            notice_delete_secs,
+            delete_service_messages,
+            join_requests,
+            private_captcha,
+            probation_hours,
+            probation_messages,
+            warn_limit,
+            warn_action,
+            warn_expire_hours,
+            webhook,
+            metrics_listen,
+            raid,
+        }
+    }
+}
+
+impl Config {
+    #[inline]
+    pub fn is_super_admin(&self, id: UserId) -> bool {
+        self.admin_ids.contains(&id)
+    }
+}
+
+/// "1, 2 3;4" -> [1, 2, 3, 4]; мусор пропускаем.
+fn parse_id_list(s: &str) -> Vec<UserId> {
+    s.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter_map(|p| p.trim().parse::<u64>().ok())
+        .map(UserId)
+        .collect()
+}
+
+/// Переопределения настроек для конкретного чата (хранятся в state-файле).
+/// `None` — берём значение из глобального `Config`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_mode: Option<CaptchaMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kick_ban_minutes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_action: Option<FailureAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_challenge_on_miss: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_unverified_messages: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<Lang>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_user_language: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub welcome_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub welcome_delete_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notice_delete_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_service_messages: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_requests: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_captcha: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probation_hours: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probation_messages: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_action: Option<WarnAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_expire_hours: Option<u64>,
+    /// Шаблоны чата (уже прошли `templates::sanitize`); `None` — из каталога.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub welcome_template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_template: Option<String>,
+    /// Ссылка на правила для `{rules_link}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules_link: Option<String>,
+    /// Правила фильтра содержимого (`/filter`); пустые — фильтр выключен.
+    #[serde(skip_serializing_if = "FilterRules::is_empty")]
+    pub filter: FilterRules,
+}
+
+/// Итоговые (эффективные) настройки чата: переопределения поверх `Config`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub captcha_mode: CaptchaMode,
+    pub captcha_timeout_secs: u64,
+    pub kick_ban_minutes: i64,
+    pub failure_action: FailureAction,
+    pub max_attempts: u32,
+    pub new_challenge_on_miss: bool,
+    pub delete_unverified_messages: bool,
+    pub language: Lang,
+    pub captcha_user_language: bool,
+    pub welcome_enabled: bool,
+    pub welcome_delete_secs: u64,
+    pub notice_delete_secs: u64,
+    pub delete_service_messages: bool,
+    pub join_requests: bool,
+    pub private_captcha: bool,
+    pub probation_hours: u64,
+    pub probation_messages: u32,
+    pub warn_limit: u32,
+    pub warn_action: WarnAction,
+    pub warn_expire_hours: u64,
+    pub welcome_template: Option<String>,
+    pub captcha_template: Option<String>,
+    pub rules_link: Option<String>,
+    pub filter: FilterRules,
+}
+
+impl Settings {
+    /// Язык промпта капчи для `user`: его собственный, если так настроено
+    /// и он поддерживается, иначе язык чата.
+    pub fn prompt_lang(&self, user: &User) -> Lang {
+        if !self.captcha_user_language {
+            return self.language;
+        }
+        user.language_code
+            .as_deref()
+            .and_then(Lang::from_code)
+            .unwrap_or(self.language)
+    }
+}
+
+impl ChatSettings {
+    pub fn resolve(&self, cfg: &Config) -> Settings {
+        Settings {
+            captcha_mode: self.captcha_mode.unwrap_or(cfg.captcha_mode),
+            captcha_timeout_secs: self
+                .captcha_timeout_secs
+                .unwrap_or(cfg.captcha_timeout_secs),
+            kick_ban_minutes: self.kick_ban_minutes.unwrap_or(cfg.kick_ban_minutes),
+            failure_action: self.failure_action.unwrap_or(cfg.failure_action),
+            max_attempts: self.max_attempts.unwrap_or(cfg.max_attempts),
+            new_challenge_on_miss: self
+                .new_challenge_on_miss
+                .unwrap_or(cfg.new_challenge_on_miss),
+            delete_unverified_messages: self
+                .delete_unverified_messages
+                .unwrap_or(cfg.delete_unverified_messages),
+            language: self.language.unwrap_or(cfg.language),
+            captcha_user_language: self
+                .captcha_user_language
+                .unwrap_or(cfg.captcha_user_language),
+            welcome_enabled: self.welcome_enabled.unwrap_or(cfg.welcome_enabled),
+            welcome_delete_secs: self.welcome_delete_secs.unwrap_or(cfg.welcome_delete_secs),
+            notice_delete_secs: self.notice_delete_secs.unwrap_or(cfg.notice_delete_secs),
+            delete_service_messages: self
+                .delete_service_messages
+                .unwrap_or(cfg.delete_service_messages),
+            join_requests: self.join_requests.unwrap_or(cfg.join_requests),
+            private_captcha: self.private_captcha.unwrap_or(cfg.private_captcha),
+            probation_hours: self.probation_hours.unwrap_or(cfg.probation_hours),
+            probation_messages: self.probation_messages.unwrap_or(cfg.probation_messages),
+            warn_limit: self.warn_limit.unwrap_or(cfg.warn_limit),
+            warn_action: self.warn_action.unwrap_or(cfg.warn_action),
+            warn_expire_hours: self.warn_expire_hours.unwrap_or(cfg.warn_expire_hours),
+            welcome_template: self.welcome_template.clone(),
+            captcha_template: self.captcha_template.clone(),
+            rules_link: self.rules_link.clone(),
+            filter: self.filter.clone(),
+        }
+    }
+
+    /// Нет ни одного переопределения — запись можно не хранить.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Конфиг для тестов (вместо `from_env`); настройки чата в тестах —
+/// `ChatSettings::default().resolve(&test_config())`.
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config {
+        captcha_timeout_secs: 60,
+        admin_ids: vec![UserId(1)],
+        delegate_chat_admins: false,
+        admin_cache_ttl_secs: 300,
+        kick_ban_minutes: 0,
+        failure_action: FailureAction::TempBan,
+        max_attempts: 3,
+        new_challenge_on_miss: false,
+        delete_unverified_messages: false,
+        captcha_mode: CaptchaMode::Button,
+        language: Lang::Ru,
+        captcha_user_language: false,
+        welcome_enabled: true,
+        welcome_delete_secs: 0,
+        notice_delete_secs: 0,
+        delete_service_messages: false,
+        join_requests: true,
+        private_captcha: false,
+        probation_hours: 0,
+        probation_messages: 0,
+        warn_limit: 3,
+        warn_action: WarnAction::Mute,
+        warn_expire_hours: 168,
+        webhook: None,
+        metrics_listen: None,
+        raid: RaidConfig {
+            join_threshold: 0,
+            window_secs: 60,
+            cooldown_secs: 900,
+            captcha_mode: CaptchaMode::Image,
+            ban_minutes: 1440,
+            restrict_chat: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admin_id_list_parsing() {
+        assert_eq!(
+            parse_id_list("1, 2 3;x;4"),
+            vec![UserId(1), UserId(2), UserId(3), UserId(4)]
+        );
+        assert!(parse_id_list("").is_empty());
+        assert!(test_config().is_super_admin(UserId(1)));
+        assert!(!test_config().is_super_admin(UserId(2)));
+    }
+
+    #[test]
+    fn chat_settings_fall_back_to_config() {
+        let s = ChatSettings::default().resolve(&test_config());
+        assert_eq!(s.captcha_mode, CaptchaMode::Button);
+        assert_eq!(s.captcha_timeout_secs, 60);
+        assert!(ChatSettings::default().is_empty());
+    }
+
+    #[test]
+    fn chat_settings_override_config() {
+        let cs = ChatSettings {
+            captcha_mode: Some(CaptchaMode::Image),
+            kick_ban_minutes: Some(30),
+            failure_action: Some(FailureAction::Mute),
+            ..Default::default()
+        };
+        let s = cs.resolve(&test_config());
+        assert_eq!(s.captcha_mode, CaptchaMode::Image);
+        assert_eq!(s.kick_ban_minutes, 30);
+        assert_eq!(s.failure_action, FailureAction::Mute);
+        assert_eq!(s.captcha_timeout_secs, 60);
+        assert!(!cs.is_empty());
+    }
+
+    #[test]
+    fn captcha_prompt_language() {
+        let user = |code: Option<&str>| User {
+            id: UserId(7),
+            is_bot: false,
+            first_name: "A".into(),
+            last_name: None,
+            username: None,
+            language_code: code.map(Into::into),
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        let mut s = ChatSettings::default().resolve(&test_config());
+        assert_eq!(s.prompt_lang(&user(Some("en"))), Lang::Ru);
+        s.captcha_user_language = true;
+        assert_eq!(s.prompt_lang(&user(Some("en-US"))), Lang::En);
+        assert_eq!(s.prompt_lang(&user(Some("de"))), Lang::Ru);
+        assert_eq!(s.prompt_lang(&user(None)), Lang::Ru);
+    }
+
+    #[test]
+    fn failure_action_parse() {
+        assert_eq!("kick".parse(), Ok(FailureAction::SoftKick));
+        assert_eq!("TEMP_BAN".parse(), Ok(FailureAction::TempBan));
+        assert_eq!("permanent".parse(), Ok(FailureAction::PermBan));
+        assert_eq!("mute".parse(), Ok(FailureAction::Mute));
+        assert_eq!("escalate".parse(), Ok(FailureAction::Escalate));
+        assert_eq!("???".parse::<FailureAction>(), Err(()));
+        for a in FailureAction::ALL {
+            assert_eq!(a.as_str().parse(), Ok(a));
         }
     }
+
+    #[test]
+    fn chat_settings_json_roundtrip() {
+        let cs = ChatSettings {
+            captcha_mode: Some(CaptchaMode::Math2),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&cs).unwrap();
+        assert_eq!(json, r#"{"captcha_mode":"math2"}"#);
+        assert_eq!(serde_json::from_str::<ChatSettings>(&json).unwrap(), cs);
+    }
+
+    #[test]
+    fn webhook_config_parsing() {
+        let wh = WebhookConfig::parse("https://bot.example.com/tg", None, None, None).unwrap();
+        assert_eq!(wh.listen, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(wh.path, None);
+        assert_eq!(wh.secret, None);
+
+        let wh = WebhookConfig::parse(
+            "https://bot.example.com/tg",
+            Some("127.0.0.1:9000"),
+            Some("hook"),
+            Some("s3cr3t_-X"),
+        )
+        .unwrap();
+        assert_eq!(wh.listen.port(), 9000);
+        assert_eq!(wh.path.as_deref(), Some("/hook"));
+        assert_eq!(wh.secret.as_deref(), Some("s3cr3t_-X"));
+
+        assert!(WebhookConfig::parse("not a url", None, None, None).is_err());
+        assert!(WebhookConfig::parse("https://x.y/", Some("nope"), None, None).is_err());
+        assert!(WebhookConfig::parse("https://x.y/", None, None, Some("bad token!")).is_err());
+    }
 }
@@ -0,0 +1,844 @@
+// src/filter.rs
+
+//! Фильтр содержимого для тех, кто уже прошёл капчу: кнопку спамер жмёт
+//! руками, а рекламу шлёт потом. Правила — свои у каждого чата (`/filter`):
+//! слова и регулярки, ссылки и домены, приглашения в другие чаты, пересылки
+//! из каналов сверх лимита. У правила своё действие — удалить, предупредить,
+//! замутить, забанить; каждое срабатывание пишется в журнал (`filter_hit`).
+//!
+//! Администраторов чата, супер-админов и whitelist фильтр не трогает.
+//! Счётчик пересылок живёт в памяти (после рестарта — с нуля).
+
+use crate::admins;
+use crate::api;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::captcha::{apply_punishment, Punishment};
+use crate::commands::record_settings;
+use crate::i18n::{tr, Lang};
+use crate::scheduler::delete_later;
+use crate::state::AppState;
+use crate::utils::mention;
+use crate::warns;
+use anyhow::Result;
+use dashmap::DashMap;
+use log::debug;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{MessageEntityKind, MessageEntityRef, MessageOrigin, ParseMode, User};
+use teloxide::utils::html::escape;
+use url::Url;
+
+/// Окно лимита пересылок из каналов.
+const FORWARD_WINDOW: Duration = Duration::from_secs(3600);
+/// Правил в одном чате.
+const MAX_RULES: usize = 50;
+/// Длина шаблона правила, символов.
+const MAX_PATTERN: usize = 200;
+/// Предел размера скомпилированной регулярки (защита от тяжёлых шаблонов).
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Что сделать с нарушителем. Порядок — по строгости: из нескольких
+/// сработавших правил берём самое строгое.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Молча удалить сообщение.
+    Delete,
+    /// Удалить и выдать предупреждение (`warns`).
+    Warn,
+    /// Удалить и оставить без права писать.
+    Mute,
+    /// Удалить и забанить навсегда.
+    Ban,
+}
+
+impl FilterAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FilterAction::Delete => "delete",
+            FilterAction::Warn => "warn",
+            FilterAction::Mute => "mute",
+            FilterAction::Ban => "ban",
+        }
+    }
+}
+
+impl FromStr for FilterAction {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "delete" | "del" | "remove" => Ok(FilterAction::Delete),
+            "warn" => Ok(FilterAction::Warn),
+            "mute" | "restrict" => Ok(FilterAction::Mute),
+            "ban" => Ok(FilterAction::Ban),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Вид правила из списка.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// Подстрока текста, без учёта регистра.
+    Word,
+    /// Регулярное выражение по тексту, без учёта регистра.
+    Regex,
+    /// Подстрока адреса любой ссылки сообщения.
+    Link,
+    /// Домен ссылки (вместе с поддоменами).
+    Domain,
+}
+
+impl RuleKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleKind::Word => "word",
+            RuleKind::Regex => "regex",
+            RuleKind::Link => "link",
+            RuleKind::Domain => "domain",
+        }
+    }
+}
+
+impl FromStr for RuleKind {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "word" | "keyword" => Ok(RuleKind::Word),
+            "regex" | "re" => Ok(RuleKind::Regex),
+            "link" | "url" => Ok(RuleKind::Link),
+            "domain" => Ok(RuleKind::Domain),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub kind: RuleKind,
+    /// Для `word`/`link`/`domain` — уже в нижнем регистре.
+    pub pattern: String,
+    pub action: FilterAction,
+}
+
+/// Больше `limit` пересылок из каналов за час — действие (0 — любая).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwardLimit {
+    pub limit: u32,
+    pub action: FilterAction,
+}
+
+/// Правила чата (хранятся в `ChatSettings::filter`).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterRules {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// Приглашения в другие чаты (`t.me/+…`, `t.me/joinchat/…`); `None` — не проверять.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invites: Option<FilterAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwards: Option<ForwardLimit>,
+}
+
+impl FilterRules {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Что проверяем в сообщении.
+#[derive(Debug, Default)]
+struct Content {
+    /// Текст или подпись, в нижнем регистре.
+    text: String,
+    /// Ссылки: видимые и спрятанные под текстом.
+    urls: Vec<Url>,
+    /// Переслано из канала.
+    from_channel: bool,
+}
+
+impl Content {
+    fn of(msg: &Message) -> Self {
+        let text = msg.text().or(msg.caption()).unwrap_or_default();
+        let entities = msg
+            .parse_entities()
+            .or_else(|| msg.parse_caption_entities())
+            .unwrap_or_default();
+        Self {
+            text: text.to_lowercase(),
+            urls: entities.iter().filter_map(entity_url).collect(),
+            from_channel: matches!(msg.forward_origin(), Some(MessageOrigin::Channel { .. })),
+        }
+    }
+}
+
+/// Ссылка из entity: `example.com/x` без схемы тоже считается.
+fn entity_url(e: &MessageEntityRef<'_>) -> Option<Url> {
+    match e.kind() {
+        MessageEntityKind::TextLink { url } => Some(url.clone()),
+        MessageEntityKind::Url => {
+            let t = e.text();
+            Url::parse(t)
+                .ok()
+                .filter(|u| u.has_host())
+                .or_else(|| Url::parse(&format!("http://{t}")).ok())
+        }
+        _ => None,
+    }
+}
+
+/// Ссылка-приглашение в чат Telegram. `t.me/+<цифры>` — номер телефона, не она.
+fn is_invite(url: &Url) -> bool {
+    if url.scheme() == "tg" {
+        return url.host_str() == Some("join");
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    if !matches!(host, "t.me" | "telegram.me" | "telegram.dog") {
+        return false;
+    }
+    let path = url.path();
+    if path.starts_with("/joinchat/") {
+        return true;
+    }
+    path.strip_prefix("/+")
+        .is_some_and(|h| !h.is_empty() && !h.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn host_matches(url: &Url, domain: &str) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_ascii_lowercase();
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Регулярка правила: без учёта регистра, с ограничением размера.
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// Сработавшее правило.
+#[derive(Debug, PartialEq, Eq)]
+struct Hit {
+    /// Вид (метка в `/metrics`): вид правила, `invite` или `forward`.
+    rule: &'static str,
+    /// Что именно совпало — в журнал.
+    detail: String,
+    action: FilterAction,
+}
+
+/// Состояние фильтра в памяти: скомпилированные регулярки и счёт пересылок.
+pub struct FilterGuard {
+    /// `None` — шаблон не компилируется (правило молчит).
+    regexes: DashMap<String, Option<Regex>>,
+    forwards: DashMap<(ChatId, u64), VecDeque<Instant>>,
+    /// Когда последний раз выметали устаревшие очереди пересылок.
+    swept: Mutex<Instant>,
+}
+
+impl Default for FilterGuard {
+    fn default() -> Self {
+        Self {
+            regexes: DashMap::new(),
+            forwards: DashMap::new(),
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl FilterGuard {
+    fn regex(&self, pattern: &str) -> Option<Regex> {
+        if let Some(re) = self.regexes.get(pattern) {
+            return re.clone();
+        }
+        let re = compile(pattern).ok();
+        self.regexes.insert(pattern.to_string(), re.clone());
+        re
+    }
+
+    /// Забыть регулярки, которых больше нет ни в одном правиле.
+    fn retain_regexes(&self, used: &HashSet<String>) {
+        self.regexes.retain(|p, _| used.contains(p));
+    }
+
+    /// Раз в окно выкинуть очереди, где всё старше окна.
+    fn sweep_forwards(&self, now: Instant) {
+        {
+            let mut swept = self.swept.lock().expect("filter sweep lock");
+            if now.saturating_duration_since(*swept) < FORWARD_WINDOW {
+                return;
+            }
+            *swept = now;
+        }
+        self.forwards.retain(|_, q| {
+            q.back()
+                .is_some_and(|t| now.saturating_duration_since(*t) <= FORWARD_WINDOW)
+        });
+    }
+
+    /// Учесть пересылку из канала; сколько их в окне вместе с этой.
+    fn count_forward(&self, key: (ChatId, u64), now: Instant) -> usize {
+        self.sweep_forwards(now);
+        let mut q = self.forwards.entry(key).or_default();
+        while q
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > FORWARD_WINDOW)
+        {
+            q.pop_front();
+        }
+        q.push_back(now);
+        q.len()
+    }
+
+    /// Самое строгое из сработавших правил.
+    fn check(
+        &self,
+        rules: &FilterRules,
+        c: &Content,
+        key: (ChatId, u64),
+        now: Instant,
+    ) -> Option<Hit> {
+        let mut hits = Vec::new();
+
+        if let Some(action) = rules.invites {
+            if let Some(u) = c.urls.iter().find(|u| is_invite(u)) {
+                hits.push(Hit {
+                    rule: "invite",
+                    detail: format!("invite link {u}"),
+                    action,
+                });
+            }
+        }
+        if let (Some(fl), true) = (rules.forwards, c.from_channel) {
+            let n = self.count_forward(key, now);
+            if n > fl.limit as usize {
+                hits.push(Hit {
+                    rule: "forward",
+                    detail: format!("{n} channel forwards per hour (limit {})", fl.limit),
+                    action: fl.action,
+                });
+            }
+        }
+        for (i, r) in rules.rules.iter().enumerate() {
+            let p = r.pattern.as_str();
+            let matched = match r.kind {
+                RuleKind::Word => c.text.contains(p),
+                RuleKind::Regex => self.regex(p).is_some_and(|re| re.is_match(&c.text)),
+                RuleKind::Link => c.urls.iter().any(|u| u.as_str().to_lowercase().contains(p)),
+                RuleKind::Domain => c.urls.iter().any(|u| host_matches(u, p)),
+            };
+            if matched {
+                hits.push(Hit {
+                    rule: r.kind.as_str(),
+                    detail: format!("rule #{} {} {p}", i + 1, r.kind.as_str()),
+                    action: r.action,
+                });
+            }
+        }
+
+        // при равной строгости — первое сработавшее
+        hits.into_iter().rev().max_by_key(|h| h.action)
+    }
+}
+
+/// Проверить сообщение участника по правилам чата (после учёта ожидающих
+/// капчу). `true` — сообщение удалено, дальше его не обрабатываем.
+pub async fn check_message(bot: &Bot, state: &AppState, msg: &Message) -> bool {
+    // Анонимные админы и посты привязанного канала — не участники.
+    if msg.chat.is_private() || msg.sender_chat.is_some() || msg.is_automatic_forward() {
+        return false;
+    }
+    let Some(from) = msg.from.as_ref() else {
+        return false;
+    };
+    if from.is_bot {
+        return false;
+    }
+    let chat = msg.chat.id;
+    let key = AppState::key(chat, from.id);
+    // Ещё не прошёл капчу — его сообщениями занимается капча.
+    if state.pending.contains_key(&key) {
+        return false;
+    }
+    let settings = state.settings(chat);
+    if settings.filter.is_empty() {
+        return false;
+    }
+    let content = Content::of(msg);
+    // Пересылки доверенных в лимит не идут: их проверяем до счёта.
+    let counts_forward = content.from_channel && settings.filter.forwards.is_some();
+    if counts_forward && is_trusted(bot, state, chat, from).await {
+        return false;
+    }
+    let Some(hit) = state
+        .filter
+        .check(&settings.filter, &content, key, Instant::now())
+    else {
+        return false;
+    };
+    // Иначе исключения проверяем только на срабатывании: это запрос к API.
+    if !counts_forward && is_trusted(bot, state, chat, from).await {
+        debug!(
+            "Filter hit ignored for trusted user: {} (chat={}, user={})",
+            hit.detail, chat.0, from.id.0
+        );
+        return false;
+    }
+
+    enforce(bot, state, msg, from.id, hit).await;
+    true
+}
+
+/// Участник из whitelist чата или модератор — фильтр его не трогает.
+async fn is_trusted(bot: &Bot, state: &AppState, chat: ChatId, from: &User) -> bool {
+    state.is_user_allowed(chat, from) || admins::is_moderator(bot, state, chat, from.id).await
+}
+
+async fn enforce(bot: &Bot, state: &AppState, msg: &Message, user: UserId, hit: Hit) {
+    let chat = msg.chat.id;
+    debug!(
+        "Filter hit: {} → {} (chat={}, user={})",
+        hit.detail,
+        hit.action.as_str(),
+        chat.0,
+        user.0
+    );
+    state.metrics.filter_hit(hit.rule);
+    state.record(AuditEntry::new(
+        chat,
+        None,
+        user.0.to_string(),
+        AuditAction::FilterHit,
+        format!("{} → {}", hit.detail, hit.action.as_str()),
+    ));
+
+    let delete = bot.delete_message(chat, msg.id);
+    if api::call(state, chat, "delete_message", delete)
+        .await
+        .is_ok()
+    {
+        state.metrics.messages_deleted(1);
+    }
+
+    let reason = format!("filter: {}", hit.detail);
+    let settings = state.settings(chat);
+    let lang = settings.language;
+    let label = reason_label(lang, hit.rule);
+    let who = mention(bot, chat, user).await;
+    let text = match hit.action {
+        FilterAction::Delete => return,
+        // Предупреждение считается; на пороге наказывают уже предупреждения.
+        FilterAction::Warn => {
+            let out = warns::warn(bot, state, chat, user, None, &reason).await;
+            warns::notice(lang, &who, label, &out)
+        }
+        FilterAction::Mute => {
+            apply_punishment(bot, state, chat, user, Punishment::Mute, &reason).await;
+            tr!(lang, "filter.notice_mute", mention = who, reason = label)
+        }
+        FilterAction::Ban => {
+            apply_punishment(bot, state, chat, user, Punishment::Ban(None), &reason).await;
+            tr!(lang, "filter.notice_ban", mention = who, reason = label)
+        }
+    };
+    let notice = bot.send_message(chat, text).parse_mode(ParseMode::Html);
+    if let Ok(m) = api::call(state, chat, "send_message", notice).await {
+        delete_later(state, chat, m.id, settings.notice_delete_secs);
+    }
+}
+
+/// Причина для участников — без самого шаблона, чтобы его не обходили.
+fn reason_label(lang: Lang, rule: &str) -> &'static str {
+    match rule {
+        "invite" => tr!(lang, "filter.reason_invite"),
+        "forward" => tr!(lang, "filter.reason_forward"),
+        "link" | "domain" => tr!(lang, "filter.reason_link"),
+        _ => tr!(lang, "filter.reason_word"),
+    }
+}
+
+/* ======================== /filter ======================== */
+
+const USAGE: &str = "<code>/filter [add &lt;word|regex|link|domain&gt; &lt;delete|warn|mute|ban&gt; &lt;pattern&gt; | del &lt;n&gt; | invites &lt;action|off&gt; | forwards &lt;n&gt; &lt;action&gt; | forwards off | clear]</code>";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Cmd {
+    Show,
+    Add(Rule),
+    Remove(usize),
+    Invites(Option<FilterAction>),
+    Forwards(Option<ForwardLimit>),
+    Clear,
+}
+
+/// Разбор аргументов `/filter`; `None` — показать подсказку.
+fn parse_cmd(arg: Option<&str>) -> Option<Cmd> {
+    let Some(arg) = arg else {
+        return Some(Cmd::Show);
+    };
+    let (sub, rest) = match arg.split_once(char::is_whitespace) {
+        Some((s, r)) => (s, r.trim()),
+        None => (arg, ""),
+    };
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    match (sub.to_ascii_lowercase().as_str(), words.as_slice()) {
+        ("add", [kind, action, ..]) => {
+            let kind: RuleKind = kind.parse().ok()?;
+            let action = action.parse().ok()?;
+            // шаблон — остаток строки как есть (в регулярке важны пробелы)
+            let pattern = rest.splitn(3, char::is_whitespace).nth(2)?.trim();
+            let pattern = match kind {
+                RuleKind::Regex => pattern.to_string(),
+                RuleKind::Domain => {
+                    let d = pattern.to_lowercase();
+                    d.trim_start_matches("*.")
+                        .trim_start_matches('.')
+                        .to_string()
+                }
+                RuleKind::Word | RuleKind::Link => pattern.to_lowercase(),
+            };
+            (!pattern.is_empty()).then_some(Cmd::Add(Rule {
+                kind,
+                pattern,
+                action,
+            }))
+        }
+        ("del" | "rm" | "remove", [n]) => n.parse().ok().filter(|n| *n > 0).map(Cmd::Remove),
+        ("invites", ["off"]) => Some(Cmd::Invites(None)),
+        ("invites", [action]) => Some(Cmd::Invites(Some(action.parse().ok()?))),
+        ("forwards", ["off"]) => Some(Cmd::Forwards(None)),
+        ("forwards", [n, action]) => Some(Cmd::Forwards(Some(ForwardLimit {
+            limit: n.parse().ok()?,
+            action: action.parse().ok()?,
+        }))),
+        ("clear", []) => Some(Cmd::Clear),
+        _ => None,
+    }
+}
+
+/// `/filter …` — правила текущего чата.
+pub async fn command(
+    bot: &Bot,
+    state: &AppState,
+    msg: &Message,
+    lang: Lang,
+    arg: Option<&str>,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let Some(cmd) = parse_cmd(arg) else {
+        bot.send_message(chat, tr!(lang, "commands.usage", syntax = USAGE))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        return Ok(());
+    };
+    let rules = state.settings(chat).filter;
+
+    let (reply, change) = match cmd {
+        Cmd::Show => (render(lang, &rules), None),
+        Cmd::Add(rule) => {
+            if rules.rules.len() >= MAX_RULES {
+                (tr!(lang, "filter.too_many", max = MAX_RULES), None)
+            } else if rule.pattern.chars().count() > MAX_PATTERN {
+                (tr!(lang, "filter.too_long", max = MAX_PATTERN), None)
+            } else if let Err(e) = (rule.kind == RuleKind::Regex)
+                .then(|| compile(&rule.pattern))
+                .transpose()
+            {
+                (
+                    tr!(
+                        lang,
+                        "filter.bad_regex",
+                        error = format!("<pre>{}</pre>", escape(&e.to_string()))
+                    ),
+                    None,
+                )
+            } else {
+                let change = format!(
+                    "filter add {} {} {}",
+                    rule.kind.as_str(),
+                    rule.action.as_str(),
+                    rule.pattern
+                );
+                state.update_chat_settings(chat, |cs| cs.filter.rules.push(rule));
+                (
+                    tr!(lang, "filter.added", n = rules.rules.len() + 1),
+                    Some(change),
+                )
+            }
+        }
+        Cmd::Remove(n) => match rules.rules.get(n - 1) {
+            Some(r) => {
+                let change = format!("filter del {} {}", r.kind.as_str(), r.pattern);
+                state.update_chat_settings(chat, |cs| {
+                    if n <= cs.filter.rules.len() {
+                        cs.filter.rules.remove(n - 1);
+                    }
+                });
+                (tr!(lang, "filter.removed", n = n), Some(change))
+            }
+            None => (tr!(lang, "filter.no_rule", n = n), None),
+        },
+        Cmd::Invites(action) => {
+            state.update_chat_settings(chat, |cs| cs.filter.invites = action);
+            (
+                tr!(lang, "filter.saved").to_string(),
+                Some(format!("filter invites={}", action_label(lang, action))),
+            )
+        }
+        Cmd::Forwards(fl) => {
+            state.update_chat_settings(chat, |cs| cs.filter.forwards = fl);
+            let value = match fl {
+                Some(fl) => format!("{}:{}", fl.limit, fl.action.as_str()),
+                None => "off".into(),
+            };
+            (
+                tr!(lang, "filter.saved").to_string(),
+                Some(format!("filter forwards={value}")),
+            )
+        }
+        Cmd::Clear => {
+            state.update_chat_settings(chat, |cs| cs.filter = FilterRules::default());
+            (
+                tr!(lang, "filter.cleared").to_string(),
+                Some("filter cleared".to_string()),
+            )
+        }
+    };
+
+    if let Some(change) = change {
+        record_settings(state, msg, change);
+        forget_unused_regexes(state);
+    }
+    bot.send_message(chat, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+/// Правила поменялись — выкинуть из кэша регулярки, что больше не нужны.
+fn forget_unused_regexes(state: &AppState) {
+    let used: HashSet<String> = state
+        .chat_settings
+        .iter()
+        .flat_map(|cs| {
+            cs.filter
+                .rules
+                .iter()
+                .filter(|r| r.kind == RuleKind::Regex)
+                .map(|r| r.pattern.clone())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    state.filter.retain_regexes(&used);
+}
+
+fn action_label(lang: Lang, a: Option<FilterAction>) -> &'static str {
+    match a {
+        Some(a) => a.as_str(),
+        None => tr!(lang, "settings.off"),
+    }
+}
+
+fn render(lang: Lang, rules: &FilterRules) -> String {
+    let mut lines = vec![format!("<b>{}</b>", tr!(lang, "filter.title"))];
+    if rules.rules.is_empty() {
+        lines.push(tr!(lang, "filter.no_rules").to_string());
+    }
+    for (i, r) in rules.rules.iter().enumerate() {
+        lines.push(format!(
+            "{}. {} <code>{}</code> → {}",
+            i + 1,
+            r.kind.as_str(),
+            escape(&r.pattern),
+            r.action.as_str()
+        ));
+    }
+    lines.push(tr!(
+        lang,
+        "filter.invites",
+        action = action_label(lang, rules.invites)
+    ));
+    lines.push(match rules.forwards {
+        Some(fl) => tr!(
+            lang,
+            "filter.forwards",
+            n = fl.limit,
+            action = fl.action.as_str()
+        ),
+        None => tr!(lang, "filter.forwards_off").to_string(),
+    });
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn rule(kind: RuleKind, pattern: &str, action: FilterAction) -> Rule {
+        Rule {
+            kind,
+            pattern: pattern.into(),
+            action,
+        }
+    }
+
+    #[test]
+    fn invite_links() {
+        assert!(is_invite(&url("https://t.me/+AbCdEf123")));
+        assert!(is_invite(&url("https://t.me/joinchat/AAAAAE")));
+        assert!(is_invite(&url("http://www.telegram.me/+xyz")));
+        assert!(is_invite(&url("tg://join?invite=AbC")));
+        assert!(!is_invite(&url("https://t.me/+79001234567")));
+        assert!(!is_invite(&url("https://t.me/rustlang")));
+        assert!(!is_invite(&url("https://example.com/+abc")));
+    }
+
+    #[test]
+    fn domain_matching() {
+        assert!(host_matches(&url("https://bit.ly/x"), "bit.ly"));
+        assert!(host_matches(&url("https://www.Casino.com/"), "casino.com"));
+        assert!(!host_matches(&url("https://notcasino.com/"), "casino.com"));
+    }
+
+    #[test]
+    fn strictest_hit_wins() {
+        let g = FilterGuard::default();
+        let rules = FilterRules {
+            rules: vec![
+                rule(RuleKind::Word, "free", FilterAction::Delete),
+                rule(RuleKind::Regex, r"cas[i1]no", FilterAction::Mute),
+                rule(RuleKind::Domain, "bit.ly", FilterAction::Warn),
+            ],
+            invites: Some(FilterAction::Ban),
+            forwards: None,
+        };
+        let key = (ChatId(-1), 7);
+        let now = Instant::now();
+        let c = |text: &str, urls: &[&str]| Content {
+            text: text.to_lowercase(),
+            urls: urls.iter().map(|u| url(u)).collect(),
+            from_channel: false,
+        };
+
+        assert_eq!(g.check(&rules, &c("hello", &[]), key, now), None);
+        let hit = g.check(&rules, &c("FREE cas1no", &[]), key, now).unwrap();
+        assert_eq!((hit.rule, hit.action), ("regex", FilterAction::Mute));
+        let hit = g
+            .check(&rules, &c("free", &["https://bit.ly/a"]), key, now)
+            .unwrap();
+        assert_eq!(hit.action, FilterAction::Warn);
+        let hit = g
+            .check(&rules, &c("join", &["https://t.me/+AbC"]), key, now)
+            .unwrap();
+        assert_eq!((hit.rule, hit.action), ("invite", FilterAction::Ban));
+    }
+
+    #[test]
+    fn channel_forward_limit() {
+        let g = FilterGuard::default();
+        let rules = FilterRules {
+            forwards: Some(ForwardLimit {
+                limit: 2,
+                action: FilterAction::Delete,
+            }),
+            ..Default::default()
+        };
+        let fwd = Content {
+            from_channel: true,
+            ..Default::default()
+        };
+        let key = (ChatId(-1), 7);
+        let t0 = Instant::now();
+        assert!(g.check(&rules, &fwd, key, t0).is_none());
+        assert!(g.check(&rules, &fwd, key, t0).is_none());
+        assert!(g.check(&rules, &fwd, key, t0).is_some());
+        // другой участник считается отдельно, старые вылетают из окна
+        assert!(g.check(&rules, &fwd, (ChatId(-1), 8), t0).is_none());
+        assert_eq!(g.forwards.len(), 2);
+        let later = t0 + FORWARD_WINDOW + Duration::from_secs(1);
+        assert!(g.check(&rules, &fwd, key, later).is_none());
+        // прошло окно: очередь участника 8 целиком устарела и выметена
+        assert_eq!(g.forwards.len(), 1);
+
+        g.regex("sp[a@]m");
+        g.retain_regexes(&HashSet::new());
+        assert!(g.regexes.is_empty());
+    }
+
+    #[test]
+    fn parse_filter_commands() {
+        assert_eq!(parse_cmd(None), Some(Cmd::Show));
+        assert_eq!(
+            parse_cmd(Some("add word ban Free  Money")),
+            Some(Cmd::Add(rule(
+                RuleKind::Word,
+                "free  money",
+                FilterAction::Ban
+            )))
+        );
+        assert_eq!(
+            parse_cmd(Some("add regex mute Cas[i1]no\\s+\\d+")),
+            Some(Cmd::Add(rule(
+                RuleKind::Regex,
+                "Cas[i1]no\\s+\\d+",
+                FilterAction::Mute
+            )))
+        );
+        assert_eq!(
+            parse_cmd(Some("add domain delete *.Bit.ly")),
+            Some(Cmd::Add(rule(
+                RuleKind::Domain,
+                "bit.ly",
+                FilterAction::Delete
+            )))
+        );
+        assert_eq!(parse_cmd(Some("del 2")), Some(Cmd::Remove(2)));
+        assert_eq!(
+            parse_cmd(Some("invites warn")),
+            Some(Cmd::Invites(Some(FilterAction::Warn)))
+        );
+        assert_eq!(parse_cmd(Some("invites off")), Some(Cmd::Invites(None)));
+        assert_eq!(
+            parse_cmd(Some("forwards 0 mute")),
+            Some(Cmd::Forwards(Some(ForwardLimit {
+                limit: 0,
+                action: FilterAction::Mute
+            })))
+        );
+        assert_eq!(parse_cmd(Some("clear")), Some(Cmd::Clear));
+        assert_eq!(parse_cmd(Some("add word kill")), None);
+        assert_eq!(parse_cmd(Some("add nope ban x")), None);
+        assert_eq!(parse_cmd(Some("del 0")), None);
+        assert_eq!(parse_cmd(Some("invites maybe")), None);
+    }
+
+    #[test]
+    fn rules_json_is_compact() {
+        let rules = FilterRules {
+            invites: Some(FilterAction::Delete),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&rules).unwrap();
+        assert_eq!(json, r#"{"invites":"delete"}"#);
+        assert_eq!(serde_json::from_str::<FilterRules>(&json).unwrap(), rules);
+        assert!(compile("(unclosed").is_err());
+    }
+}
@@ -1,30 +1,53 @@
-use crate::{captcha, commands, state::AppState};
+use crate::{api, captcha, commands, filter, probation, state::AppState};
 use anyhow::Result;
 use std::sync::Arc;
 use teloxide::prelude::*;
+use teloxide::types::ChatJoinRequest;
 
 pub async fn on_message(bot: Bot, state: Arc<AppState>, msg: Message) -> Result<()> {
+    let _t = state.metrics.time("message");
+
     // 1) трекаем сообщения тех, кто ждёт капчу
     if let (Some(from), chat) = (msg.from.as_ref(), &msg.chat) {
-        if let Some(mut pending) = state.pending.get_mut(&AppState::key(chat.id, from.id)) {
-            if msg.id.0 != 0 {
-                pending.user_message_ids.push(msg.id.0);
-            }
+        if msg.id.0 != 0 {
+            state.track_pending_message(&AppState::key(chat.id, from.id), msg.id.0);
         }
     }
 
-    // 2) fallback: сервисное сообщение о вступлении (вдруг приходит и в форуме)
+    // 2) фильтр содержимого для прошедших капчу; удалённое дальше не разбираем
+    if filter::check_message(&bot, &state, &msg).await {
+        return Ok(());
+    }
+    // 3) испытательный срок: новичкам пока только текст
+    if probation::check_message(&bot, &state, &msg).await {
+        return Ok(());
+    }
+
+    // 4) сервисные «вступил/вышел» — убрать, если чат так настроил
+    if (msg.new_chat_members().is_some() || msg.left_chat_member().is_some())
+        && state.settings(msg.chat.id).delete_service_messages
+    {
+        let delete = bot.delete_message(msg.chat.id, msg.id);
+        if api::call(&state, msg.chat.id, "delete_message", delete)
+            .await
+            .is_ok()
+        {
+            state.metrics.messages_deleted(1);
+        }
+    }
+
+    // 5) fallback: сервисное сообщение о вступлении (вдруг приходит и в форуме)
     if let Some(newbies) = msg.new_chat_members() {
         for user in newbies {
-            captcha::ask_captcha(&bot, state.clone(), msg.chat.id, &user).await?;
+            captcha::ask_captcha(&bot, state.clone(), msg.chat.id, user).await?;
         }
         return Ok(());
     }
 
-    // 3) дать шанс капче (math2) принять текстовый ответ
+    // 6) дать шанс капче (math2) принять текстовый ответ
     captcha::on_user_message(bot.clone(), state.clone(), &msg).await?;
 
-    // 4) команды
+    // 7) команды
     if let Some(text) = msg.text() {
         if text.starts_with('/') {
             commands::handle_command(&bot, state.clone(), &msg, text).await?;
@@ -38,17 +61,30 @@ pub async fn on_chat_member_update(
     state: Arc<AppState>,
     upd: ChatMemberUpdated,
 ) -> Result<()> {
+    let _t = state.metrics.time("chat_member");
+
+    // Кого-то повысили/понизили — список админов чата устарел.
+    if upd.old_chat_member.is_privileged() || upd.new_chat_member.is_privileged() {
+        state.admin_cache.invalidate(upd.chat.id);
+    }
+
     let became_present = upd.new_chat_member.is_present();
     let was_absent = !upd.old_chat_member.is_present();
 
     if became_present && was_absent {
         let chat_id = upd.chat.id;
         let user = &upd.new_chat_member.user;
-        captcha::ask_captcha(&bot, state, chat_id, user).await?;
+        captcha::ask_captcha(&bot, state.clone(), chat_id, user).await?;
     }
     Ok(())
 }
 
+/// Заявка на вступление в чат с одобрением новых участников.
+pub async fn on_join_request(bot: Bot, state: Arc<AppState>, req: ChatJoinRequest) -> Result<()> {
+    let _t = state.metrics.time("join_request");
+    captcha::on_join_request(&bot, state.clone(), &req).await
+}
+
 // --- Диагностика (опционально). Включать feature 'diag' при сборке, чтобы не тащить в релиз. ---
 
 #[cfg(feature = "diag")]
@@ -0,0 +1,183 @@
+// src/i18n.rs
+
+//! Локализация: каталоги строк `locales/{en,ru}.toml` вшиты в бинарник.
+//! Ключи — через точку по секциям (`captcha.welcome`), подстановки — `{name}`.
+//!
+//! Язык выбирается по чату (`LANGUAGE`, `/settings`); промпт капчи можно
+//! показывать на языке самого участника (`language_code` из Telegram).
+
+use crate::state::AppState;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use teloxide::types::{ChatId, User};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lang {
+    En,
+    Ru,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::Ru];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Ru => "ru",
+        }
+    }
+
+    /// По `language_code` Telegram (`ru`, `en-GB`, ...). Неизвестный — `None`.
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let base = code.split(['-', '_']).next().unwrap_or(code);
+        base.parse().ok()
+    }
+}
+
+impl FromStr for Lang {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "en" | "eng" | "english" => Ok(Lang::En),
+            "ru" | "rus" | "russian" => Ok(Lang::Ru),
+            _ => Err(()),
+        }
+    }
+}
+
+type Catalog = HashMap<String, String>;
+
+static CATALOGS: LazyLock<HashMap<Lang, Catalog>> = LazyLock::new(|| {
+    HashMap::from([
+        (Lang::En, parse_catalog(include_str!("locales/en.toml"))),
+        (Lang::Ru, parse_catalog(include_str!("locales/ru.toml"))),
+    ])
+});
+
+/// TOML -> плоская таблица `секция.ключ -> строка`.
+fn parse_catalog(src: &str) -> Catalog {
+    fn walk(prefix: &str, table: &toml::Table, out: &mut Catalog) {
+        for (k, v) in table {
+            let key = if prefix.is_empty() {
+                k.clone()
+            } else {
+                format!("{prefix}.{k}")
+            };
+            match v {
+                toml::Value::String(s) => {
+                    out.insert(key, s.clone());
+                }
+                toml::Value::Table(t) => walk(&key, t, out),
+                other => panic!("locale key {key}: expected string, got {other}"),
+            }
+        }
+    }
+    let table: toml::Table = src.parse().expect("embedded locale is valid TOML");
+    let mut out = Catalog::new();
+    walk("", &table, &mut out);
+    out
+}
+
+/// Строка по ключу; нет перевода — английская, нет и её — сам ключ.
+pub fn t(lang: Lang, key: &'static str) -> &'static str {
+    let found = CATALOGS
+        .get(&lang)
+        .and_then(|c| c.get(key))
+        .or_else(|| CATALOGS.get(&Lang::En).and_then(|c| c.get(key)));
+    match found {
+        Some(s) => s.as_str(),
+        None => {
+            warn!("Missing locale key: {key}");
+            key
+        }
+    }
+}
+
+/// Строка с подстановками `{name}`. Обычно через макрос `tr!`.
+pub fn format(lang: Lang, key: &'static str, args: &[(&str, &dyn Display)]) -> String {
+    let mut s = t(lang, key).to_string();
+    for (name, value) in args {
+        s = s.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+    s
+}
+
+/// `tr!(lang, "key")` -> `&'static str`;
+/// `tr!(lang, "key", name = value, ...)` -> `String`.
+macro_rules! tr {
+    ($lang:expr, $key:expr) => {
+        $crate::i18n::t($lang, $key)
+    };
+    ($lang:expr, $key:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        // Через `let`: временные `&dyn Display` не доживут до `.await` (Send).
+        let s = $crate::i18n::format(
+            $lang,
+            $key,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),+],
+        );
+        s
+    }};
+}
+pub(crate) use tr;
+
+/// Язык ответа в чате: в группе — настройка чата, в личке — язык
+/// пользователя (если он из поддерживаемых).
+pub fn chat_lang(state: &AppState, chat: ChatId, user: Option<&User>) -> Lang {
+    let default = state.settings(chat).language;
+    if !chat.is_user() {
+        return default;
+    }
+    user.and_then(|u| u.language_code.as_deref())
+        .and_then(Lang::from_code)
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(s: &str) -> BTreeSet<&str> {
+        s.split('{')
+            .skip(1)
+            .filter_map(|p| p.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    #[test]
+    fn catalogs_have_same_keys_and_placeholders() {
+        let en = &CATALOGS[&Lang::En];
+        let ru = &CATALOGS[&Lang::Ru];
+        let en_keys: BTreeSet<_> = en.keys().collect();
+        let ru_keys: BTreeSet<_> = ru.keys().collect();
+        assert_eq!(en_keys, ru_keys);
+        for (k, v) in en {
+            assert_eq!(placeholders(v), placeholders(&ru[k]), "placeholders of {k}");
+        }
+    }
+
+    #[test]
+    fn lookup_and_format() {
+        assert_eq!(tr!(Lang::En, "lang_name"), "English");
+        assert_eq!(tr!(Lang::Ru, "lang_name"), "Русский");
+        assert_eq!(
+            tr!(Lang::En, "captcha.welcome", mention = "@bob"),
+            "Welcome, @bob!"
+        );
+        assert_eq!(tr!(Lang::Ru, "settings.ban_days", n = 7), "бан 7д");
+        assert_eq!(tr!(Lang::En, "no.such.key"), "no.such.key");
+    }
+
+    #[test]
+    fn lang_from_telegram_code() {
+        assert_eq!(Lang::from_code("ru"), Some(Lang::Ru));
+        assert_eq!(Lang::from_code("en-GB"), Some(Lang::En));
+        assert_eq!(Lang::from_code("pt-br"), None);
+        assert_eq!("RU".parse::<Lang>(), Ok(Lang::Ru));
+    }
+}
@@ -1,10 +1,23 @@
+mod admins;
+mod api;
 mod app;
+mod audit;
 mod config;
+mod i18n;
+mod metrics;
+mod raid;
+mod scheduler;
 mod state;
+mod templates;
+mod storage;
 mod handlers;
 mod commands;
+mod settings_menu;
 mod utils;
 mod captcha;
+mod filter;
+mod probation;
+mod warns;
 
 use anyhow::Result;
 
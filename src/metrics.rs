@@ -0,0 +1,373 @@
+// src/metrics.rs
+
+//! Метрики в формате Prometheus (`GET /metrics`).
+//!
+//! Счётчики живут в `AppState::metrics`; HTTP отдаётся только отдельным
+//! сервером на `METRICS_LISTEN`. Метки — режим капчи, вид задачи, правило
+//! фильтра и метод API; id чатов и пользователей в метриках нет.
+
+use crate::scheduler::ScheduledJob;
+use crate::state::AppState;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use dashmap::DashMap;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Границы бакетов латентности хендлеров, секунды.
+const BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+type Labeled = DashMap<&'static str, u64>;
+
+#[derive(Default)]
+struct Histogram {
+    /// Не кумулятивно: `counts[i]` — попавшие в `(BUCKETS[i-1], BUCKETS[i]]`.
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    captchas_issued: Labeled,
+    captchas_passed: Labeled,
+    captchas_failed: Labeled,
+    bans: AtomicU64,
+    soft_kicks: AtomicU64,
+    mutes: AtomicU64,
+    bots_banned: AtomicU64,
+    messages_deleted: AtomicU64,
+    lockdowns: AtomicU64,
+    filter_hits: Labeled,
+    warnings: AtomicU64,
+    api_errors: Labeled,
+    latency: DashMap<&'static str, Histogram>,
+}
+
+impl Metrics {
+    pub fn captcha_issued(&self, mode: &'static str) {
+        *self.captchas_issued.entry(mode).or_default() += 1;
+    }
+
+    pub fn captcha_passed(&self, mode: &'static str) {
+        *self.captchas_passed.entry(mode).or_default() += 1;
+    }
+
+    pub fn captcha_failed(&self, mode: &'static str) {
+        *self.captchas_failed.entry(mode).or_default() += 1;
+    }
+
+    pub fn ban(&self) {
+        self.bans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn soft_kick(&self) {
+        self.soft_kicks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn mute(&self) {
+        self.mutes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bot_banned(&self) {
+        self.bots_banned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages_deleted(&self, n: u64) {
+        self.messages_deleted.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn lockdown(&self) {
+        self.lockdowns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Сработал фильтр содержимого; `rule` — вид правила.
+    pub fn filter_hit(&self, rule: &'static str) {
+        *self.filter_hits.entry(rule).or_default() += 1;
+    }
+
+    pub fn warning(&self) {
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Ошибка Telegram API; `method` — имя метода Bot API в snake_case.
+    pub fn api_error(&self, method: &'static str) {
+        *self.api_errors.entry(method).or_default() += 1;
+    }
+
+    /// Замер длительности хендлера: время пишется при drop.
+    pub fn time(&self, handler: &'static str) -> Timer<'_> {
+        Timer {
+            metrics: self,
+            handler,
+            started: Instant::now(),
+        }
+    }
+
+    fn observe(&self, handler: &'static str, secs: f64) {
+        let mut h = self.latency.entry(handler).or_default();
+        if let Some(i) = BUCKETS.iter().position(|b| secs <= *b) {
+            h.counts[i] += 1;
+        }
+        h.count += 1;
+        h.sum += secs;
+    }
+
+    /// Текст в exposition format. `pending` — текущий размер очереди капч,
+    /// `jobs` — отложенные задачи (`AppState::jobs`).
+    pub fn render(&self, pending: usize, jobs: &[ScheduledJob]) -> String {
+        let mut out = String::new();
+        labeled(
+            &mut out,
+            "ranger_captchas_issued_total",
+            "Captchas sent to new members",
+            "mode",
+            &self.captchas_issued,
+        );
+        labeled(
+            &mut out,
+            "ranger_captchas_passed_total",
+            "Captchas solved",
+            "mode",
+            &self.captchas_passed,
+        );
+        labeled(
+            &mut out,
+            "ranger_captchas_failed_total",
+            "Captchas failed (timeout or wrong answers)",
+            "mode",
+            &self.captchas_failed,
+        );
+        gauge(
+            &mut out,
+            "ranger_pending_captchas",
+            "Captchas waiting for an answer",
+            pending as u64,
+        );
+        let mut kinds = BTreeMap::new();
+        for sj in jobs {
+            *kinds.entry(sj.job.kind()).or_insert(0u64) += 1;
+        }
+        let _ = writeln!(
+            out,
+            "# HELP ranger_scheduled_jobs Delayed jobs waiting to run"
+        );
+        let _ = writeln!(out, "# TYPE ranger_scheduled_jobs gauge");
+        for (kind, n) in kinds {
+            let _ = writeln!(out, "ranger_scheduled_jobs{{kind=\"{kind}\"}} {n}");
+        }
+        counter(
+            &mut out,
+            "ranger_bans_total",
+            "Bans after a failed captcha",
+            &self.bans,
+        );
+        counter(
+            &mut out,
+            "ranger_soft_kicks_total",
+            "Soft kicks (ban+unban) after a failed captcha",
+            &self.soft_kicks,
+        );
+        counter(
+            &mut out,
+            "ranger_mutes_total",
+            "Members muted after a failed captcha",
+            &self.mutes,
+        );
+        counter(
+            &mut out,
+            "ranger_bots_banned_total",
+            "Bots banned for not being whitelisted",
+            &self.bots_banned,
+        );
+        counter(
+            &mut out,
+            "ranger_messages_deleted_total",
+            "Messages deleted by the bot",
+            &self.messages_deleted,
+        );
+        counter(
+            &mut out,
+            "ranger_lockdowns_total",
+            "Raid lockdowns started",
+            &self.lockdowns,
+        );
+        labeled(
+            &mut out,
+            "ranger_filter_hits_total",
+            "Messages caught by the content filter",
+            "rule",
+            &self.filter_hits,
+        );
+        counter(
+            &mut out,
+            "ranger_warnings_total",
+            "Warnings issued by admins and the content filter",
+            &self.warnings,
+        );
+        labeled(
+            &mut out,
+            "ranger_api_errors_total",
+            "Failed Telegram Bot API calls",
+            "method",
+            &self.api_errors,
+        );
+        self.render_latency(&mut out);
+        out
+    }
+
+    fn render_latency(&self, out: &mut String) {
+        let name = "ranger_handler_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Update handler latency");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut handlers: Vec<_> = self.latency.iter().map(|e| *e.key()).collect();
+        handlers.sort_unstable();
+        for handler in handlers {
+            let Some(h) = self.latency.get(handler) else {
+                continue;
+            };
+            let mut acc = 0;
+            for (b, n) in BUCKETS.iter().zip(h.counts) {
+                acc += n;
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{handler=\"{handler}\",le=\"{b}\"}} {acc}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{handler=\"{handler}\",le=\"+Inf\"}} {}",
+                h.count
+            );
+            let _ = writeln!(out, "{name}_sum{{handler=\"{handler}\"}} {}", h.sum);
+            let _ = writeln!(out, "{name}_count{{handler=\"{handler}\"}} {}", h.count);
+        }
+    }
+}
+
+pub struct Timer<'a> {
+    metrics: &'a Metrics,
+    handler: &'static str,
+    started: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .observe(self.handler, self.started.elapsed().as_secs_f64());
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, v: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", v.load(Ordering::Relaxed));
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, v: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {v}");
+}
+
+fn labeled(out: &mut String, name: &str, help: &str, label: &str, values: &Labeled) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let mut rows: Vec<(&str, u64)> = values.iter().map(|e| (*e.key(), *e.value())).collect();
+    rows.sort_unstable();
+    for (k, v) in rows {
+        let _ = writeln!(out, "{name}{{{label}=\"{k}\"}} {v}");
+    }
+}
+
+/// Роутер с одним `GET /metrics`.
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(state)
+}
+
+async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state
+            .metrics
+            .render(state.pending.len(), &state.jobs.list()),
+    )
+}
+
+/// Отдельный HTTP-сервер для метрик (`METRICS_LISTEN`).
+pub fn serve(addr: SocketAddr, state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("Metrics: cannot bind {addr}: {e}");
+                return;
+            }
+        };
+        info!("Metrics on http://{addr}/metrics");
+        if let Err(e) = axum::serve(listener, router(state)).await {
+            error!("Metrics server error: {e}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_labels() {
+        let m = Metrics::default();
+        m.captcha_issued("button");
+        m.captcha_issued("button");
+        m.captcha_failed("image");
+        m.ban();
+        m.messages_deleted(3);
+        m.api_error("ban_chat_member");
+
+        let job = ScheduledJob::new(
+            chrono::Utc::now(),
+            crate::scheduler::Job::LockdownExpiry {
+                chat_id: -1,
+                saved_permissions: None,
+            },
+        );
+        let text = m.render(2, &[job]);
+        assert!(text.contains("ranger_captchas_issued_total{mode=\"button\"} 2"));
+        assert!(text.contains("ranger_captchas_failed_total{mode=\"image\"} 1"));
+        assert!(text.contains("ranger_pending_captchas 2"));
+        assert!(text.contains("ranger_scheduled_jobs{kind=\"lockdown_expiry\"} 1"));
+        assert!(text.contains("ranger_bans_total 1"));
+        assert!(text.contains("ranger_messages_deleted_total 3"));
+        assert!(text.contains("ranger_api_errors_total{method=\"ban_chat_member\"} 1"));
+        assert!(text.contains("# TYPE ranger_soft_kicks_total counter"));
+    }
+
+    #[test]
+    fn latency_histogram_is_cumulative() {
+        let m = Metrics::default();
+        m.observe("message", 0.003);
+        m.observe("message", 0.2);
+        m.observe("message", 60.0);
+
+        let text = m.render(0, &[]);
+        let name = "ranger_handler_duration_seconds";
+        assert!(text.contains(&format!(
+            "{name}_bucket{{handler=\"message\",le=\"0.005\"}} 1"
+        )));
+        assert!(text.contains(&format!(
+            "{name}_bucket{{handler=\"message\",le=\"0.25\"}} 2"
+        )));
+        assert!(text.contains(&format!("{name}_bucket{{handler=\"message\",le=\"5\"}} 2")));
+        assert!(text.contains(&format!(
+            "{name}_bucket{{handler=\"message\",le=\"+Inf\"}} 3"
+        )));
+        assert!(text.contains(&format!("{name}_count{{handler=\"message\"}} 3")));
+    }
+}
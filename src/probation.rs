@@ -0,0 +1,218 @@
+// src/probation.rs
+
+//! Испытательный срок после капчи: первые `PROBATION_HOURS` часов (или
+//! `PROBATION_MESSAGES` сообщений, что наступит раньше) новичок пишет только
+//! текст. Права урезаны до `SEND_MESSAGES`; ссылки и пересылки правами не
+//! запретить, поэтому такие сообщения (и всё, что проскочило) удаляются.
+//!
+//! Отдельного хранилища нет: запись о сроке — задача `ProbationEnd` в
+//! планировщике. Она переживает рестарт и по сроку возвращает полные права.
+//! Счёт сообщений — в памяти (`AppState::probation_sent`); после рестарта он
+//! начинается заново.
+
+use crate::api;
+use crate::captcha::member_permissions;
+use crate::i18n::tr;
+use crate::scheduler::{delete_later, probation_key, Job, ScheduledJob};
+use crate::state::AppState;
+use crate::utils::mention;
+use chrono::{Duration as ChronoDuration, Utc};
+use dashmap::DashMap;
+use log::{debug, info};
+use teloxide::prelude::*;
+use teloxide::types::{ChatPermissions, MessageEntityKind, MessageKind, ParseMode};
+use teloxide::RequestError;
+
+/// Начать испытательный срок, если он включён в чате. `false` — выключен
+/// (или урезать права не вышло), права выдаёт вызывающий.
+pub async fn start(bot: &Bot, state: &AppState, chat: ChatId, user: UserId) -> bool {
+    let s = state.settings(chat);
+    // Без часов срока нет: `PROBATION_MESSAGES` лишь заканчивает его раньше.
+    if s.probation_hours == 0 {
+        return false;
+    }
+    let restrict = bot.restrict_chat_member(chat, user, ChatPermissions::SEND_MESSAGES);
+    if api::moderate(
+        state,
+        chat,
+        user.0.to_string(),
+        "restrict_chat_member",
+        restrict,
+    )
+    .await
+    .is_err()
+    {
+        return false;
+    }
+    state.schedule(
+        Utc::now() + ChronoDuration::hours(s.probation_hours as i64),
+        Job::ProbationEnd {
+            chat_id: chat.0,
+            user: user.0,
+            messages_left: (s.probation_messages > 0).then_some(s.probation_messages),
+        },
+    );
+    debug!(
+        "Probation started: {} h, {} messages (chat={}, user={})",
+        s.probation_hours, s.probation_messages, chat.0, user.0
+    );
+    true
+}
+
+/// Срок вышел (или набралось сообщений) — вернуть полные права.
+pub async fn finish(
+    bot: &Bot,
+    state: &AppState,
+    chat: ChatId,
+    user: UserId,
+) -> Result<(), RequestError> {
+    info!("Probation over (chat={}, user={})", chat.0, user.0);
+    state.probation_sent.remove(&(chat, user.0));
+    let restrict = bot.restrict_chat_member(chat, user, member_permissions());
+    api::moderate(
+        state,
+        chat,
+        user.0.to_string(),
+        "restrict_chat_member",
+        restrict,
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Сообщение участника на испытательном сроке: ссылки, медиа и пересылки
+/// удаляем (`true` — удалено), текст засчитываем в досрочный конец срока.
+pub async fn check_message(bot: &Bot, state: &AppState, msg: &Message) -> bool {
+    // Сервисные сообщения (вступил/вышел и т.п.) не трогаем.
+    if !matches!(msg.kind, MessageKind::Common(_)) || msg.sender_chat.is_some() {
+        return false;
+    }
+    let Some(from) = msg.from.as_ref() else {
+        return false;
+    };
+    let chat = msg.chat.id;
+    let key = probation_key(chat, from.id.0);
+    let Some(sj) = state.jobs.get(&key) else {
+        return false;
+    };
+    let Job::ProbationEnd { messages_left, .. } = sj.job else {
+        return false;
+    };
+
+    if !is_plain_text(msg) {
+        debug!(
+            "Probation: deleting non-text message (chat={}, user={})",
+            chat.0, from.id.0
+        );
+        let delete = bot.delete_message(chat, msg.id);
+        if api::call(state, chat, "delete_message", delete)
+            .await
+            .is_ok()
+        {
+            state.metrics.messages_deleted(1);
+        }
+        let settings = state.settings(chat);
+        let who = mention(bot, chat, from.id).await;
+        let text = tr!(settings.language, "probation.notice", mention = who);
+        let notice = bot.send_message(chat, text).parse_mode(ParseMode::Html);
+        if let Ok(m) = api::call(state, chat, "send_message", notice).await {
+            delete_later(state, chat, m.id, settings.notice_delete_secs);
+        }
+        return true;
+    }
+
+    let Some(limit) = messages_left else {
+        return false;
+    };
+    if !count_message(&state.probation_sent, (chat, from.id.0), limit) {
+        return false;
+    }
+    // Последнее сообщение — срок переносим на «сейчас»: права вернёт
+    // планировщик, с его повторами при сбое.
+    state.put_job(ScheduledJob {
+        at: Utc::now(),
+        job: Job::ProbationEnd {
+            chat_id: chat.0,
+            user: from.id.0,
+            messages_left: None,
+        },
+        ..sj
+    });
+    false
+}
+
+/// Засчитать сообщение; `true` — набралось `limit`, счёт сброшен.
+fn count_message(sent: &DashMap<(ChatId, u64), u32>, key: (ChatId, u64), limit: u32) -> bool {
+    let mut n = sent.entry(key).or_insert(0);
+    *n += 1;
+    if *n < limit {
+        return false;
+    }
+    drop(n);
+    sent.remove(&key);
+    true
+}
+
+/// Только текст: без медиа, пересылок и ссылок (в том числе спрятанных под текстом).
+fn is_plain_text(msg: &Message) -> bool {
+    let Some(entities) = msg.parse_entities() else {
+        return false;
+    };
+    msg.forward_origin().is_none()
+        && !entities.iter().any(|e| {
+            matches!(
+                e.kind(),
+                MessageEntityKind::Url | MessageEntityKind::TextLink { .. }
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(extra: serde_json::Value) -> Message {
+        let mut json = serde_json::json!({
+            "message_id": 10,
+            "date": 1_700_000_000,
+            "chat": {"id": -100, "type": "supergroup", "title": "t"},
+            "from": {"id": 7, "is_bot": false, "first_name": "n"},
+        });
+        json.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn countdown_stays_in_memory_until_the_last_message() {
+        let sent = DashMap::new();
+        let key = (ChatId(-100), 7);
+        assert!(!count_message(&sent, key, 3));
+        assert!(!count_message(&sent, key, 3));
+        assert_eq!(sent.get(&key).map(|n| *n), Some(2));
+        assert!(count_message(&sent, key, 3));
+        assert!(sent.is_empty());
+        assert!(count_message(&sent, key, 1));
+    }
+
+    #[test]
+    fn only_plain_text_passes() {
+        assert!(is_plain_text(&message(serde_json::json!({"text": "hi"}))));
+        assert!(!is_plain_text(&message(serde_json::json!({
+            "text": "go example.com",
+            "entities": [{"type": "url", "offset": 3, "length": 11}],
+        }))));
+        assert!(!is_plain_text(&message(serde_json::json!({
+            "text": "here",
+            "entities": [{"type": "text_link", "offset": 0, "length": 4, "url": "https://x.org/"}],
+        }))));
+        assert!(!is_plain_text(&message(serde_json::json!({
+            "text": "news",
+            "forward_origin": {"type": "hidden_user", "date": 1_700_000_000, "sender_user_name": "x"},
+        }))));
+        assert!(!is_plain_text(&message(serde_json::json!({
+            "photo": [{"file_id": "a", "file_unique_id": "b", "width": 1, "height": 1}],
+        }))));
+    }
+}
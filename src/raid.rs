@@ -0,0 +1,343 @@
+// src/raid.rs
+
+//! Защита от рейдов: скользящее окно вступлений по чату.
+//! Если за `RAID_WINDOW_SEC` вошло `RAID_JOIN_THRESHOLD` и больше человек —
+//! чат уходит в блокировку: строже капча, дольше бан, по желанию
+//! `set_chat_permissions` без прав. Блокировка снимается сама, когда
+//! `RAID_COOLDOWN_MIN` подряд не было новой волны.
+//!
+//! Блокировка (срок и прежние права чата) хранится и в задаче
+//! `LockdownExpiry`: после рестарта `resume` поднимает её оттуда.
+
+use crate::api;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::i18n::tr;
+use crate::scheduler::Job;
+use crate::state::AppState;
+use chrono::{Duration as ChronoDuration, Utc};
+use dashmap::DashMap;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+use teloxide::types::{ChatPermissions, ParseMode};
+
+struct Lockdown {
+    until: Instant,
+    /// Права чата до блокировки — вернуть при снятии.
+    saved_permissions: Option<ChatPermissions>,
+}
+
+/// Состояние детектора в памяти. Окно вступлений после рестарта начинается
+/// с чистого листа, блокировки восстанавливает `resume`.
+#[derive(Default)]
+pub struct RaidGuard {
+    joins: DashMap<ChatId, VecDeque<(Instant, u64)>>,
+    lockdowns: DashMap<ChatId, Lockdown>,
+}
+
+impl RaidGuard {
+    /// Учесть вступление; `true` — в окне набралось `threshold` человек.
+    /// Один и тот же user в окне считается один раз (chat_member + service message).
+    fn register_join(
+        &self,
+        chat: ChatId,
+        user: UserId,
+        now: Instant,
+        window: Duration,
+        threshold: usize,
+    ) -> bool {
+        let mut q = self.joins.entry(chat).or_default();
+        while q
+            .front()
+            .is_some_and(|(t, _)| now.duration_since(*t) > window)
+        {
+            q.pop_front();
+        }
+        if !q.iter().any(|(_, u)| *u == user.0) {
+            q.push_back((now, user.0));
+        }
+        q.len() >= threshold
+    }
+
+    pub fn is_locked(&self, chat: ChatId) -> bool {
+        self.lockdowns
+            .get(&chat)
+            .is_some_and(|l| l.until > Instant::now())
+    }
+
+    /// Начать или продлить блокировку. `true` — она только что началась.
+    fn begin(&self, chat: ChatId, until: Instant) -> bool {
+        let mut fresh = false;
+        self.lockdowns
+            .entry(chat)
+            .and_modify(|l| l.until = l.until.max(until))
+            .or_insert_with(|| {
+                fresh = true;
+                Lockdown {
+                    until,
+                    saved_permissions: None,
+                }
+            });
+        fresh
+    }
+
+    fn until(&self, chat: ChatId) -> Option<Instant> {
+        self.lockdowns.get(&chat).map(|l| l.until)
+    }
+
+    /// Вернуть блокировку из сохранённой задачи (после рестарта).
+    fn restore(&self, chat: ChatId, until: Instant, saved_permissions: Option<ChatPermissions>) {
+        self.lockdowns.insert(
+            chat,
+            Lockdown {
+                until,
+                saved_permissions,
+            },
+        );
+    }
+
+    fn saved_permissions(&self, chat: ChatId) -> Option<ChatPermissions> {
+        self.lockdowns
+            .get(&chat)
+            .and_then(|l| l.saved_permissions.clone())
+    }
+
+    fn save_permissions(&self, chat: ChatId, p: ChatPermissions) {
+        if let Some(mut l) = self.lockdowns.get_mut(&chat) {
+            l.saved_permissions = Some(p);
+        }
+    }
+
+    /// Снять блокировку, если её срок вышел (и её не продлили).
+    fn end_if_expired(&self, chat: ChatId, now: Instant) -> Option<Lockdown> {
+        self.lockdowns
+            .remove_if(&chat, |_, l| l.until <= now)
+            .map(|(_, l)| l)
+    }
+}
+
+/// Вызывается на каждое вступление до выдачи капчи: считает темп
+/// и при необходимости включает блокировку.
+pub async fn on_join(bot: &Bot, state: &Arc<AppState>, chat: ChatId, user: UserId) {
+    let rc = &state.cfg.raid;
+    if !rc.enabled() || chat.is_user() {
+        return;
+    }
+    let now = Instant::now();
+    let window = Duration::from_secs(rc.window_secs);
+    if !state
+        .raid
+        .register_join(chat, user, now, window, rc.join_threshold)
+    {
+        return;
+    }
+    // Каждая следующая волна продлевает блокировку (и переносит её снятие).
+    let fresh = state
+        .raid
+        .begin(chat, now + Duration::from_secs(rc.cooldown_secs));
+    schedule_expiry(state, chat);
+    if !fresh {
+        return;
+    }
+
+    warn!(
+        "RAID detected: {}+ joins in {}s, lockdown (chat={})",
+        rc.join_threshold, rc.window_secs, chat.0
+    );
+    state.metrics.lockdown();
+    state.record(AuditEntry::new(
+        chat,
+        None,
+        format!("chat {}", chat.0),
+        AuditAction::LockdownOn,
+        format!("{}+ joins in {}s", rc.join_threshold, rc.window_secs),
+    ));
+
+    if rc.restrict_chat {
+        match api::call(state, chat, "get_chat", bot.get_chat(chat)).await {
+            Ok(c) => {
+                if let Some(p) = c.permissions() {
+                    state.raid.save_permissions(chat, p);
+                    // права — в задачу, чтобы вернуть их и после рестарта
+                    schedule_expiry(state, chat);
+                }
+                let lock = bot.set_chat_permissions(chat, ChatPermissions::empty());
+                let target = format!("chat {}", chat.0);
+                let _ = api::moderate(state, chat, target, "set_chat_permissions", lock).await;
+            }
+            Err(e) => {
+                warn!("get_chat before lockdown failed (chat={}): {}", chat.0, e);
+            }
+        }
+    }
+
+    let lang = state.settings(chat).language;
+    let text = tr!(
+        lang,
+        "raid.lockdown_on",
+        joins = rc.join_threshold,
+        secs = rc.window_secs,
+        mode = rc.captcha_mode.as_str(),
+        ban = rc.ban_minutes,
+        readonly = if rc.restrict_chat {
+            tr!(lang, "raid.readonly")
+        } else {
+            ""
+        },
+        cooldown = rc.cooldown_secs / 60,
+    );
+    notify(bot, state, chat, &text).await;
+}
+
+/// Поставить снятие блокировки на её текущий срок (`until`).
+fn schedule_expiry(state: &AppState, chat: ChatId) {
+    let Some(until) = state.raid.until(chat) else {
+        return;
+    };
+    let left = until.saturating_duration_since(Instant::now());
+    state.schedule(
+        Utc::now() + ChronoDuration::from_std(left).unwrap_or_default(),
+        Job::LockdownExpiry {
+            chat_id: chat.0,
+            saved_permissions: state.raid.saved_permissions(chat),
+        },
+    );
+}
+
+/// После рестарта: вернуть в память блокировки из задач `LockdownExpiry`,
+/// чтобы по сроку снять их и вернуть чату прежние права.
+pub fn resume(state: &AppState) {
+    let now = Utc::now();
+    for sj in state.jobs.list() {
+        if let Job::LockdownExpiry {
+            chat_id,
+            saved_permissions,
+        } = sj.job
+        {
+            let left = (sj.at - now).to_std().unwrap_or_default();
+            state
+                .raid
+                .restore(ChatId(chat_id), Instant::now() + left, saved_permissions);
+        }
+    }
+}
+
+/// Срабатывание таймера блокировки (из очереди задач).
+pub async fn on_expiry(bot: &Bot, state: &AppState, chat: ChatId) {
+    let Some(ld) = state.raid.end_if_expired(chat, Instant::now()) else {
+        // Продлили между постановкой и запуском — ждём нового срока.
+        schedule_expiry(state, chat);
+        return;
+    };
+
+    info!("Lockdown lifted (chat={})", chat.0);
+    if let Some(p) = ld.saved_permissions {
+        let unlock = bot.set_chat_permissions(chat, p);
+        let target = format!("chat {}", chat.0);
+        let _ = api::moderate(state, chat, target, "set_chat_permissions", unlock).await;
+    }
+    state.record(AuditEntry::new(
+        chat,
+        None,
+        format!("chat {}", chat.0),
+        AuditAction::LockdownOff,
+        "cool-down elapsed",
+    ));
+    let lang = state.settings(chat).language;
+    notify(bot, state, chat, tr!(lang, "raid.lockdown_off")).await;
+}
+
+/// Сообщение в сам чат и в личку супер-админам (если они писали боту).
+async fn notify(bot: &Bot, state: &AppState, chat: ChatId, text: &str) {
+    let notice = bot.send_message(chat, text).parse_mode(ParseMode::Html);
+    let _ = api::call(state, chat, "send_message", notice).await;
+    let dm = format!("<code>{}</code>: {text}", chat.0);
+    for admin in &state.cfg.admin_ids {
+        let req = bot
+            .send_message(*admin, dm.as_str())
+            .parse_mode(ParseMode::Html);
+        let _ = api::call(state, ChatId::from(*admin), "send_message", req).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, CaptchaMode, ChatSettings, FailureAction, RaidConfig};
+
+    #[test]
+    fn window_counts_unique_recent_joins() {
+        let g = RaidGuard::default();
+        let chat = ChatId(-1);
+        let w = Duration::from_secs(10);
+        let t0 = Instant::now();
+
+        assert!(!g.register_join(chat, UserId(1), t0, w, 3));
+        assert!(!g.register_join(chat, UserId(1), t0, w, 3)); // дубль
+        assert!(!g.register_join(chat, UserId(2), t0, w, 3));
+        assert!(g.register_join(chat, UserId(3), t0 + Duration::from_secs(5), w, 3));
+
+        // Старые вылетают из окна
+        assert!(!g.register_join(chat, UserId(4), t0 + Duration::from_secs(12), w, 3));
+        // Другой чат не затронут
+        assert!(!g.register_join(ChatId(-2), UserId(5), t0, w, 3));
+    }
+
+    #[test]
+    fn lockdown_extends_and_expires() {
+        let g = RaidGuard::default();
+        let chat = ChatId(-1);
+        let t0 = Instant::now();
+
+        assert!(g.begin(chat, t0 + Duration::from_secs(60)));
+        assert!(g.is_locked(chat));
+        assert!(!g.begin(chat, t0 + Duration::from_secs(120)));
+        assert_eq!(g.until(chat), Some(t0 + Duration::from_secs(120)));
+
+        assert!(g
+            .end_if_expired(chat, t0 + Duration::from_secs(60))
+            .is_none());
+        assert!(g
+            .end_if_expired(chat, t0 + Duration::from_secs(120))
+            .is_some());
+        assert!(!g.is_locked(chat));
+    }
+
+    #[test]
+    fn restored_lockdown_keeps_permissions() {
+        let g = RaidGuard::default();
+        let chat = ChatId(-1);
+        let t0 = Instant::now();
+        g.restore(
+            chat,
+            t0 + Duration::from_secs(60),
+            Some(ChatPermissions::SEND_MESSAGES),
+        );
+        assert!(g.is_locked(chat));
+        let ld = g
+            .end_if_expired(chat, t0 + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(ld.saved_permissions, Some(ChatPermissions::SEND_MESSAGES));
+    }
+
+    #[test]
+    fn lockdown_tightens_settings() {
+        let rc = RaidConfig {
+            join_threshold: 10,
+            window_secs: 60,
+            cooldown_secs: 900,
+            captcha_mode: CaptchaMode::Image,
+            ban_minutes: 1440,
+            restrict_chat: false,
+        };
+        let mut s = ChatSettings::default().resolve(&test_config());
+        s.captcha_mode = CaptchaMode::Button;
+        s.failure_action = FailureAction::SoftKick;
+        rc.apply(&mut s);
+        assert_eq!(s.captcha_mode, CaptchaMode::Image);
+        assert_eq!(s.kick_ban_minutes, 1440);
+        assert_eq!(s.failure_action, FailureAction::TempBan);
+    }
+}
@@ -0,0 +1,350 @@
+// src/scheduler.rs
+
+//! Отложенные действия: таймауты капч, удаление уведомлений, разбан,
+//! снятие блокировки после рейда, конец испытательного срока. Одна очередь по времени вместо россыпи
+//! `tokio::spawn` + `sleep`: задачи видно, их можно отменить по ключу,
+//! они переживают рестарт (через `Store`), сетевые сбои повторяются.
+//!
+//! Ключ задачи — её цель (`captcha:{chat}:{user}`, `delete:{chat}:{msg}`, ...):
+//! повторное планирование с тем же ключом переносит срок, а не дублирует.
+
+use crate::api::{self, is_transient};
+use crate::state::AppState;
+use crate::{captcha, probation, raid};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{ChatPermissions, MessageId};
+use tokio::sync::Notify;
+
+/// Повторов задачи, если `api::call` не справился с сетевой ошибкой.
+const MAX_RETRIES: u32 = 3;
+/// Пауза перед повтором растёт линейно: 30 с, 60 с, 90 с.
+const RETRY_STEP_SECS: i64 = 30;
+/// Пустая очередь: просыпаемся изредка (новая задача будит раньше).
+const IDLE: Duration = Duration::from_secs(3600);
+
+/// Что сделать.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Время на капчу вышло.
+    CaptchaTimeout { chat_id: i64, user: u64 },
+    /// Удалить сообщение (уведомление бота).
+    DeleteMessage { chat_id: i64, message_id: i32 },
+    /// Снять временный бан.
+    Unban { chat_id: i64, user: u64 },
+    /// Срок блокировки чата (рейд) вышел. `saved_permissions` — права чата
+    /// до блокировки: по ним блокировка восстанавливается после рестарта.
+    LockdownExpiry {
+        chat_id: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        saved_permissions: Option<ChatPermissions>,
+    },
+    /// Испытательный срок новичка вышел. Пока задача в очереди — он на
+    /// испытательном сроке; `messages_left` — после скольких сообщений он
+    /// кончается досрочно (сам счёт — в памяти).
+    ProbationEnd {
+        chat_id: i64,
+        user: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        messages_left: Option<u32>,
+    },
+}
+
+impl Job {
+    /// Вид задачи (метка в `/metrics`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::CaptchaTimeout { .. } => "captcha_timeout",
+            Job::DeleteMessage { .. } => "delete_message",
+            Job::Unban { .. } => "unban",
+            Job::LockdownExpiry { .. } => "lockdown_expiry",
+            Job::ProbationEnd { .. } => "probation_end",
+        }
+    }
+
+    pub fn key(&self) -> String {
+        match self {
+            Job::CaptchaTimeout { chat_id, user } => captcha_key(ChatId(*chat_id), *user),
+            Job::DeleteMessage {
+                chat_id,
+                message_id,
+            } => format!("delete:{chat_id}:{message_id}"),
+            Job::Unban { chat_id, user } => format!("unban:{chat_id}:{user}"),
+            Job::LockdownExpiry { chat_id, .. } => format!("lockdown:{chat_id}"),
+            Job::ProbationEnd { chat_id, user, .. } => probation_key(ChatId(*chat_id), *user),
+        }
+    }
+}
+
+/// Ключ таймера капчи — чтобы снять его, когда капча решена.
+pub fn captcha_key(chat: ChatId, user: u64) -> String {
+    format!("captcha:{}:{user}", chat.0)
+}
+
+/// Ключ испытательного срока: по нему же проверяем, идёт ли он.
+pub fn probation_key(chat: ChatId, user: u64) -> String {
+    format!("probation:{}:{user}", chat.0)
+}
+
+/// Задача со временем запуска (в таком виде и хранится).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub job: Job,
+    /// Сколько раз уже повторяли после ошибки.
+    #[serde(default)]
+    pub retries: u32,
+}
+
+impl ScheduledJob {
+    pub fn new(at: DateTime<Utc>, job: Job) -> Self {
+        Self {
+            at,
+            job,
+            retries: 0,
+        }
+    }
+}
+
+/// Очередь в памяти. На диск пишет `AppState` (`schedule`/`cancel_job`).
+#[derive(Default)]
+pub struct Scheduler {
+    queue: Mutex<Queue>,
+    wake: Notify,
+}
+
+#[derive(Default)]
+struct Queue {
+    order: BTreeSet<(DateTime<Utc>, String)>,
+    jobs: HashMap<String, ScheduledJob>,
+}
+
+impl Scheduler {
+    /// Поставить задачу; задача с тем же ключом заменяется.
+    pub fn insert(&self, sj: ScheduledJob) {
+        let (key, at) = (sj.job.key(), sj.at);
+        {
+            let mut q = self.queue.lock().expect("scheduler lock");
+            if let Some(old) = q.jobs.insert(key.clone(), sj) {
+                q.order.remove(&(old.at, key.clone()));
+            }
+            q.order.insert((at, key));
+        }
+        self.wake.notify_one();
+    }
+
+    /// Снять задачу по ключу.
+    pub fn remove(&self, key: &str) -> Option<ScheduledJob> {
+        let mut q = self.queue.lock().expect("scheduler lock");
+        let sj = q.jobs.remove(key)?;
+        q.order.remove(&(sj.at, key.to_string()));
+        Some(sj)
+    }
+
+    pub fn get(&self, key: &str) -> Option<ScheduledJob> {
+        let q = self.queue.lock().expect("scheduler lock");
+        q.jobs.get(key).cloned()
+    }
+
+    /// Забрать самую раннюю задачу, если её время пришло.
+    pub fn pop_due(&self, now: DateTime<Utc>) -> Option<ScheduledJob> {
+        let mut q = self.queue.lock().expect("scheduler lock");
+        let (at, _) = q.order.first()?;
+        if *at > now {
+            return None;
+        }
+        let (_, key) = q.order.pop_first()?;
+        q.jobs.remove(&key)
+    }
+
+    pub fn next_at(&self) -> Option<DateTime<Utc>> {
+        let q = self.queue.lock().expect("scheduler lock");
+        q.order.first().map(|(at, _)| *at)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().expect("scheduler lock").jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Все задачи по порядку запуска.
+    pub fn list(&self) -> Vec<ScheduledJob> {
+        let q = self.queue.lock().expect("scheduler lock");
+        q.order.iter().map(|(_, k)| q.jobs[k].clone()).collect()
+    }
+}
+
+/// Удалить сообщение через `secs` секунд (0 — не удалять).
+pub fn delete_later(state: &AppState, chat_id: ChatId, msg_id: MessageId, secs: u64) {
+    if secs == 0 {
+        return;
+    }
+    state.schedule(
+        Utc::now() + ChronoDuration::seconds(secs as i64),
+        Job::DeleteMessage {
+            chat_id: chat_id.0,
+            message_id: msg_id.0,
+        },
+    );
+}
+
+/// Цикл исполнителя: спать до ближайшей задачи (или до новой), запускать
+/// созревшие. Каждая задача — в своей task, чтобы долгие не держали очередь.
+pub fn spawn(bot: Bot, state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            while let Some(sj) = state.take_due_job(Utc::now()) {
+                tokio::spawn(execute(bot.clone(), state.clone(), sj));
+            }
+            let wait = state
+                .jobs
+                .next_at()
+                .map_or(IDLE, |at| (at - Utc::now()).to_std().unwrap_or_default());
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = state.jobs.wake.notified() => {}
+            }
+        }
+    });
+}
+
+async fn execute(bot: Bot, state: Arc<AppState>, sj: ScheduledJob) {
+    debug!("Job fired: {} (retries={})", sj.job.key(), sj.retries);
+    let res = match sj.job {
+        Job::CaptchaTimeout { chat_id, user } => {
+            captcha::on_timeout(&bot, &state, ChatId(chat_id), UserId(user)).await;
+            Ok(())
+        }
+        Job::DeleteMessage {
+            chat_id,
+            message_id,
+        } => {
+            let delete = bot.delete_message(ChatId(chat_id), MessageId(message_id));
+            api::call(&state, ChatId(chat_id), "delete_message", delete)
+                .await
+                .map(|_| state.metrics.messages_deleted(1))
+        }
+        Job::Unban { chat_id, user } => {
+            let unban = bot
+                .unban_chat_member(ChatId(chat_id), UserId(user))
+                .only_if_banned(true);
+            api::moderate(
+                &state,
+                ChatId(chat_id),
+                user.to_string(),
+                "unban_chat_member",
+                unban,
+            )
+            .await
+            .map(|_| ())
+        }
+        Job::LockdownExpiry { chat_id, .. } => {
+            raid::on_expiry(&bot, &state, ChatId(chat_id)).await;
+            Ok(())
+        }
+        Job::ProbationEnd { chat_id, user, .. } => {
+            probation::finish(&bot, &state, ChatId(chat_id), UserId(user)).await
+        }
+    };
+
+    let Err(e) = res else {
+        return;
+    };
+    if is_transient(&e) && sj.retries < MAX_RETRIES {
+        let retries = sj.retries + 1;
+        warn!(
+            "Job {} failed, retry {retries}/{MAX_RETRIES}: {e}",
+            sj.job.key()
+        );
+        state.put_job(ScheduledJob {
+            at: Utc::now() + ChronoDuration::seconds(RETRY_STEP_SECS * retries as i64),
+            retries,
+            ..sj
+        });
+    } else {
+        warn!("Job {} failed: {e}", sj.job.key());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn pops_in_time_order_and_replaces_by_key() {
+        let s = Scheduler::default();
+        let captcha = Job::CaptchaTimeout {
+            chat_id: -1,
+            user: 7,
+        };
+        s.insert(ScheduledJob::new(at(30), captcha.clone()));
+        s.insert(ScheduledJob::new(
+            at(10),
+            Job::LockdownExpiry {
+                chat_id: -1,
+                saved_permissions: None,
+            },
+        ));
+        // тот же ключ — переносим срок, а не дублируем
+        s.insert(ScheduledJob::new(at(5), captcha.clone()));
+        assert_eq!(s.len(), 2);
+        assert_eq!(s.next_at(), Some(at(5)));
+
+        assert!(s.pop_due(at(0)).is_none());
+        assert_eq!(s.pop_due(at(20)).map(|j| j.job), Some(captcha));
+        assert_eq!(
+            s.pop_due(at(20)).map(|j| j.job),
+            Some(Job::LockdownExpiry {
+                chat_id: -1,
+                saved_permissions: None,
+            })
+        );
+        assert!(s.pop_due(at(100)).is_none());
+    }
+
+    #[test]
+    fn cancel_by_key() {
+        let s = Scheduler::default();
+        s.insert(ScheduledJob::new(
+            at(1),
+            Job::CaptchaTimeout {
+                chat_id: -1,
+                user: 7,
+            },
+        ));
+        assert!(s.remove(&captcha_key(ChatId(-1), 7)).is_some());
+        assert!(s.remove(&captcha_key(ChatId(-1), 7)).is_none());
+        assert!(s.pop_due(at(100)).is_none());
+        assert_eq!(s.len(), 0);
+    }
+
+    #[test]
+    fn job_json_is_flat() {
+        let sj = ScheduledJob::new(
+            at(0),
+            Job::DeleteMessage {
+                chat_id: -5,
+                message_id: 9,
+            },
+        );
+        let json = serde_json::to_value(&sj).unwrap();
+        assert_eq!(json["kind"], "delete_message");
+        assert_eq!(json["message_id"], 9);
+        let back: ScheduledJob = serde_json::from_value(json).unwrap();
+        assert_eq!(back, sj);
+    }
+}
@@ -0,0 +1,744 @@
+// src/settings_menu.rs
+
+//! Inline-меню `/settings`: правка настроек конкретного чата без перезапуска.
+//! Callback-данные: `set:{chat_id}:{действие}[:{значение}]` (≤ 64 байт).
+
+use crate::admins;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::config::{CaptchaMode, ChatSettings, FailureAction, Settings, WarnAction};
+use crate::i18n::{tr, Lang};
+use crate::state::AppState;
+use anyhow::Result;
+use std::sync::Arc;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
+
+/// Префикс callback-данных меню (по нему роутим в app.rs).
+pub const PREFIX: &str = "set:";
+
+/// Пресеты таймаута капчи, секунды.
+const TIMEOUT_PRESETS: [u64; 5] = [30, 60, 120, 300, 600];
+/// Пресеты бана после провала, минуты (0 = мягкий кик).
+const BAN_PRESETS: [i64; 5] = [0, 10, 60, 1440, 10080];
+/// Пресеты лимита попыток для текстовых капч (0 = без лимита).
+const ATTEMPT_PRESETS: [u32; 4] = [1, 3, 5, 0];
+/// Пресеты автоудаления приветствия/уведомлений, секунды (0 = не удалять).
+const CLEANUP_PRESETS: [u64; 5] = [0, 30, 60, 300, 3600];
+/// Пресеты испытательного срока, часы (0 = без него).
+const PROBATION_PRESETS: [u64; 5] = [0, 1, 6, 24, 72];
+/// Пресеты досрочного конца срока, сообщения (0 = только по времени).
+const PROBATION_MESSAGE_PRESETS: [u32; 4] = [0, 5, 10, 20];
+/// Пресеты порога предупреждений (0 = не наказывать).
+const WARN_LIMIT_PRESETS: [u32; 4] = [0, 2, 3, 5];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Mode(CaptchaMode),
+    Timeout(u64),
+    Ban(i64),
+    Failure(FailureAction),
+    Attempts(u32),
+    ToggleFresh,
+    ToggleDelete,
+    Language(Lang),
+    ToggleUserLanguage,
+    ToggleWelcome,
+    WelcomeDelete(u64),
+    NoticeDelete(u64),
+    ToggleService,
+    ToggleJoinRequests,
+    TogglePrivate,
+    Probation(u64),
+    ProbationMessages(u32),
+    WarnLimit(u32),
+    WarnAction(WarnAction),
+    Reset,
+    Close,
+}
+
+/// Отправить меню настроек для `target` чата.
+pub async fn open(bot: &Bot, state: Arc<AppState>, msg: &Message, target: ChatId) -> Result<()> {
+    let s = state.settings(target);
+    bot.send_message(msg.chat.id, render_text(target, &s))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard(target, &s))
+        .await?;
+    Ok(())
+}
+
+/// Обработчик нажатий в меню.
+pub async fn on_callback(bot: Bot, state: Arc<AppState>, q: CallbackQuery) -> Result<()> {
+    let _t = state.metrics.time("callback");
+    let Some((target, action)) = q.data.as_deref().and_then(parse_action) else {
+        bot.answer_callback_query(q.id.clone()).await.ok();
+        return Ok(());
+    };
+
+    if !admins::access(&bot, &state, target, q.from.id)
+        .await
+        .is_admin()
+    {
+        bot.answer_callback_query(q.id.clone())
+            .text(tr!(state.settings(target).language, "settings.admin_only"))
+            .show_alert(true)
+            .await
+            .ok();
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id.clone()).await.ok();
+
+    let Some(menu) = &q.message else {
+        return Ok(());
+    };
+
+    let change = match &action {
+        Action::Close => {
+            bot.delete_message(menu.chat().id, menu.id()).await.ok();
+            return Ok(());
+        }
+        Action::Reset => "reset to defaults".to_string(),
+        Action::ToggleWelcome => format!(
+            "welcome_enabled={}",
+            !state.settings(target).welcome_enabled
+        ),
+        Action::WelcomeDelete(t) => format!("welcome_delete_secs={t}"),
+        Action::NoticeDelete(t) => format!("notice_delete_secs={t}"),
+        Action::ToggleService => format!(
+            "delete_service_messages={}",
+            !state.settings(target).delete_service_messages
+        ),
+        Action::ToggleJoinRequests => {
+            format!("join_requests={}", !state.settings(target).join_requests)
+        }
+        Action::TogglePrivate => format!(
+            "private_captcha={}",
+            !state.settings(target).private_captcha
+        ),
+        Action::Probation(h) => format!("probation_hours={h}"),
+        Action::ProbationMessages(n) => format!("probation_messages={n}"),
+        Action::WarnLimit(n) => format!("warn_limit={n}"),
+        Action::WarnAction(a) => format!("warn_action={}", a.as_str()),
+        Action::Mode(m) => format!("captcha_mode={}", m.as_str()),
+        Action::Timeout(t) => format!("captcha_timeout_secs={t}"),
+        Action::Ban(m) => format!("kick_ban_minutes={m}"),
+        Action::Failure(a) => format!("failure_action={}", a.as_str()),
+        Action::Attempts(n) => format!("max_attempts={n}"),
+        Action::ToggleFresh => format!(
+            "new_challenge_on_miss={}",
+            !state.settings(target).new_challenge_on_miss
+        ),
+        Action::ToggleDelete => format!(
+            "delete_unverified_messages={}",
+            !state.settings(target).delete_unverified_messages
+        ),
+        Action::Language(l) => format!("language={}", l.as_str()),
+        Action::ToggleUserLanguage => format!(
+            "captcha_user_language={}",
+            !state.settings(target).captcha_user_language
+        ),
+    };
+
+    match action {
+        Action::Close => {}
+        // Тексты (шаблоны, правила) и фильтр правятся командами — сброс меню их не трогает.
+        Action::Reset => state.update_chat_settings(target, |cs| {
+            *cs = ChatSettings {
+                welcome_template: cs.welcome_template.take(),
+                captcha_template: cs.captcha_template.take(),
+                rules_link: cs.rules_link.take(),
+                filter: std::mem::take(&mut cs.filter),
+                ..Default::default()
+            }
+        }),
+        Action::ToggleWelcome => {
+            let cur = state.settings(target).welcome_enabled;
+            state.update_chat_settings(target, |cs| cs.welcome_enabled = Some(!cur))
+        }
+        Action::WelcomeDelete(t) => {
+            state.update_chat_settings(target, |cs| cs.welcome_delete_secs = Some(t))
+        }
+        Action::NoticeDelete(t) => {
+            state.update_chat_settings(target, |cs| cs.notice_delete_secs = Some(t))
+        }
+        Action::ToggleService => {
+            let cur = state.settings(target).delete_service_messages;
+            state.update_chat_settings(target, |cs| cs.delete_service_messages = Some(!cur))
+        }
+        Action::ToggleJoinRequests => {
+            let cur = state.settings(target).join_requests;
+            state.update_chat_settings(target, |cs| cs.join_requests = Some(!cur))
+        }
+        Action::TogglePrivate => {
+            let cur = state.settings(target).private_captcha;
+            state.update_chat_settings(target, |cs| cs.private_captcha = Some(!cur))
+        }
+        Action::Probation(h) => {
+            state.update_chat_settings(target, |cs| cs.probation_hours = Some(h))
+        }
+        Action::ProbationMessages(n) => {
+            state.update_chat_settings(target, |cs| cs.probation_messages = Some(n))
+        }
+        Action::WarnLimit(n) => state.update_chat_settings(target, |cs| cs.warn_limit = Some(n)),
+        Action::WarnAction(a) => state.update_chat_settings(target, |cs| cs.warn_action = Some(a)),
+        Action::Mode(m) => state.update_chat_settings(target, |cs| cs.captcha_mode = Some(m)),
+        Action::Timeout(t) => {
+            state.update_chat_settings(target, |cs| cs.captcha_timeout_secs = Some(t))
+        }
+        Action::Ban(m) => state.update_chat_settings(target, |cs| cs.kick_ban_minutes = Some(m)),
+        Action::Failure(a) => state.update_chat_settings(target, |cs| cs.failure_action = Some(a)),
+        Action::Attempts(n) => state.update_chat_settings(target, |cs| cs.max_attempts = Some(n)),
+        Action::ToggleFresh => {
+            let cur = state.settings(target).new_challenge_on_miss;
+            state.update_chat_settings(target, |cs| cs.new_challenge_on_miss = Some(!cur))
+        }
+        Action::ToggleDelete => {
+            let cur = state.settings(target).delete_unverified_messages;
+            state.update_chat_settings(target, |cs| cs.delete_unverified_messages = Some(!cur))
+        }
+        Action::Language(l) => state.update_chat_settings(target, |cs| cs.language = Some(l)),
+        Action::ToggleUserLanguage => {
+            let cur = state.settings(target).captcha_user_language;
+            state.update_chat_settings(target, |cs| cs.captcha_user_language = Some(!cur))
+        }
+    }
+
+    state.record(AuditEntry::new(
+        target,
+        Some(q.from.id),
+        format!("chat {}", target.0),
+        AuditAction::SettingsChanged,
+        change,
+    ));
+
+    let s = state.settings(target);
+    bot.edit_message_text(menu.chat().id, menu.id(), render_text(target, &s))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard(target, &s))
+        .await
+        .ok();
+    Ok(())
+}
+
+/* ======================== Рендер ======================== */
+
+fn render_text(target: ChatId, s: &Settings) -> String {
+    let l = s.language;
+    let yes_no = |on: bool| tr!(l, if on { "settings.yes" } else { "settings.no" });
+    format!(
+        "<b>{}</b> <code>{}</code>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>{}\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>{}\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>{}\n\
+         {}: <b>{}</b>\n\
+         {}: <b>{}</b>{}",
+        tr!(l, "settings.title"),
+        target.0,
+        tr!(l, "settings.captcha"),
+        s.captcha_mode.as_str(),
+        tr!(l, "settings.timeout"),
+        tr!(l, "settings.seconds", n = s.captcha_timeout_secs),
+        tr!(l, "settings.on_failure"),
+        failure_label(l, s.failure_action),
+        tr!(l, "settings.ban_term"),
+        ban_label(l, s.kick_ban_minutes),
+        tr!(l, "settings.attempts"),
+        attempts_label(s.max_attempts),
+        if s.new_challenge_on_miss {
+            tr!(l, "settings.fresh_suffix")
+        } else {
+            ""
+        },
+        tr!(l, "settings.delete_unverified"),
+        yes_no(s.delete_unverified_messages),
+        tr!(l, "settings.welcome"),
+        yes_no(s.welcome_enabled),
+        if s.welcome_template.is_some() {
+            tr!(l, "settings.custom")
+        } else {
+            ""
+        },
+        tr!(l, "settings.cleanup"),
+        tr!(
+            l,
+            "settings.cleanup_value",
+            welcome = cleanup_label(l, s.welcome_delete_secs),
+            notice = cleanup_label(l, s.notice_delete_secs),
+        ),
+        tr!(l, "settings.service_messages"),
+        yes_no(s.delete_service_messages),
+        tr!(l, "settings.join_requests"),
+        yes_no(s.join_requests),
+        tr!(l, "settings.private_captcha"),
+        yes_no(s.private_captcha),
+        tr!(l, "settings.probation"),
+        hours_label(l, s.probation_hours),
+        if s.probation_hours > 0 && s.probation_messages > 0 {
+            tr!(
+                l,
+                "settings.probation_messages_suffix",
+                n = s.probation_messages
+            )
+        } else {
+            String::new()
+        },
+        tr!(l, "settings.warns"),
+        warns_label(l, s),
+        tr!(l, "settings.language"),
+        tr!(l, "lang_name"),
+        if s.captcha_user_language {
+            tr!(l, "settings.user_language")
+        } else {
+            ""
+        },
+    )
+}
+
+fn keyboard(target: ChatId, s: &Settings) -> InlineKeyboardMarkup {
+    let l = s.language;
+    let cb = |action: String| format!("{PREFIX}{}:{action}", target.0);
+    let on_off = |on: bool| tr!(l, if on { "settings.on" } else { "settings.off" });
+    let mark = |on: bool, label: String| {
+        if on {
+            format!("• {label} •")
+        } else {
+            label
+        }
+    };
+
+    let modes = CaptchaMode::ALL
+        .iter()
+        .map(|m| {
+            InlineKeyboardButton::callback(
+                mark(*m == s.captcha_mode, m.as_str().to_string()),
+                cb(format!("mode:{}", m.as_str())),
+            )
+        })
+        .collect::<Vec<_>>();
+    let timeouts = TIMEOUT_PRESETS
+        .iter()
+        .map(|t| {
+            InlineKeyboardButton::callback(
+                mark(*t == s.captcha_timeout_secs, format!("{t}s")),
+                cb(format!("timeout:{t}")),
+            )
+        })
+        .collect::<Vec<_>>();
+    let bans = BAN_PRESETS
+        .iter()
+        .map(|m| {
+            InlineKeyboardButton::callback(
+                mark(*m == s.kick_ban_minutes, ban_label(l, *m)),
+                cb(format!("ban:{m}")),
+            )
+        })
+        .collect::<Vec<_>>();
+    let failures = FailureAction::ALL
+        .iter()
+        .map(|a| {
+            InlineKeyboardButton::callback(
+                mark(*a == s.failure_action, failure_label(l, *a).to_string()),
+                cb(format!("fail:{}", a.as_str())),
+            )
+        })
+        .collect::<Vec<_>>();
+    let attempts = ATTEMPT_PRESETS
+        .iter()
+        .map(|n| {
+            InlineKeyboardButton::callback(
+                mark(*n == s.max_attempts, attempts_label(*n)),
+                cb(format!("att:{n}")),
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut languages = Lang::ALL
+        .iter()
+        .map(|x| {
+            InlineKeyboardButton::callback(
+                mark(*x == l, tr!(*x, "lang_name").to_string()),
+                cb(format!("lang:{}", x.as_str())),
+            )
+        })
+        .collect::<Vec<_>>();
+    languages.push(InlineKeyboardButton::callback(
+        tr!(
+            l,
+            "settings.btn_user_language",
+            state = on_off(s.captcha_user_language)
+        ),
+        cb("ulang".into()),
+    ));
+
+    InlineKeyboardMarkup::new(vec![
+        modes,
+        timeouts,
+        failures,
+        bans,
+        attempts,
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_fresh",
+                state = on_off(s.new_challenge_on_miss)
+            ),
+            cb("fresh".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_delete",
+                state = on_off(s.delete_unverified_messages)
+            ),
+            cb("del".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(l, "settings.btn_welcome", state = on_off(s.welcome_enabled)),
+            cb("welcome".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_welcome_delete",
+                ttl = cleanup_label(l, s.welcome_delete_secs)
+            ),
+            cb(format!("wdel:{}", next_cleanup(s.welcome_delete_secs))),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_notice_delete",
+                ttl = cleanup_label(l, s.notice_delete_secs)
+            ),
+            cb(format!("ndel:{}", next_cleanup(s.notice_delete_secs))),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_service",
+                state = on_off(s.delete_service_messages)
+            ),
+            cb("svc".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_join_requests",
+                state = on_off(s.join_requests)
+            ),
+            cb("jreq".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_private_captcha",
+                state = on_off(s.private_captcha)
+            ),
+            cb("priv".into()),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_probation",
+                value = hours_label(l, s.probation_hours)
+            ),
+            cb(format!(
+                "prob:{}",
+                next_preset(&PROBATION_PRESETS, s.probation_hours)
+            )),
+        )],
+        vec![InlineKeyboardButton::callback(
+            tr!(
+                l,
+                "settings.btn_probation_messages",
+                value = probation_messages_label(l, s.probation_messages)
+            ),
+            cb(format!(
+                "probmsg:{}",
+                next_preset(&PROBATION_MESSAGE_PRESETS, s.probation_messages)
+            )),
+        )],
+        vec![
+            InlineKeyboardButton::callback(
+                tr!(
+                    l,
+                    "settings.btn_warn_limit",
+                    value = attempts_label(s.warn_limit)
+                ),
+                cb(format!(
+                    "wlim:{}",
+                    next_preset(&WARN_LIMIT_PRESETS, s.warn_limit)
+                )),
+            ),
+            InlineKeyboardButton::callback(
+                tr!(
+                    l,
+                    "settings.btn_warn_action",
+                    value = warn_action_label(l, s.warn_action)
+                ),
+                cb(format!(
+                    "wact:{}",
+                    next_preset(&WarnAction::ALL, s.warn_action).as_str()
+                )),
+            ),
+        ],
+        languages,
+        vec![
+            InlineKeyboardButton::callback(tr!(l, "settings.btn_reset"), cb("reset".into())),
+            InlineKeyboardButton::callback(tr!(l, "settings.btn_close"), cb("close".into())),
+        ],
+    ])
+}
+
+fn failure_label(l: Lang, a: FailureAction) -> &'static str {
+    match a {
+        FailureAction::SoftKick => tr!(l, "settings.fail_soft_kick"),
+        FailureAction::TempBan => tr!(l, "settings.fail_temp_ban"),
+        FailureAction::PermBan => tr!(l, "settings.fail_perm_ban"),
+        FailureAction::Mute => tr!(l, "settings.fail_mute"),
+        FailureAction::Escalate => tr!(l, "settings.fail_escalate"),
+    }
+}
+
+fn attempts_label(n: u32) -> String {
+    match n {
+        0 => "∞".into(),
+        n => n.to_string(),
+    }
+}
+
+/// Кнопка автоудаления листает пресеты по кругу.
+fn next_cleanup(cur: u64) -> u64 {
+    next_preset(&CLEANUP_PRESETS, cur)
+}
+
+/// Следующий пресет по кругу; незнакомое значение — к первому.
+fn next_preset<T: Copy + PartialEq>(presets: &[T], cur: T) -> T {
+    presets
+        .iter()
+        .position(|t| *t == cur)
+        .map_or(presets[0], |i| presets[(i + 1) % presets.len()])
+}
+
+fn hours_label(l: Lang, hours: u64) -> String {
+    match hours {
+        0 => tr!(l, "settings.off").into(),
+        h if h % 24 == 0 => tr!(l, "settings.days", n = h / 24),
+        h => tr!(l, "settings.hours", n = h),
+    }
+}
+
+fn warn_action_label(l: Lang, a: WarnAction) -> &'static str {
+    match a {
+        WarnAction::Mute => tr!(l, "settings.fail_mute"),
+        WarnAction::Ban => tr!(l, "settings.fail_perm_ban"),
+    }
+}
+
+/// «3 → мут, сгорают через 7 д»; без порога — только срок.
+fn warns_label(l: Lang, s: &Settings) -> String {
+    let expire = match s.warn_expire_hours {
+        0 => tr!(l, "settings.warns_no_expire").to_string(),
+        h => tr!(l, "settings.warns_expire", ttl = hours_label(l, h)),
+    };
+    match s.warn_limit {
+        0 => tr!(l, "settings.warns_count_only", expire = expire),
+        n => tr!(
+            l,
+            "settings.warns_value",
+            n = n,
+            action = warn_action_label(l, s.warn_action),
+            expire = expire
+        ),
+    }
+}
+
+fn probation_messages_label(l: Lang, n: u32) -> String {
+    match n {
+        0 => tr!(l, "settings.never").into(),
+        n => tr!(l, "settings.messages", n = n),
+    }
+}
+
+fn cleanup_label(l: Lang, secs: u64) -> String {
+    match secs {
+        0 => tr!(l, "settings.never").into(),
+        s if s % 60 == 0 => tr!(l, "settings.minutes", n = s / 60),
+        s => tr!(l, "settings.seconds", n = s),
+    }
+}
+
+fn ban_label(l: Lang, minutes: i64) -> String {
+    match minutes {
+        m if m <= 0 => tr!(l, "settings.kick").into(),
+        m if m % 1440 == 0 => tr!(l, "settings.ban_days", n = m / 1440),
+        m if m % 60 == 0 => tr!(l, "settings.ban_hours", n = m / 60),
+        m => tr!(l, "settings.ban_minutes", n = m),
+    }
+}
+
+/* ======================== Разбор ======================== */
+
+/// `set:{chat}:{action}[:{value}]` -> (чат, действие).
+fn parse_action(data: &str) -> Option<(ChatId, Action)> {
+    let rest = data.strip_prefix(PREFIX)?;
+    let mut it = rest.splitn(3, ':');
+    let chat = ChatId(it.next()?.parse().ok()?);
+    let action = match (it.next()?, it.next()) {
+        ("mode", Some(v)) => Action::Mode(v.parse().ok()?),
+        ("timeout", Some(v)) => Action::Timeout(v.parse().ok().filter(|t| *t > 0)?),
+        ("ban", Some(v)) => Action::Ban(v.parse().ok()?),
+        ("fail", Some(v)) => Action::Failure(v.parse().ok()?),
+        ("att", Some(v)) => Action::Attempts(v.parse().ok()?),
+        ("fresh", None) => Action::ToggleFresh,
+        ("del", None) => Action::ToggleDelete,
+        ("lang", Some(v)) => Action::Language(v.parse().ok()?),
+        ("ulang", None) => Action::ToggleUserLanguage,
+        ("welcome", None) => Action::ToggleWelcome,
+        ("wdel", Some(v)) => Action::WelcomeDelete(v.parse().ok()?),
+        ("ndel", Some(v)) => Action::NoticeDelete(v.parse().ok()?),
+        ("svc", None) => Action::ToggleService,
+        ("jreq", None) => Action::ToggleJoinRequests,
+        ("priv", None) => Action::TogglePrivate,
+        ("prob", Some(v)) => Action::Probation(v.parse().ok()?),
+        ("probmsg", Some(v)) => Action::ProbationMessages(v.parse().ok()?),
+        ("wlim", Some(v)) => Action::WarnLimit(v.parse().ok()?),
+        ("wact", Some(v)) => Action::WarnAction(v.parse().ok()?),
+        ("reset", None) => Action::Reset,
+        ("close", None) => Action::Close,
+        _ => return None,
+    };
+    Some((chat, action))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    #[test]
+    fn parse_actions() {
+        assert_eq!(
+            parse_action("set:-100123:mode:image"),
+            Some((ChatId(-100123), Action::Mode(CaptchaMode::Image)))
+        );
+        assert_eq!(
+            parse_action("set:-100123:timeout:120"),
+            Some((ChatId(-100123), Action::Timeout(120)))
+        );
+        assert_eq!(
+            parse_action("set:5:ban:0"),
+            Some((ChatId(5), Action::Ban(0)))
+        );
+        assert_eq!(
+            parse_action("set:5:fail:perm_ban"),
+            Some((ChatId(5), Action::Failure(FailureAction::PermBan)))
+        );
+        assert_eq!(
+            parse_action("set:5:del"),
+            Some((ChatId(5), Action::ToggleDelete))
+        );
+        assert_eq!(
+            parse_action("set:5:att:0"),
+            Some((ChatId(5), Action::Attempts(0)))
+        );
+        assert_eq!(
+            parse_action("set:5:fresh"),
+            Some((ChatId(5), Action::ToggleFresh))
+        );
+        assert_eq!(
+            parse_action("set:5:lang:en"),
+            Some((ChatId(5), Action::Language(Lang::En)))
+        );
+        assert_eq!(
+            parse_action("set:5:ulang"),
+            Some((ChatId(5), Action::ToggleUserLanguage))
+        );
+        assert_eq!(
+            parse_action("set:5:welcome"),
+            Some((ChatId(5), Action::ToggleWelcome))
+        );
+        assert_eq!(
+            parse_action("set:5:wdel:60"),
+            Some((ChatId(5), Action::WelcomeDelete(60)))
+        );
+        assert_eq!(
+            parse_action("set:5:svc"),
+            Some((ChatId(5), Action::ToggleService))
+        );
+        assert_eq!(
+            parse_action("set:5:jreq"),
+            Some((ChatId(5), Action::ToggleJoinRequests))
+        );
+        assert_eq!(
+            parse_action("set:5:priv"),
+            Some((ChatId(5), Action::TogglePrivate))
+        );
+        assert_eq!(
+            parse_action("set:5:prob:24"),
+            Some((ChatId(5), Action::Probation(24)))
+        );
+        assert_eq!(
+            parse_action("set:5:probmsg:10"),
+            Some((ChatId(5), Action::ProbationMessages(10)))
+        );
+        assert_eq!(
+            parse_action("set:5:wact:ban"),
+            Some((ChatId(5), Action::WarnAction(WarnAction::Ban)))
+        );
+        assert_eq!(parse_action("set:5:lang:xx"), None);
+        assert_eq!(parse_action("set:5:att:-1"), None);
+        assert_eq!(parse_action("set:5:timeout:0"), None);
+        assert_eq!(parse_action("set:x:reset"), None);
+        assert_eq!(parse_action("ok:5"), None);
+    }
+
+    #[test]
+    fn keyboard_payloads_roundtrip_and_fit() {
+        let s = Settings {
+            failure_action: FailureAction::Escalate,
+            ..ChatSettings::default().resolve(&test_config())
+        };
+        let chat = ChatId(-1001234567890);
+        for row in keyboard(chat, &s).inline_keyboard {
+            for b in row {
+                let InlineKeyboardButtonKind::CallbackData(d) = b.kind else {
+                    panic!("callback button expected");
+                };
+                assert!(d.len() <= 64, "callback data too long: {d}");
+                assert_eq!(parse_action(&d).map(|(c, _)| c), Some(chat));
+            }
+        }
+    }
+
+    #[test]
+    fn cleanup_presets_cycle() {
+        assert_eq!(next_cleanup(0), 30);
+        assert_eq!(next_cleanup(3600), 0);
+        assert_eq!(next_cleanup(45), 0);
+        assert_eq!(cleanup_label(Lang::En, 0), "never");
+        assert_eq!(cleanup_label(Lang::En, 30), "30 s");
+        assert_eq!(cleanup_label(Lang::Ru, 300), "5 мин");
+        assert_eq!(next_preset(&PROBATION_PRESETS, 72), 0);
+        assert_eq!(hours_label(Lang::En, 0), "off");
+        assert_eq!(hours_label(Lang::En, 6), "6 h");
+        assert_eq!(hours_label(Lang::Ru, 72), "3 д");
+    }
+
+    #[test]
+    fn ban_labels() {
+        assert_eq!(ban_label(Lang::Ru, 0), "кик");
+        assert_eq!(ban_label(Lang::Ru, 10), "бан 10м");
+        assert_eq!(ban_label(Lang::Ru, 60), "бан 1ч");
+        assert_eq!(ban_label(Lang::Ru, 1440), "бан 1д");
+        assert_eq!(ban_label(Lang::En, 120), "ban 2h");
+    }
+}
@@ -1,65 +1,98 @@
-//! Хранилище состояния и настройка whitelists (с JSON-персистом).
+//! Состояние приложения и настройка whitelists (персист — через `storage::Store`).
 
-use crate::config::Config;
+use crate::admins::AdminCache;
+use crate::api::Limiter;
+use crate::audit::AuditEntry;
+use crate::config::{ChatSettings, Config, Settings};
+use crate::filter::FilterGuard;
+use crate::metrics::Metrics;
+use crate::raid::RaidGuard;
+use crate::scheduler::{self, Job, ScheduledJob, Scheduler};
+use crate::storage::{self, Store, WlEntry};
+use crate::utils::normalize_username;
+use crate::warns::Warning;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 use dashmap::{DashMap, DashSet};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::collections::BTreeSet;
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
-use std::{fs, io};
 use teloxide::types::{ChatId, User, UserId};
-use crate::utils::normalize_username;
 
-/// Ожидание прохождения капчи (в памяти, не пишется в файл).
-#[derive(Clone)]
+/// Сколько помним одобренную заявку: вступление приходит почти сразу.
+const JOIN_APPROVED_TTL: Duration = Duration::from_secs(300);
+
+/// Ожидание прохождения капчи (сохраняется в хранилище, чтобы пережить рестарт).
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Pending {
-    pub _user: u64,
+    pub user: u64,
     pub captcha_msg_id: i32,
-    pub _deadline: Instant,
+    /// Когда истекает время на капчу (по нему же восстанавливаем таймер).
+    pub deadline: DateTime<Utc>,
     pub user_message_ids: Vec<i32>,
     pub captcha_mode: crate::config::CaptchaMode,
     pub expected_answer: Option<String>,
+    /// Сколько неверных ответов уже было.
+    #[serde(default)]
+    pub attempts: u32,
+    /// Случайная метка задачи в callback-данных кнопок; пусто — кнопок нет.
+    #[serde(default)]
+    pub nonce: String,
+    /// Сообщение-капча — в личке (заявка на вступление или deep link).
+    #[serde(default)]
+    pub via_dm: bool,
+    /// Метка deep link `/start verify_<token>`; пусто — капча без ссылки.
+    #[serde(default)]
+    pub token: String,
 }
 
-/// То, что реально сохраняем на диск.
-#[derive(Default, Serialize, Deserialize)]
-struct PersistentState {
-    // Боты
-    bot_whitelist_ids: Vec<u64>,
-    bot_whitelist_usernames: Vec<String>, // lower-case, без '@'
-    // Люди
-    user_whitelist_ids: Vec<u64>,
-    user_whitelist_usernames: Vec<String>, // lower-case, без '@'
+impl Pending {
+    /// Капча по заявке: заявитель ещё не в чате, итог — одобрить/отклонить.
+    pub fn is_join_request(&self) -> bool {
+        self.via_dm && self.token.is_empty()
+    }
 }
 
-/// Простое файловое хранилище JSON.
-struct FileStore {
-    path: PathBuf,
+/// Какой whitelist правим: общий (супер-админы) или список чата (его админы).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WlScope {
+    Global,
+    Chat(ChatId),
 }
 
-impl FileStore {
-    fn new(path: impl Into<PathBuf>) -> Self {
-        Self { path: path.into() }
+/// Whitelist конкретного чата. Имена — lower-case, без '@'.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatWhitelist {
+    pub bot_ids: BTreeSet<u64>,
+    pub bot_names: BTreeSet<String>,
+    pub user_ids: BTreeSet<u64>,
+    pub user_names: BTreeSet<String>,
+}
+
+impl ChatWhitelist {
+    fn allows_bot(&self, user: &User) -> bool {
+        self.bot_ids.contains(&user.id.0)
+            || user
+                .username
+                .as_ref()
+                .is_some_and(|n| self.bot_names.contains(&n.to_lowercase()))
     }
 
-    fn load(&self) -> io::Result<PersistentState> {
-        match fs::read_to_string(&self.path) {
-            Ok(s) => Ok(serde_json::from_str(&s).unwrap_or_default()),
-            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PersistentState::default()),
-            Err(e) => Err(e),
-        }
+    fn allows_user(&self, user: &User) -> bool {
+        self.user_ids.contains(&user.id.0)
+            || user
+                .username
+                .as_ref()
+                .is_some_and(|n| self.user_names.contains(&n.to_lowercase()))
     }
 
-    fn save(&self, st: &PersistentState) -> io::Result<()> {
-        if let Some(dir) = self.path.parent() {
-            fs::create_dir_all(dir)?; // гарантируем каталог
-        }
-        // atomic-ish запись
-        let tmp = self.path.with_extension("tmp");
-        let data = serde_json::to_vec_pretty(st).expect("serialize state");
-        fs::write(&tmp, data)?;
-        fs::rename(&tmp, &self.path)?;
-        Ok(())
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bot_ids.is_empty()
+            && self.bot_names.is_empty()
+            && self.user_ids.is_empty()
+            && self.user_names.is_empty()
     }
 }
 
@@ -67,10 +100,11 @@ impl FileStore {
 pub struct AppState {
     pub cfg: Config,
 
-    /// Очередь ожидающих капчу: ключ (chat_id, user_id).
+    /// Очередь ожидающих капчу: ключ (chat_id, user_id). Правим через
+    /// `insert_pending`/`remove_pending`, чтобы изменения попадали на диск.
     pub pending: DashMap<(ChatId, u64), Pending>,
 
-    // --- WHITELISTS (в памяти, зеркалим в JSON) ---
+    // --- WHITELISTS (в памяти, зеркалим в хранилище) ---
     // Боты
     pub bot_whitelist_ids: DashSet<u64>,
     pub bot_whitelist_names: DashSet<String>, // lower-case, без '@'
@@ -78,19 +112,61 @@ pub struct AppState {
     pub user_whitelist_ids: DashSet<u64>,
     pub user_whitelist_names: DashSet<String>, // lower-case, без '@'
 
-    store: Mutex<FileStore>,
+    /// Переопределения настроек по чатам (зеркалим в хранилище).
+    pub chat_settings: DashMap<ChatId, ChatSettings>,
+
+    /// Whitelists отдельных чатов (зеркалим в хранилище).
+    pub chat_whitelists: DashMap<ChatId, ChatWhitelist>,
+
+    /// Сколько раз пользователь провалил капчу в чате (для эскалации).
+    pub failures: DashMap<(ChatId, u64), u32>,
+
+    /// Предупреждения по (chat_id, user_id); правим через `update_warnings`.
+    pub warnings: DashMap<(ChatId, u64), Vec<Warning>>,
+
+    /// Одобренные заявки: вступление по ним приходит следом (сервисным
+    /// сообщением и `chat_member`), вторая капча не нужна.
+    pub join_approved: DashMap<(ChatId, u64), Instant>,
+
+    /// Сколько текстовых сообщений написано на испытательном сроке. Только в
+    /// памяти: в хранилище срок уходит, лишь когда счёт дошёл до конца.
+    pub probation_sent: DashMap<(ChatId, u64), u32>,
+
+    /// @username бота для ссылок `t.me/<bot>?start=...` (узнаём при первой нужде).
+    pub bot_username: OnceLock<String>,
+
+    /// Кэш администраторов чатов (get_chat_administrators).
+    pub admin_cache: AdminCache,
+
+    /// Счётчики для `/metrics`.
+    pub metrics: Metrics,
+
+    /// Детектор рейдов и активные блокировки.
+    pub raid: RaidGuard,
+
+    /// Фильтр содержимого: регулярки и счёт пересылок.
+    pub filter: FilterGuard,
+
+    /// Темп вызовов Telegram API (`api::call`).
+    pub limiter: Limiter,
+
+    /// Отложенные задачи (зеркалим в хранилище). Правим через
+    /// `schedule`/`cancel_job`.
+    pub jobs: Scheduler,
+
+    store: Box<dyn Store>,
 }
 
 impl AppState {
     pub fn new(cfg: Config) -> Self {
-        let path: PathBuf = std::env::var("STATE_FILE")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("data/state.json"));
-        let store = FileStore::new(path);
+        let store = storage::open_from_env().expect("open state storage (STORAGE/STATE_FILE)");
 
-        let persisted = store.load().unwrap_or_default();
+        let persisted = store.load().unwrap_or_else(|e| {
+            warn!("Failed to load state: {e:#}");
+            Default::default()
+        });
 
-        // Восстанавливаем наборы из файла
+        // Восстанавливаем наборы из хранилища
         let bots_ids = DashSet::new();
         for id in persisted.bot_whitelist_ids {
             bots_ids.insert(id);
@@ -109,20 +185,59 @@ impl AppState {
             users_names.insert(n.to_lowercase());
         }
 
+        let chat_settings = DashMap::new();
+        for (chat, cs) in persisted.chat_settings {
+            chat_settings.insert(ChatId(chat), cs);
+        }
+        let chat_whitelists = DashMap::new();
+        for (chat, wl) in persisted.chat_whitelists {
+            chat_whitelists.insert(ChatId(chat), wl);
+        }
+        let pending = DashMap::new();
+        for rec in persisted.pending {
+            pending.insert((ChatId(rec.chat_id), rec.pending.user), rec.pending);
+        }
+        let failures = DashMap::new();
+        for rec in persisted.failures {
+            failures.insert((ChatId(rec.chat_id), rec.user), rec.count);
+        }
+        let warnings = DashMap::new();
+        for rec in persisted.warnings {
+            warnings.insert((ChatId(rec.chat_id), rec.user), rec.warnings);
+        }
+        let jobs = Scheduler::default();
+        for sj in persisted.jobs {
+            jobs.insert(sj);
+        }
+        let admin_cache = AdminCache::new(Duration::from_secs(cfg.admin_cache_ttl_secs));
+
         Self {
             cfg,
-            pending: DashMap::new(),
+            pending,
             bot_whitelist_ids: bots_ids,
             bot_whitelist_names: bots_names,
             user_whitelist_ids: users_ids,
             user_whitelist_names: users_names,
-            store: Mutex::new(store),
+            chat_settings,
+            chat_whitelists,
+            failures,
+            warnings,
+            join_approved: DashMap::new(),
+            probation_sent: DashMap::new(),
+            bot_username: OnceLock::new(),
+            admin_cache,
+            metrics: Metrics::default(),
+            raid: RaidGuard::default(),
+            filter: FilterGuard::default(),
+            limiter: Limiter::default(),
+            jobs,
+            store,
         }
     }
 
     #[inline]
-    pub fn timeout(&self) -> Duration {
-        Duration::from_secs(self.cfg.captcha_timeout_secs)
+    pub fn timeout(&self, chat: ChatId) -> Duration {
+        Duration::from_secs(self.settings(chat).captcha_timeout_secs)
     }
 
     #[inline]
@@ -130,87 +245,369 @@ impl AppState {
         (chat, user.0)
     }
 
+    // ---------- PENDING ----------
+
+    /// Добавить ожидание капчи и сохранить. Возвращает прежнее, если было.
+    pub fn insert_pending(&self, key: (ChatId, u64), p: Pending) -> Option<Pending> {
+        self.save("pending", self.store.set_pending(key, Some(&p)));
+        self.pending.insert(key, p)
+    }
+
+    /// Забрать ожидание капчи (решена/истекла) и сохранить; таймер
+    /// капчи снимается вместе с ним.
+    pub fn remove_pending(&self, key: &(ChatId, u64)) -> Option<Pending> {
+        let removed = self.pending.remove(key).map(|(_, p)| p);
+        if removed.is_some() {
+            self.save("pending", self.store.set_pending(*key, None));
+            self.cancel_job(&scheduler::captcha_key(key.0, key.1));
+        }
+        removed
+    }
+
+    /// Запомнить сообщение пользователя, ожидающего капчу. Только в памяти:
+    /// при флуде не переписываем хранилище на каждое сообщение, а на диск
+    /// список попадёт со следующим `update_pending`.
+    pub fn track_pending_message(&self, key: &(ChatId, u64), message_id: i32) {
+        if let Some(mut p) = self.pending.get_mut(key) {
+            p.user_message_ids.push(message_id);
+        }
+    }
+
+    /// Изменить ожидание капчи на месте и сохранить. `None` — его уже нет.
+    pub fn update_pending(
+        &self,
+        key: &(ChatId, u64),
+        f: impl FnOnce(&mut Pending),
+    ) -> Option<Pending> {
+        let updated = self.pending.get_mut(key).map(|mut p| {
+            f(&mut p);
+            p.clone()
+        });
+        if let Some(p) = &updated {
+            self.save("pending", self.store.set_pending(*key, Some(p)));
+        }
+        updated
+    }
+
+    /// Ожидание капчи в личке по ответу из приватного чата. `nonce` —
+    /// метка нажатой кнопки; без неё — любая ожидающая заявка пользователя.
+    pub fn find_dm_pending(&self, user: UserId, nonce: Option<&str>) -> Option<(ChatId, u64)> {
+        self.pending
+            .iter()
+            .find(|e| e.key().1 == user.0 && e.via_dm && nonce.is_none_or(|n| n == e.nonce))
+            .map(|e| *e.key())
+    }
+
+    /// Ожидание капчи по метке deep link.
+    pub fn find_by_token(&self, token: &str) -> Option<(ChatId, u64)> {
+        self.pending
+            .iter()
+            .find(|e| !e.token.is_empty() && e.token == token)
+            .map(|e| *e.key())
+    }
+
+    /// Запомнить одобренную заявку (см. `join_approved`).
+    pub fn mark_join_approved(&self, key: (ChatId, u64)) {
+        let now = Instant::now();
+        self.join_approved
+            .retain(|_, at| now.duration_since(*at) < JOIN_APPROVED_TTL);
+        self.join_approved.insert(key, now);
+    }
+
+    /// Участник только что вошёл по одобренной ботом заявке.
+    pub fn is_join_approved(&self, key: &(ChatId, u64)) -> bool {
+        self.join_approved
+            .get(key)
+            .is_some_and(|at| at.elapsed() < JOIN_APPROVED_TTL)
+    }
+
+    /// Учесть ещё один провал капчи; возвращает общее число провалов.
+    pub fn record_failure(&self, key: (ChatId, u64)) -> u32 {
+        let count = {
+            let mut c = self.failures.entry(key).or_default();
+            *c += 1;
+            *c
+        };
+        self.save("failures", self.store.set_failures(key, count));
+        count
+    }
+
+    /// Изменить предупреждения пользователя и сохранить. Сначала выкидываем
+    /// сгоревшие (старше `expire`, если он задан).
+    pub fn update_warnings<R>(
+        &self,
+        key: (ChatId, u64),
+        expire: Option<ChronoDuration>,
+        f: impl FnOnce(&mut Vec<Warning>) -> R,
+    ) -> R {
+        let (res, before, after) = {
+            let mut list = self.warnings.entry(key).or_default();
+            let before = list.clone();
+            if let Some(ttl) = expire {
+                let cutoff = Utc::now() - ttl;
+                list.retain(|w| w.at > cutoff);
+            }
+            let res = f(&mut list);
+            (res, before, list.clone())
+        };
+        self.warnings.remove_if(&key, |_, l| l.is_empty());
+        if before != after {
+            self.save("warnings", self.store.set_warnings(key, &after));
+        }
+        res
+    }
+
+    /// Действующие предупреждения пользователя (без сгоревших). Только
+    /// чтение: хранилище не трогаем, сгоревшие выкинет `update_warnings`.
+    pub fn warnings(&self, key: (ChatId, u64), expire: Option<ChronoDuration>) -> Vec<Warning> {
+        let cutoff = expire.map(|ttl| Utc::now() - ttl);
+        self.warnings
+            .get(&key)
+            .map(|l| {
+                l.iter()
+                    .filter(|w| cutoff.is_none_or(|c| w.at > c))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     // ---------- BOT WL ----------
 
-    /// Разрешён ли бот (по id или по @username, если он есть).
+    /// Разрешён ли бот в чате (общий список или список чата; по id или @username).
     #[inline]
-    pub fn is_bot_allowed_user(&self, user: &User) -> bool {
+    pub fn is_bot_allowed_user(&self, chat: ChatId, user: &User) -> bool {
         if self.bot_whitelist_ids.contains(&user.id.0) {
             return true;
         }
         if let Some(name) = &user.username {
-            return self.bot_whitelist_names.contains(&name.to_lowercase());
+            if self.bot_whitelist_names.contains(&name.to_lowercase()) {
+                return true;
+            }
         }
-        false
+        self.chat_whitelists
+            .get(&chat)
+            .is_some_and(|wl| wl.allows_bot(user))
     }
 
-    pub fn allow_bot_id(&self, id: u64) {
-        self.bot_whitelist_ids.insert(id);
-        self.persist();
+    pub fn allow_bot_id(&self, scope: WlScope, id: u64) {
+        match scope {
+            WlScope::Global => {
+                self.bot_whitelist_ids.insert(id);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.bot_ids.insert(id);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::BotId(id), true);
     }
-    pub fn deny_bot_id(&self, id: u64) {
-        self.bot_whitelist_ids.remove(&id);
-        self.persist();
+    pub fn deny_bot_id(&self, scope: WlScope, id: u64) {
+        match scope {
+            WlScope::Global => {
+                self.bot_whitelist_ids.remove(&id);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.bot_ids.remove(&id);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::BotId(id), false);
     }
-    pub fn allow_bot_username<S: AsRef<str>>(&self, name: S) {
-        self.bot_whitelist_names.insert(normalize_username(name));
-        self.persist();
+    pub fn allow_bot_username<S: AsRef<str>>(&self, scope: WlScope, name: S) {
+        let n = normalize_username(name);
+        match scope {
+            WlScope::Global => {
+                self.bot_whitelist_names.insert(n.clone());
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.bot_names.insert(n.clone());
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::BotName(&n), true);
     }
-    pub fn deny_bot_username<S: AsRef<str>>(&self, name: S) {
+    pub fn deny_bot_username<S: AsRef<str>>(&self, scope: WlScope, name: S) {
         let n = normalize_username(name);
-        self.bot_whitelist_names.remove(&n);
-        self.persist();
+        match scope {
+            WlScope::Global => {
+                self.bot_whitelist_names.remove(&n);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.bot_names.remove(&n);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::BotName(&n), false);
     }
 
     // ---------- HUMAN WL ----------
 
-    /// Разрешён ли пользователь (по id или по @username).
+    /// Разрешён ли пользователь в чате (общий список или список чата).
     #[inline]
-    pub fn is_user_allowed(&self, user: &User) -> bool {
+    pub fn is_user_allowed(&self, chat: ChatId, user: &User) -> bool {
         if self.user_whitelist_ids.contains(&user.id.0) {
             return true;
         }
         if let Some(u) = &user.username {
-            return self.user_whitelist_names.contains(&u.to_lowercase());
+            if self.user_whitelist_names.contains(&u.to_lowercase()) {
+                return true;
+            }
         }
-        false
+        self.chat_whitelists
+            .get(&chat)
+            .is_some_and(|wl| wl.allows_user(user))
     }
 
     #[inline]
-    pub fn allow_user_id(&self, id: u64) {
-        self.user_whitelist_ids.insert(id);
-        self.persist();
+    pub fn allow_user_id(&self, scope: WlScope, id: u64) {
+        match scope {
+            WlScope::Global => {
+                self.user_whitelist_ids.insert(id);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.user_ids.insert(id);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::UserId(id), true);
     }
 
     #[inline]
-    pub fn deny_user_id(&self, id: u64) {
-        self.user_whitelist_ids.remove(&id);
-        self.persist();
+    pub fn deny_user_id(&self, scope: WlScope, id: u64) {
+        match scope {
+            WlScope::Global => {
+                self.user_whitelist_ids.remove(&id);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.user_ids.remove(&id);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::UserId(id), false);
     }
 
     #[inline]
-    pub fn allow_username<S: AsRef<str>>(&self, name: S) {
-        self.user_whitelist_names.insert(normalize_username(name));
-        self.persist();
+    pub fn allow_username<S: AsRef<str>>(&self, scope: WlScope, name: S) {
+        let n = normalize_username(name);
+        match scope {
+            WlScope::Global => {
+                self.user_whitelist_names.insert(n.clone());
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.user_names.insert(n.clone());
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::UserName(&n), true);
     }
 
     #[inline]
-    pub fn deny_username<S: AsRef<str>>(&self, name: S) {
+    pub fn deny_username<S: AsRef<str>>(&self, scope: WlScope, name: S) {
         let n = normalize_username(name);
-        self.user_whitelist_names.remove(&n);
-        self.persist();
+        match scope {
+            WlScope::Global => {
+                self.user_whitelist_names.remove(&n);
+            }
+            WlScope::Chat(c) => self.update_chat_whitelist(c, |wl| {
+                wl.user_names.remove(&n);
+            }),
+        }
+        self.save_whitelisted(scope, WlEntry::UserName(&n), false);
+    }
+
+    /// Изменить whitelist чата в памяти. Пустые записи удаляются.
+    fn update_chat_whitelist(&self, chat: ChatId, f: impl FnOnce(&mut ChatWhitelist)) {
+        {
+            let mut entry = self.chat_whitelists.entry(chat).or_default();
+            f(&mut entry);
+        }
+        self.chat_whitelists.remove_if(&chat, |_, wl| wl.is_empty());
     }
 
-    /// Снимок и запись на диск.
-    fn persist(&self) {
-        let snapshot = PersistentState {
-            bot_whitelist_ids: self.bot_whitelist_ids.iter().map(|x| *x).collect(),
-            bot_whitelist_usernames: self.bot_whitelist_names.iter().map(|s| s.clone()).collect(),
-            user_whitelist_ids: self.user_whitelist_ids.iter().map(|x| *x).collect(),
-            user_whitelist_usernames: self.user_whitelist_names.iter().map(|s| s.clone()).collect(),
+    fn save_whitelisted(&self, scope: WlScope, entry: WlEntry<'_>, present: bool) {
+        self.save(
+            "whitelist",
+            self.store.set_whitelisted(scope, entry, present),
+        );
+    }
+
+    // ---------- ПЕР-ЧАТ НАСТРОЙКИ ----------
+
+    /// Эффективные настройки чата (переопределения поверх `Config`).
+    pub fn settings(&self, chat: ChatId) -> Settings {
+        let mut s = match self.chat_settings.get(&chat) {
+            Some(cs) => cs.resolve(&self.cfg),
+            None => ChatSettings::default().resolve(&self.cfg),
+        };
+        // Во время блокировки (рейд) — строже, чем настроено.
+        if self.raid.is_locked(chat) {
+            self.cfg.raid.apply(&mut s);
+        }
+        s
+    }
+
+    /// Изменить переопределения чата и сохранить. Пустые записи удаляются.
+    pub fn update_chat_settings(&self, chat: ChatId, f: impl FnOnce(&mut ChatSettings)) {
+        let updated = {
+            let mut entry = self.chat_settings.entry(chat).or_default();
+            f(&mut entry);
+            entry.clone()
         };
-        if let Ok(store) = self.store.lock() {
-            let _ = store.save(&snapshot);
+        self.chat_settings.remove_if(&chat, |_, cs| cs.is_empty());
+        let cs = (!updated.is_empty()).then_some(&updated);
+        self.save("chat settings", self.store.set_chat_settings(chat, cs));
+    }
+
+    // ---------- ОТЛОЖЕННЫЕ ЗАДАЧИ ----------
+
+    /// Запланировать задачу на `at`; задача с тем же ключом переносится.
+    pub fn schedule(&self, at: DateTime<Utc>, job: Job) {
+        self.put_job(ScheduledJob::new(at, job));
+    }
+
+    /// Поставить задачу как есть (повтор после ошибки — со счётчиком).
+    pub fn put_job(&self, sj: ScheduledJob) {
+        self.save("job", self.store.set_job(&sj.job.key(), Some(&sj)));
+        self.jobs.insert(sj);
+    }
+
+    /// Отменить задачу по ключу. `false` — такой не было.
+    pub fn cancel_job(&self, key: &str) -> bool {
+        let removed = self.jobs.remove(key).is_some();
+        if removed {
+            self.save("job", self.store.set_job(key, None));
+        }
+        removed
+    }
+
+    /// Забрать созревшую задачу для исполнения.
+    pub fn take_due_job(&self, now: DateTime<Utc>) -> Option<ScheduledJob> {
+        let sj = self.jobs.pop_due(now)?;
+        self.save("job", self.store.set_job(&sj.job.key(), None));
+        Some(sj)
+    }
+
+    // ---------- AUDIT ----------
+
+    /// Записать действие модерации в журнал (и в лог).
+    pub fn record(&self, entry: AuditEntry) {
+        info!(
+            "AUDIT {} chat={} target={} actor={:?} reason={}",
+            entry.action.as_str(),
+            entry.chat_id,
+            entry.target,
+            entry.actor,
+            entry.reason
+        );
+        self.save("audit", self.store.append_audit(&entry));
+    }
+
+    /// Страница журнала (от новых к старым).
+    pub fn audit_log(&self, chat: Option<ChatId>, offset: usize, limit: usize) -> Vec<AuditEntry> {
+        self.store.audit(chat, offset, limit).unwrap_or_else(|e| {
+            warn!("Failed to read audit log: {e:#}");
+            Vec::new()
+        })
+    }
+
+    /// Ошибки записи не роняют обработчик — только в лог.
+    fn save(&self, what: &str, res: anyhow::Result<()>) {
+        if let Err(e) = res {
+            warn!("Failed to persist {what}: {e:#}");
         }
     }
 }
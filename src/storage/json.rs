@@ -0,0 +1,301 @@
+//! JSON-файл: держим снимок в памяти и на каждое изменение перезаписываем файл.
+//! Журнал модерации — отдельный `audit.jsonl` рядом (одна запись на строку).
+
+use super::*;
+use std::io::Write;
+use std::sync::Mutex;
+use std::{fs, io};
+
+/// Простое файловое хранилище JSON.
+pub struct JsonStore {
+    path: PathBuf,
+    audit_path: PathBuf,
+    state: Mutex<PersistentState>,
+}
+
+impl JsonStore {
+    /// Открыть (и прочитать) файл; отсутствующий файл — пустое состояние.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let state = read_file(&path)?;
+        Ok(Self {
+            audit_path: audit_path(&path),
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PersistentState)) -> Result<()> {
+        let mut st = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("json store lock poisoned"))?;
+        f(&mut st);
+        self.save(&st)?;
+        Ok(())
+    }
+
+    fn save(&self, st: &PersistentState) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?; // гарантируем каталог
+        }
+        // atomic-ish запись
+        let tmp = self.path.with_extension("tmp");
+        let data = serde_json::to_vec_pretty(st).expect("serialize state");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Прочитать `state.json`. Битый файл — пустое состояние (как и раньше).
+pub(super) fn read_file(path: &std::path::Path) -> io::Result<PersistentState> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s).unwrap_or_default()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PersistentState::default()),
+        Err(e) => Err(e),
+    }
+}
+
+impl Store for JsonStore {
+    fn load(&self) -> Result<PersistentState> {
+        let st = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("json store lock poisoned"))?;
+        Ok(st.clone())
+    }
+
+    fn set_whitelisted(&self, scope: WlScope, entry: WlEntry<'_>, present: bool) -> Result<()> {
+        self.update(|st| match scope {
+            WlScope::Global => match entry {
+                WlEntry::BotId(id) => set_in_vec(&mut st.bot_whitelist_ids, id, present),
+                WlEntry::BotName(n) => {
+                    set_in_vec(&mut st.bot_whitelist_usernames, n.to_string(), present)
+                }
+                WlEntry::UserId(id) => set_in_vec(&mut st.user_whitelist_ids, id, present),
+                WlEntry::UserName(n) => {
+                    set_in_vec(&mut st.user_whitelist_usernames, n.to_string(), present)
+                }
+            },
+            WlScope::Chat(chat) => {
+                let wl = st.chat_whitelists.entry(chat.0).or_default();
+                match (entry, present) {
+                    (WlEntry::BotId(id), true) => wl.bot_ids.insert(id),
+                    (WlEntry::BotId(id), false) => wl.bot_ids.remove(&id),
+                    (WlEntry::BotName(n), true) => wl.bot_names.insert(n.to_string()),
+                    (WlEntry::BotName(n), false) => wl.bot_names.remove(n),
+                    (WlEntry::UserId(id), true) => wl.user_ids.insert(id),
+                    (WlEntry::UserId(id), false) => wl.user_ids.remove(&id),
+                    (WlEntry::UserName(n), true) => wl.user_names.insert(n.to_string()),
+                    (WlEntry::UserName(n), false) => wl.user_names.remove(n),
+                };
+                if wl.is_empty() {
+                    st.chat_whitelists.remove(&chat.0);
+                }
+            }
+        })
+    }
+
+    fn set_chat_settings(&self, chat: ChatId, cs: Option<&ChatSettings>) -> Result<()> {
+        self.update(|st| match cs {
+            Some(cs) => {
+                st.chat_settings.insert(chat.0, cs.clone());
+            }
+            None => {
+                st.chat_settings.remove(&chat.0);
+            }
+        })
+    }
+
+    fn set_pending(&self, key: (ChatId, u64), p: Option<&Pending>) -> Result<()> {
+        self.update(|st| {
+            st.pending
+                .retain(|r| !(r.chat_id == key.0 .0 && r.pending.user == key.1));
+            if let Some(p) = p {
+                st.pending.push(PendingRecord {
+                    chat_id: key.0 .0,
+                    pending: p.clone(),
+                });
+            }
+        })
+    }
+
+    fn set_failures(&self, key: (ChatId, u64), count: u32) -> Result<()> {
+        self.update(|st| {
+            st.failures
+                .retain(|r| !(r.chat_id == key.0 .0 && r.user == key.1));
+            if count > 0 {
+                st.failures.push(FailureRecord {
+                    chat_id: key.0 .0,
+                    user: key.1,
+                    count,
+                });
+            }
+        })
+    }
+
+    fn set_warnings(&self, key: (ChatId, u64), warnings: &[Warning]) -> Result<()> {
+        self.update(|st| {
+            st.warnings
+                .retain(|r| !(r.chat_id == key.0 .0 && r.user == key.1));
+            if !warnings.is_empty() {
+                st.warnings.push(WarningRecord {
+                    chat_id: key.0 .0,
+                    user: key.1,
+                    warnings: warnings.to_vec(),
+                });
+            }
+        })
+    }
+
+    fn set_job(&self, key: &str, job: Option<&ScheduledJob>) -> Result<()> {
+        self.update(|st| {
+            st.jobs.retain(|j| j.job.key() != key);
+            if let Some(j) = job {
+                st.jobs.push(j.clone());
+            }
+        })
+    }
+
+    fn append_audit(&self, entry: &AuditEntry) -> Result<()> {
+        // под тем же локом, чтобы строки не перемешивались
+        let _guard = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("json store lock poisoned"))?;
+        if let Some(dir) = self.audit_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)?;
+        writeln!(f, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    fn audit(&self, chat: Option<ChatId>, offset: usize, limit: usize) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .read_audit()?
+            .into_iter()
+            .rev()
+            .filter(|e| chat.is_none_or(|c| c.0 == e.chat_id))
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+}
+
+impl JsonStore {
+    fn read_audit(&self) -> io::Result<Vec<AuditEntry>> {
+        read_audit_file(&self.audit_path)
+    }
+}
+
+/// Журнал лежит рядом с файлом состояния.
+pub(super) fn audit_path(state: &std::path::Path) -> PathBuf {
+    state.with_file_name("audit.jsonl")
+}
+
+/// Прочитать журнал (от старых к новым); нет файла — пустой.
+pub(super) fn read_audit_file(path: &std::path::Path) -> io::Result<Vec<AuditEntry>> {
+    match fs::read_to_string(path) {
+        // битые строки пропускаем
+        Ok(s) => Ok(s
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn set_in_vec<T: PartialEq>(v: &mut Vec<T>, item: T, present: bool) {
+    if present {
+        if !v.contains(&item) {
+            v.push(item);
+        }
+    } else {
+        v.retain(|x| *x != item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Отдельный каталог на тест: рядом с файлом ложится audit.jsonl.
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ranger-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("state.json")
+    }
+
+    #[test]
+    fn changes_survive_reopen() {
+        let path = temp_path("reopen");
+        let _ = fs::remove_file(&path);
+
+        let store = JsonStore::open(&path).unwrap();
+        store
+            .set_whitelisted(WlScope::Global, WlEntry::UserName("bob"), true)
+            .unwrap();
+        store
+            .set_whitelisted(WlScope::Chat(ChatId(-5)), WlEntry::BotId(7), true)
+            .unwrap();
+        store
+            .set_whitelisted(WlScope::Global, WlEntry::UserName("bob"), true)
+            .unwrap();
+
+        let st = JsonStore::open(&path).unwrap().load().unwrap();
+        assert_eq!(st.user_whitelist_usernames, vec!["bob".to_string()]);
+        assert!(st.chat_whitelists[&-5].bot_ids.contains(&7));
+
+        store
+            .set_whitelisted(WlScope::Chat(ChatId(-5)), WlEntry::BotId(7), false)
+            .unwrap();
+        let st = JsonStore::open(&path).unwrap().load().unwrap();
+        assert!(st.chat_whitelists.is_empty());
+
+        store.set_failures((ChatId(-5), 9), 2).unwrap();
+        store.set_failures((ChatId(-5), 9), 3).unwrap();
+        let st = JsonStore::open(&path).unwrap().load().unwrap();
+        assert_eq!(st.failures.len(), 1);
+        assert_eq!(st.failures[0].count, 3);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn audit_pages_newest_first() {
+        use crate::audit::AuditAction;
+
+        let path = temp_path("audit");
+        let store = JsonStore::open(&path).unwrap();
+        let _ = fs::remove_file(&store.audit_path);
+        for i in 0..5 {
+            let chat = ChatId(if i % 2 == 0 { -1 } else { -2 });
+            store
+                .append_audit(&AuditEntry::new(
+                    chat,
+                    None,
+                    i.to_string(),
+                    AuditAction::Ban,
+                    "",
+                ))
+                .unwrap();
+        }
+
+        let page: Vec<String> = store
+            .audit(Some(ChatId(-1)), 0, 2)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.target)
+            .collect();
+        assert_eq!(page, vec!["4", "2"]);
+        assert_eq!(store.audit(None, 4, 10).unwrap().len(), 1);
+
+        let _ = fs::remove_file(&store.audit_path);
+    }
+}
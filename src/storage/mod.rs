@@ -0,0 +1,182 @@
+//! Хранилище состояния за трейтом `Store`.
+//! - `JsonStore` — один JSON-файл (`STATE_FILE`), как и раньше; по умолчанию.
+//! - `SqliteStore` — встроенный SQLite (`STORAGE=sqlite`, `SQLITE_PATH`):
+//!   точечные INSERT/DELETE вместо перезаписи всего файла. При первом запуске
+//!   переносит данные из существующего `state.json` и журнал из `audit.jsonl`.
+//!
+//! В памяти всё живёт в `AppState`; сюда уходят только изменения.
+//! Журнал модерации (`audit`) в память не грузится — только дописывается
+//! и читается постранично.
+
+mod json;
+mod sqlite;
+
+pub use json::JsonStore;
+pub use sqlite::SqliteStore;
+
+use crate::audit::AuditEntry;
+use crate::config::ChatSettings;
+use crate::scheduler::ScheduledJob;
+use crate::state::{ChatWhitelist, Pending, WlScope};
+use crate::warns::Warning;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use teloxide::types::ChatId;
+
+/// Полный снимок состояния (формат `state.json`; его же отдаёт `load`).
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PersistentState {
+    // Боты
+    pub bot_whitelist_ids: Vec<u64>,
+    pub bot_whitelist_usernames: Vec<String>, // lower-case, без '@'
+    // Люди
+    pub user_whitelist_ids: Vec<u64>,
+    pub user_whitelist_usernames: Vec<String>, // lower-case, без '@'
+    // Переопределения настроек по чатам
+    #[serde(default)]
+    pub chat_settings: HashMap<i64, ChatSettings>,
+    // Whitelists отдельных чатов
+    #[serde(default)]
+    pub chat_whitelists: HashMap<i64, ChatWhitelist>,
+    // Незавершённые капчи
+    #[serde(default)]
+    pub pending: Vec<PendingRecord>,
+    // Счётчики проваленных капч (для эскалации)
+    #[serde(default)]
+    pub failures: Vec<FailureRecord>,
+    // Отложенные задачи
+    #[serde(default)]
+    pub jobs: Vec<ScheduledJob>,
+    // Предупреждения участникам
+    #[serde(default)]
+    pub warnings: Vec<WarningRecord>,
+}
+
+/// Pending на диске: плюс чат, из ключа `(chat_id, user_id)`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingRecord {
+    pub chat_id: i64,
+    #[serde(flatten)]
+    pub pending: Pending,
+}
+
+/// Сколько раз пользователь провалил капчу в чате.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureRecord {
+    pub chat_id: i64,
+    pub user: u64,
+    pub count: u32,
+}
+
+/// Предупреждения пользователя в чате (ещё не сгоревшие на момент записи).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarningRecord {
+    pub chat_id: i64,
+    pub user: u64,
+    pub warnings: Vec<Warning>,
+}
+
+/// Одна запись whitelist'а. Имена — lower-case, без '@'.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WlEntry<'a> {
+    BotId(u64),
+    BotName(&'a str),
+    UserId(u64),
+    UserName(&'a str),
+}
+
+/// Бэкенд хранилища. Методы синхронные и быстрые (локальный диск).
+pub trait Store: Send + Sync {
+    /// Прочитать всё состояние при старте.
+    fn load(&self) -> Result<PersistentState>;
+
+    /// Добавить (`present = true`) или убрать запись whitelist'а.
+    fn set_whitelisted(&self, scope: WlScope, entry: WlEntry<'_>, present: bool) -> Result<()>;
+
+    /// Сохранить переопределения чата; `None` — удалить.
+    fn set_chat_settings(&self, chat: ChatId, cs: Option<&ChatSettings>) -> Result<()>;
+
+    /// Сохранить ожидание капчи; `None` — удалить.
+    fn set_pending(&self, key: (ChatId, u64), p: Option<&Pending>) -> Result<()>;
+
+    /// Сохранить число провалов капчи; 0 — удалить.
+    fn set_failures(&self, key: (ChatId, u64), count: u32) -> Result<()>;
+
+    /// Сохранить предупреждения пользователя; пустой список — удалить.
+    fn set_warnings(&self, key: (ChatId, u64), warnings: &[Warning]) -> Result<()>;
+
+    /// Сохранить отложенную задачу под ключом; `None` — удалить.
+    fn set_job(&self, key: &str, job: Option<&ScheduledJob>) -> Result<()>;
+
+    /// Дописать запись в журнал модерации.
+    fn append_audit(&self, entry: &AuditEntry) -> Result<()>;
+
+    /// Страница журнала, от новых к старым; `chat = None` — все чаты.
+    fn audit(&self, chat: Option<ChatId>, offset: usize, limit: usize) -> Result<Vec<AuditEntry>>;
+}
+
+/// Выбор бэкенда по `STORAGE` (`json` | `sqlite`).
+pub fn open_from_env() -> Result<Box<dyn Store>> {
+    let json_path: PathBuf = std::env::var("STATE_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("data/state.json"));
+
+    let kind = std::env::var("STORAGE").unwrap_or_default();
+    match kind.trim().to_ascii_lowercase().as_str() {
+        "sqlite" | "sqlite3" | "db" => {
+            let db_path: PathBuf = std::env::var("SQLITE_PATH")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("data/state.db"));
+            Ok(Box::new(SqliteStore::open(db_path, json_path)?))
+        }
+        _ => Ok(Box::new(JsonStore::open(json_path)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CaptchaMode;
+    use chrono::Utc;
+
+    #[test]
+    fn pending_survives_json_roundtrip() {
+        let st = PersistentState {
+            pending: vec![PendingRecord {
+                chat_id: -100,
+                pending: Pending {
+                    user: 42,
+                    captcha_msg_id: 7,
+                    deadline: Utc::now(),
+                    user_message_ids: vec![8, 9],
+                    captcha_mode: CaptchaMode::Math2,
+                    expected_answer: Some("12".into()),
+                    attempts: 0,
+                    nonce: String::new(),
+                    via_dm: false,
+                    token: String::new(),
+                },
+            }],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&st).unwrap();
+        let back: PersistentState = serde_json::from_str(&json).unwrap();
+        let rec = &back.pending[0];
+        assert_eq!(rec.chat_id, -100);
+        assert_eq!(rec.pending.user, 42);
+        assert_eq!(rec.pending.user_message_ids, vec![8, 9]);
+        assert_eq!(rec.pending.captcha_mode, CaptchaMode::Math2);
+        assert_eq!(rec.pending.deadline, st.pending[0].pending.deadline);
+    }
+
+    #[test]
+    fn old_state_file_still_loads() {
+        let json = r#"{"bot_whitelist_ids":[1],"bot_whitelist_usernames":[],
+                       "user_whitelist_ids":[],"user_whitelist_usernames":["bob"]}"#;
+        let st: PersistentState = serde_json::from_str(json).unwrap();
+        assert_eq!(st.bot_whitelist_ids, vec![1]);
+        assert!(st.pending.is_empty());
+    }
+}
@@ -0,0 +1,672 @@
+//! Встроенный SQLite. Whitelist — строками таблицы; настройки чата, pending,
+//! предупреждения и отложенные задачи — JSON-колонкой (структуры растут, а схема остаётся прежней).
+
+use super::*;
+use chrono::{DateTime, Utc};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// В таблице `whitelist` общий список хранится под chat_id = 0.
+const GLOBAL_CHAT: i64 = 0;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS whitelist (
+    chat_id INTEGER NOT NULL,
+    kind    TEXT    NOT NULL,
+    value   TEXT    NOT NULL,
+    PRIMARY KEY (chat_id, kind, value)
+);
+CREATE TABLE IF NOT EXISTS chat_settings (
+    chat_id INTEGER PRIMARY KEY,
+    data    TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending (
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    data    TEXT    NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS failures (
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    count   INTEGER NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS warnings (
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    data    TEXT    NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS jobs (
+    key  TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS audit (
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts      TEXT    NOT NULL,
+    chat_id INTEGER NOT NULL,
+    actor   INTEGER,
+    target  TEXT    NOT NULL,
+    action  TEXT    NOT NULL,
+    reason  TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS audit_chat ON audit (chat_id, id);
+";
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Открыть БД (создав схему). Если БД новая, а `legacy_json` существует —
+    /// переносим его содержимое (один раз, отмечаем в `meta`).
+    pub fn open(path: impl AsRef<Path>, legacy_json: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let store = Self::from_connection(Connection::open(path)?)?;
+        store.migrate_from_json(legacy_json.as_ref())?;
+        Ok(store)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| anyhow::anyhow!("sqlite store lock poisoned"))
+    }
+
+    fn migrate_from_json(&self, json: &Path) -> Result<()> {
+        let mut conn = self.conn()?;
+        let done: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'json_migrated'",
+                [],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if done.is_some() {
+            return Ok(());
+        }
+
+        let st = json::read_file(json)?;
+        let tx = conn.transaction()?;
+        for id in &st.bot_whitelist_ids {
+            insert_wl(&tx, GLOBAL_CHAT, WlEntry::BotId(*id))?;
+        }
+        for n in &st.bot_whitelist_usernames {
+            insert_wl(&tx, GLOBAL_CHAT, WlEntry::BotName(n))?;
+        }
+        for id in &st.user_whitelist_ids {
+            insert_wl(&tx, GLOBAL_CHAT, WlEntry::UserId(*id))?;
+        }
+        for n in &st.user_whitelist_usernames {
+            insert_wl(&tx, GLOBAL_CHAT, WlEntry::UserName(n))?;
+        }
+        for (chat, wl) in &st.chat_whitelists {
+            for id in &wl.bot_ids {
+                insert_wl(&tx, *chat, WlEntry::BotId(*id))?;
+            }
+            for n in &wl.bot_names {
+                insert_wl(&tx, *chat, WlEntry::BotName(n))?;
+            }
+            for id in &wl.user_ids {
+                insert_wl(&tx, *chat, WlEntry::UserId(*id))?;
+            }
+            for n in &wl.user_names {
+                insert_wl(&tx, *chat, WlEntry::UserName(n))?;
+            }
+        }
+        for (chat, cs) in &st.chat_settings {
+            tx.execute(
+                "INSERT OR REPLACE INTO chat_settings (chat_id, data) VALUES (?1, ?2)",
+                params![chat, serde_json::to_string(cs)?],
+            )?;
+        }
+        for rec in &st.pending {
+            tx.execute(
+                "INSERT OR REPLACE INTO pending (chat_id, user_id, data) VALUES (?1, ?2, ?3)",
+                params![
+                    rec.chat_id,
+                    rec.pending.user as i64,
+                    serde_json::to_string(&rec.pending)?
+                ],
+            )?;
+        }
+        for rec in &st.failures {
+            tx.execute(
+                "INSERT OR REPLACE INTO failures (chat_id, user_id, count) VALUES (?1, ?2, ?3)",
+                params![rec.chat_id, rec.user as i64, rec.count],
+            )?;
+        }
+        for rec in &st.warnings {
+            tx.execute(
+                "INSERT OR REPLACE INTO warnings (chat_id, user_id, data) VALUES (?1, ?2, ?3)",
+                params![
+                    rec.chat_id,
+                    rec.user as i64,
+                    serde_json::to_string(&rec.warnings)?
+                ],
+            )?;
+        }
+        for job in &st.jobs {
+            tx.execute(
+                "INSERT OR REPLACE INTO jobs (key, data) VALUES (?1, ?2)",
+                params![job.job.key(), serde_json::to_string(job)?],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('json_migrated', ?1)",
+            params![json.display().to_string()],
+        )?;
+        tx.commit()?;
+
+        if json.exists() {
+            info!("Migrated state from {} into SQLite", json.display());
+        }
+        drop(conn);
+        self.migrate_audit(&json::audit_path(json))
+    }
+
+    /// Перенести `audit.jsonl` (один раз, отдельной отметкой: БД, переехавшие
+    /// раньше, журнал ещё не получали).
+    fn migrate_audit(&self, path: &Path) -> Result<()> {
+        let mut conn = self.conn()?;
+        let done: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'json_audit_migrated'",
+                [],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if done.is_some() {
+            return Ok(());
+        }
+
+        let entries = json::read_audit_file(path)?;
+        let tx = conn.transaction()?;
+        for e in &entries {
+            insert_audit(&tx, e)?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('json_audit_migrated', ?1)",
+            params![path.display().to_string()],
+        )?;
+        tx.commit()?;
+
+        if !entries.is_empty() {
+            info!(
+                "Migrated {} audit entries from {} into SQLite",
+                entries.len(),
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn insert_audit(conn: &Connection, entry: &AuditEntry) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO audit (ts, chat_id, actor, target, action, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            entry.ts.to_rfc3339(),
+            entry.chat_id,
+            entry.actor.map(|a| a as i64),
+            entry.target,
+            entry.action.as_str(),
+            entry.reason
+        ],
+    )
+}
+
+/// (kind, value) для строки таблицы `whitelist`.
+fn wl_row(entry: WlEntry<'_>) -> (&'static str, String) {
+    match entry {
+        WlEntry::BotId(id) => ("bot_id", id.to_string()),
+        WlEntry::BotName(n) => ("bot_name", n.to_string()),
+        WlEntry::UserId(id) => ("user_id", id.to_string()),
+        WlEntry::UserName(n) => ("user_name", n.to_string()),
+    }
+}
+
+fn insert_wl(conn: &Connection, chat: i64, entry: WlEntry<'_>) -> rusqlite::Result<usize> {
+    let (kind, value) = wl_row(entry);
+    conn.execute(
+        "INSERT OR IGNORE INTO whitelist (chat_id, kind, value) VALUES (?1, ?2, ?3)",
+        params![chat, kind, value],
+    )
+}
+
+impl Store for SqliteStore {
+    fn load(&self) -> Result<PersistentState> {
+        let conn = self.conn()?;
+        let mut st = PersistentState::default();
+
+        let mut q = conn.prepare("SELECT chat_id, kind, value FROM whitelist")?;
+        let rows = q.query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (chat, kind, value) = row?;
+            let id = value.parse::<u64>().ok();
+            if chat == GLOBAL_CHAT {
+                match (kind.as_str(), id) {
+                    ("bot_id", Some(id)) => st.bot_whitelist_ids.push(id),
+                    ("bot_name", _) => st.bot_whitelist_usernames.push(value),
+                    ("user_id", Some(id)) => st.user_whitelist_ids.push(id),
+                    ("user_name", _) => st.user_whitelist_usernames.push(value),
+                    _ => {}
+                }
+            } else {
+                let wl = st.chat_whitelists.entry(chat).or_default();
+                match (kind.as_str(), id) {
+                    ("bot_id", Some(id)) => {
+                        wl.bot_ids.insert(id);
+                    }
+                    ("bot_name", _) => {
+                        wl.bot_names.insert(value);
+                    }
+                    ("user_id", Some(id)) => {
+                        wl.user_ids.insert(id);
+                    }
+                    ("user_name", _) => {
+                        wl.user_names.insert(value);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut q = conn.prepare("SELECT chat_id, data FROM chat_settings")?;
+        let rows = q.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (chat, data) = row?;
+            if let Ok(cs) = serde_json::from_str(&data) {
+                st.chat_settings.insert(chat, cs);
+            }
+        }
+
+        let mut q = conn.prepare("SELECT chat_id, data FROM pending")?;
+        let rows = q.query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (chat_id, data) = row?;
+            if let Ok(pending) = serde_json::from_str(&data) {
+                st.pending.push(PendingRecord { chat_id, pending });
+            }
+        }
+
+        let mut q = conn.prepare("SELECT chat_id, user_id, count FROM failures")?;
+        let rows = q.query_map([], |r| {
+            Ok(FailureRecord {
+                chat_id: r.get(0)?,
+                user: r.get::<_, i64>(1)? as u64,
+                count: r.get(2)?,
+            })
+        })?;
+        for row in rows {
+            st.failures.push(row?);
+        }
+
+        let mut q = conn.prepare("SELECT chat_id, user_id, data FROM warnings")?;
+        let rows = q.query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                r.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (chat_id, user, data) = row?;
+            if let Ok(warnings) = serde_json::from_str(&data) {
+                st.warnings.push(WarningRecord {
+                    chat_id,
+                    user: user as u64,
+                    warnings,
+                });
+            }
+        }
+
+        let mut q = conn.prepare("SELECT data FROM jobs")?;
+        let rows = q.query_map([], |r| r.get::<_, String>(0))?;
+        for row in rows {
+            if let Ok(job) = serde_json::from_str(&row?) {
+                st.jobs.push(job);
+            }
+        }
+
+        Ok(st)
+    }
+
+    fn set_whitelisted(&self, scope: WlScope, entry: WlEntry<'_>, present: bool) -> Result<()> {
+        let conn = self.conn()?;
+        let chat = match scope {
+            WlScope::Global => GLOBAL_CHAT,
+            WlScope::Chat(c) => c.0,
+        };
+        if present {
+            insert_wl(&conn, chat, entry)?;
+        } else {
+            let (kind, value) = wl_row(entry);
+            conn.execute(
+                "DELETE FROM whitelist WHERE chat_id = ?1 AND kind = ?2 AND value = ?3",
+                params![chat, kind, value],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_chat_settings(&self, chat: ChatId, cs: Option<&ChatSettings>) -> Result<()> {
+        let conn = self.conn()?;
+        match cs {
+            Some(cs) => conn.execute(
+                "INSERT OR REPLACE INTO chat_settings (chat_id, data) VALUES (?1, ?2)",
+                params![chat.0, serde_json::to_string(cs)?],
+            )?,
+            None => conn.execute(
+                "DELETE FROM chat_settings WHERE chat_id = ?1",
+                params![chat.0],
+            )?,
+        };
+        Ok(())
+    }
+
+    fn set_pending(&self, key: (ChatId, u64), p: Option<&Pending>) -> Result<()> {
+        let conn = self.conn()?;
+        match p {
+            Some(p) => conn.execute(
+                "INSERT OR REPLACE INTO pending (chat_id, user_id, data) VALUES (?1, ?2, ?3)",
+                params![key.0 .0, key.1 as i64, serde_json::to_string(p)?],
+            )?,
+            None => conn.execute(
+                "DELETE FROM pending WHERE chat_id = ?1 AND user_id = ?2",
+                params![key.0 .0, key.1 as i64],
+            )?,
+        };
+        Ok(())
+    }
+
+    fn set_failures(&self, key: (ChatId, u64), count: u32) -> Result<()> {
+        let conn = self.conn()?;
+        if count > 0 {
+            conn.execute(
+                "INSERT OR REPLACE INTO failures (chat_id, user_id, count) VALUES (?1, ?2, ?3)",
+                params![key.0 .0, key.1 as i64, count],
+            )?;
+        } else {
+            conn.execute(
+                "DELETE FROM failures WHERE chat_id = ?1 AND user_id = ?2",
+                params![key.0 .0, key.1 as i64],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_warnings(&self, key: (ChatId, u64), warnings: &[Warning]) -> Result<()> {
+        let conn = self.conn()?;
+        if warnings.is_empty() {
+            conn.execute(
+                "DELETE FROM warnings WHERE chat_id = ?1 AND user_id = ?2",
+                params![key.0 .0, key.1 as i64],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO warnings (chat_id, user_id, data) VALUES (?1, ?2, ?3)",
+                params![key.0 .0, key.1 as i64, serde_json::to_string(warnings)?],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_job(&self, key: &str, job: Option<&ScheduledJob>) -> Result<()> {
+        let conn = self.conn()?;
+        match job {
+            Some(job) => conn.execute(
+                "INSERT OR REPLACE INTO jobs (key, data) VALUES (?1, ?2)",
+                params![key, serde_json::to_string(job)?],
+            )?,
+            None => conn.execute("DELETE FROM jobs WHERE key = ?1", params![key])?,
+        };
+        Ok(())
+    }
+
+    fn append_audit(&self, entry: &AuditEntry) -> Result<()> {
+        insert_audit(&*self.conn()?, entry)?;
+        Ok(())
+    }
+
+    fn audit(&self, chat: Option<ChatId>, offset: usize, limit: usize) -> Result<Vec<AuditEntry>> {
+        let conn = self.conn()?;
+        let mut q = conn.prepare(
+            "SELECT ts, chat_id, actor, target, action, reason FROM audit
+             WHERE ?1 IS NULL OR chat_id = ?1
+             ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = q.query_map(
+            params![chat.map(|c| c.0), limit as i64, offset as i64],
+            |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, i64>(1)?,
+                    r.get::<_, Option<i64>>(2)?,
+                    r.get::<_, String>(3)?,
+                    r.get::<_, String>(4)?,
+                    r.get::<_, String>(5)?,
+                ))
+            },
+        )?;
+        let mut out = Vec::new();
+        for row in rows {
+            let (ts, chat_id, actor, target, action, reason) = row?;
+            // action храним строкой; неизвестные (из будущих версий) пропускаем
+            let Ok(action) = serde_json::from_value(serde_json::Value::String(action)) else {
+                continue;
+            };
+            out.push(AuditEntry {
+                ts: DateTime::parse_from_rfc3339(&ts)?.with_timezone(&Utc),
+                chat_id,
+                actor: actor.map(|a| a as u64),
+                target,
+                action,
+                reason,
+            });
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditAction;
+    use crate::config::CaptchaMode;
+    use teloxide::types::UserId;
+
+    fn memory() -> SqliteStore {
+        SqliteStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn whitelist_settings_pending_roundtrip() {
+        let store = memory();
+        store
+            .set_whitelisted(WlScope::Global, WlEntry::BotName("helper_bot"), true)
+            .unwrap();
+        store
+            .set_whitelisted(WlScope::Chat(ChatId(-9)), WlEntry::UserId(5), true)
+            .unwrap();
+        store
+            .set_chat_settings(
+                ChatId(-9),
+                Some(&ChatSettings {
+                    captcha_mode: Some(CaptchaMode::Image),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+        let p = Pending {
+            user: 5,
+            captcha_msg_id: 1,
+            deadline: Utc::now(),
+            user_message_ids: vec![],
+            captcha_mode: CaptchaMode::Image,
+            expected_answer: Some("12345".into()),
+            attempts: 0,
+            nonce: String::new(),
+            via_dm: false,
+            token: String::new(),
+        };
+        store.set_pending((ChatId(-9), 5), Some(&p)).unwrap();
+        store.set_failures((ChatId(-9), 5), 2).unwrap();
+        let warning = Warning {
+            at: Utc::now(),
+            by: Some(1),
+            reason: "spam".into(),
+        };
+        store
+            .set_warnings((ChatId(-9), 5), std::slice::from_ref(&warning))
+            .unwrap();
+
+        let st = store.load().unwrap();
+        assert_eq!(
+            st.warnings,
+            vec![WarningRecord {
+                chat_id: -9,
+                user: 5,
+                warnings: vec![warning],
+            }]
+        );
+        assert_eq!(
+            st.failures,
+            vec![FailureRecord {
+                chat_id: -9,
+                user: 5,
+                count: 2
+            }]
+        );
+        assert_eq!(st.bot_whitelist_usernames, vec!["helper_bot".to_string()]);
+        assert!(st.chat_whitelists[&-9].user_ids.contains(&5));
+        assert_eq!(st.chat_settings[&-9].captcha_mode, Some(CaptchaMode::Image));
+        assert_eq!(st.pending.len(), 1);
+        assert_eq!(
+            st.pending[0].pending.expected_answer.as_deref(),
+            Some("12345")
+        );
+
+        store.set_pending((ChatId(-9), 5), None).unwrap();
+        store.set_chat_settings(ChatId(-9), None).unwrap();
+        store.set_warnings((ChatId(-9), 5), &[]).unwrap();
+        store
+            .set_whitelisted(WlScope::Global, WlEntry::BotName("helper_bot"), false)
+            .unwrap();
+        let st = store.load().unwrap();
+        assert!(st.pending.is_empty());
+        assert!(st.warnings.is_empty());
+        assert!(st.chat_settings.is_empty());
+        assert!(st.bot_whitelist_usernames.is_empty());
+    }
+
+    #[test]
+    fn jobs_roundtrip() {
+        use crate::scheduler::Job;
+
+        let store = memory();
+        let job = ScheduledJob::new(
+            Utc::now(),
+            Job::Unban {
+                chat_id: -9,
+                user: 5,
+            },
+        );
+        let key = job.job.key();
+        store.set_job(&key, Some(&job)).unwrap();
+        assert_eq!(store.load().unwrap().jobs, vec![job]);
+
+        store.set_job(&key, None).unwrap();
+        assert!(store.load().unwrap().jobs.is_empty());
+    }
+
+    #[test]
+    fn audit_pages_newest_first() {
+        let store = memory();
+        for i in 0..5 {
+            let chat = ChatId(if i % 2 == 0 { -1 } else { -2 });
+            let e = AuditEntry::new(
+                chat,
+                Some(UserId(1)),
+                i.to_string(),
+                AuditAction::SoftKick,
+                "t",
+            );
+            store.append_audit(&e).unwrap();
+        }
+        let page = store.audit(Some(ChatId(-1)), 1, 2).unwrap();
+        let targets: Vec<&str> = page.iter().map(|e| e.target.as_str()).collect();
+        assert_eq!(targets, vec!["2", "0"]);
+        assert_eq!(page[0].action, AuditAction::SoftKick);
+        assert_eq!(page[0].actor, Some(1));
+        assert_eq!(store.audit(None, 0, 100).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn migrates_legacy_json_once() {
+        let json = std::env::temp_dir().join(format!("ranger-{}-legacy.json", std::process::id()));
+        std::fs::write(
+            &json,
+            r#"{"bot_whitelist_ids":[11],"bot_whitelist_usernames":[],
+                "user_whitelist_ids":[],"user_whitelist_usernames":["alice"]}"#,
+        )
+        .unwrap();
+
+        let store = memory();
+        store.migrate_from_json(&json).unwrap();
+        store
+            .set_whitelisted(WlScope::Global, WlEntry::UserName("alice"), false)
+            .unwrap();
+        // повторный запуск не должен вернуть удалённое
+        store.migrate_from_json(&json).unwrap();
+
+        let st = store.load().unwrap();
+        assert_eq!(st.bot_whitelist_ids, vec![11]);
+        assert!(st.user_whitelist_usernames.is_empty());
+
+        let _ = std::fs::remove_file(&json);
+    }
+
+    #[test]
+    fn migrates_audit_log_once() {
+        let dir = std::env::temp_dir().join(format!("ranger-{}-audit-mig", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("state.json");
+        let log = json::audit_path(&json);
+        let e = AuditEntry::new(ChatId(-1), None, "7", AuditAction::SoftKick, "t");
+        std::fs::write(&log, format!("{}\n", serde_json::to_string(&e).unwrap())).unwrap();
+
+        let store = memory();
+        store.migrate_from_json(&json).unwrap();
+        store.migrate_from_json(&json).unwrap();
+        let page = store.audit(None, 0, 10).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].target, "7");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,339 @@
+// src/templates.rs
+
+//! Шаблоны приветствия и промпта капчи, которые задают админы чата.
+//! HTML (подмножество Telegram) + подстановки `{name}`.
+//!
+//! Проверка при сохранении (`sanitize`): неизвестные подстановки и теги,
+//! незакрытые/перепутанные теги — ошибка; одиночные `<`, `>`, `&` экранируются.
+//! Подстановки — только в тексте, не внутри тегов; значения экранирует
+//! вызывающий (`{rules_link}` — готовая ссылка). Шаблон после `sanitize`
+//! уже не может сломать отправку с `ParseMode::Html`.
+
+use crate::api;
+use crate::i18n::{tr, Lang};
+use crate::state::AppState;
+use std::fmt::Display;
+use teloxide::prelude::*;
+use teloxide::utils::html::escape;
+
+/// Подстановки приветствия.
+pub const WELCOME: &[&str] = &["mention", "chat_title", "timeout", "rules_link"];
+/// Подстановки промпта капчи; `{task}` — сама задача, обязательна.
+pub const CAPTCHA: &[&str] = &["mention", "chat_title", "timeout", "rules_link", "task"];
+
+/// Предел длины шаблона (символы): с запасом под подстановки до 4096.
+pub const MAX_LEN: usize = 2000;
+
+/// Теги, которые понимает Telegram в `ParseMode::Html`.
+const TAGS: &[&str] = &[
+    "b",
+    "strong",
+    "i",
+    "em",
+    "u",
+    "ins",
+    "s",
+    "strike",
+    "del",
+    "a",
+    "code",
+    "pre",
+    "tg-spoiler",
+    "span",
+    "blockquote",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    TooLong,
+    UnclosedBrace,
+    UnknownPlaceholder(String),
+    MissingPlaceholder(&'static str),
+    BadTag(String),
+    Unbalanced(String),
+    /// `{…}` внутри тега: значения подставляются только в текст.
+    PlaceholderInTag,
+}
+
+impl TemplateError {
+    /// Текст ошибки для админа (HTML).
+    pub fn describe(&self, lang: Lang) -> String {
+        let esc = teloxide::utils::html::escape;
+        match self {
+            TemplateError::TooLong => tr!(lang, "templates.too_long", max = MAX_LEN),
+            TemplateError::UnclosedBrace => tr!(lang, "templates.unclosed_brace").to_string(),
+            TemplateError::UnknownPlaceholder(name) => {
+                tr!(lang, "templates.unknown_placeholder", name = esc(name))
+            }
+            TemplateError::MissingPlaceholder(name) => {
+                tr!(lang, "templates.missing_placeholder", name = name)
+            }
+            TemplateError::BadTag(tag) => tr!(lang, "templates.bad_tag", tag = esc(tag)),
+            TemplateError::Unbalanced(tag) => tr!(lang, "templates.unbalanced", tag = esc(tag)),
+            TemplateError::PlaceholderInTag => {
+                tr!(lang, "templates.placeholder_in_tag").to_string()
+            }
+        }
+    }
+}
+
+/// Проверить шаблон и привести его к безопасному HTML.
+/// `allowed` — допустимые подстановки; из `CAPTCHA` обязательна `{task}`.
+pub fn sanitize(src: &str, allowed: &[&str]) -> Result<String, TemplateError> {
+    if src.chars().count() > MAX_LEN {
+        return Err(TemplateError::TooLong);
+    }
+
+    let mut out = String::with_capacity(src.len());
+    let mut open: Vec<String> = Vec::new();
+    let mut rest = src;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '{' => {
+                let end = rest.find('}').ok_or(TemplateError::UnclosedBrace)?;
+                let name = &rest[1..end];
+                if !allowed.contains(&name) {
+                    return Err(TemplateError::UnknownPlaceholder(format!("{{{name}}}")));
+                }
+                out.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+            }
+            '&' => {
+                let len = entity_len(rest);
+                if len > 0 {
+                    out.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                } else {
+                    out.push_str("&amp;");
+                    rest = &rest[1..];
+                }
+            }
+            '<' if looks_like_tag(rest) => {
+                let end = rest.find('>').ok_or_else(|| {
+                    TemplateError::BadTag(rest.chars().take(16).collect::<String>())
+                })?;
+                let inner = &rest[1..end];
+                if inner.contains('{') {
+                    return Err(TemplateError::PlaceholderInTag);
+                }
+                if let Some(name) = inner.strip_prefix('/') {
+                    let name = name.trim().to_ascii_lowercase();
+                    if open.pop().as_deref() != Some(name.as_str()) {
+                        return Err(TemplateError::Unbalanced(name));
+                    }
+                } else {
+                    open.push(check_open_tag(inner)?);
+                }
+                out.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+            }
+            '<' => {
+                out.push_str("&lt;");
+                rest = &rest[1..];
+            }
+            '>' => {
+                out.push_str("&gt;");
+                rest = &rest[1..];
+            }
+            c => {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    if let Some(tag) = open.pop() {
+        return Err(TemplateError::Unbalanced(tag));
+    }
+    if allowed.contains(&"task") && !out.contains("{task}") {
+        return Err(TemplateError::MissingPlaceholder("{task}"));
+    }
+    Ok(out)
+}
+
+/// Подставить значения `{name}`. Значения должны быть уже HTML-безопасны.
+pub fn render(template: &str, vars: &[(&str, &dyn Display)]) -> String {
+    let mut s = template.to_string();
+    for (name, value) in vars {
+        s = s.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+    s
+}
+
+/// Заполнить шаблон чата значениями. `mention` — уже HTML, `task` — текст
+/// задачи капчи (для приветствия пусто). Название чата запрашиваем, только
+/// если оно нужно шаблону.
+pub async fn fill(
+    bot: &Bot,
+    state: &AppState,
+    chat_id: ChatId,
+    lang: Lang,
+    template: &str,
+    mention: &str,
+    task: &str,
+) -> String {
+    let settings = state.settings(chat_id);
+    let chat_title = if template.contains("{chat_title}") {
+        match api::call(state, chat_id, "get_chat", bot.get_chat(chat_id)).await {
+            Ok(c) => c.title().map(escape).unwrap_or_default(),
+            Err(_) => String::new(),
+        }
+    } else {
+        String::new()
+    };
+    let rules_link = settings
+        .rules_link
+        .as_deref()
+        .map(|url| {
+            format!(
+                "<a href=\"{}\">{}</a>",
+                escape(url),
+                tr!(lang, "templates.rules_label")
+            )
+        })
+        .unwrap_or_default();
+    render(
+        template,
+        &[
+            ("mention", &mention),
+            ("chat_title", &chat_title),
+            ("timeout", &settings.captcha_timeout_secs),
+            ("rules_link", &rules_link),
+            ("task", &task),
+        ],
+    )
+}
+
+/// `<b>`, `</i>`, `<a href=…>` — а не `<3` или `< 5`.
+fn looks_like_tag(s: &str) -> bool {
+    let mut it = s.chars().skip(1);
+    match it.next() {
+        Some('/') => it.next().is_some_and(|c| c.is_ascii_alphabetic()),
+        Some(c) => c.is_ascii_alphabetic(),
+        None => false,
+    }
+}
+
+/// Длина HTML-сущности в начале `s` (`&lt;`, `&#39;`, ...), 0 — не сущность.
+fn entity_len(s: &str) -> usize {
+    let Some(end) = s[1..].find(';').map(|i| i + 1) else {
+        return 0;
+    };
+    let body = &s[1..end];
+    let ok = match body.strip_prefix('#') {
+        Some(num) => match num.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => matches!(body, "lt" | "gt" | "amp" | "quot"),
+    };
+    if ok {
+        end + 1
+    } else {
+        0
+    }
+}
+
+/// Разобрать открывающий тег; вернуть его имя для проверки закрытия.
+fn check_open_tag(inner: &str) -> Result<String, TemplateError> {
+    let inner = inner.trim();
+    let (name, attrs) = inner
+        .split_once(char::is_whitespace)
+        .map_or((inner, ""), |(n, a)| (n, a.trim()));
+    let name = name.to_ascii_lowercase();
+    if !TAGS.contains(&name.as_str()) {
+        return Err(TemplateError::BadTag(name));
+    }
+    let attr_ok = match name.as_str() {
+        "a" => quoted_attr(attrs, "href").is_some_and(|v| !v.is_empty()),
+        "span" => quoted_attr(attrs, "class") == Some("tg-spoiler"),
+        "code" => attrs.is_empty() || quoted_attr(attrs, "class").is_some(),
+        "blockquote" => attrs.is_empty() || attrs == "expandable",
+        _ => attrs.is_empty(),
+    };
+    if !attr_ok {
+        return Err(TemplateError::BadTag(name));
+    }
+    Ok(name)
+}
+
+/// `name="value"` -> `value`; ровно один атрибут, в двойных кавычках.
+fn quoted_attr<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let v = attrs.strip_prefix(name)?.trim_start().strip_prefix('=')?;
+    let v = v.trim_start().strip_prefix('"')?.strip_suffix('"')?;
+    (!v.contains(['"', '<', '>'])).then_some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_escapes_stray_markup() {
+        assert_eq!(
+            sanitize("Hi {mention} <3 & welcome > all", WELCOME).unwrap(),
+            "Hi {mention} &lt;3 &amp; welcome &gt; all"
+        );
+        assert_eq!(
+            sanitize(
+                "<b>{chat_title}</b> &amp; <a href=\"https://t.me/x\">FAQ</a>",
+                WELCOME
+            )
+            .unwrap(),
+            "<b>{chat_title}</b> &amp; <a href=\"https://t.me/x\">FAQ</a>"
+        );
+    }
+
+    #[test]
+    fn sanitize_rejects_broken_templates() {
+        assert_eq!(
+            sanitize("Hi {name}", WELCOME),
+            Err(TemplateError::UnknownPlaceholder("{name}".into()))
+        );
+        assert_eq!(
+            sanitize("Hi {mention", WELCOME),
+            Err(TemplateError::UnclosedBrace)
+        );
+        assert_eq!(
+            sanitize("<script>x</script>", WELCOME),
+            Err(TemplateError::BadTag("script".into()))
+        );
+        assert_eq!(
+            sanitize("<b><i>x</b></i>", WELCOME),
+            Err(TemplateError::Unbalanced("b".into()))
+        );
+        assert_eq!(
+            sanitize("<b>x", WELCOME),
+            Err(TemplateError::Unbalanced("b".into()))
+        );
+        assert_eq!(
+            sanitize("<a href=\"{rules_link}\">x</a>", WELCOME),
+            Err(TemplateError::PlaceholderInTag)
+        );
+        assert_eq!(
+            sanitize("<a>x</a>", WELCOME),
+            Err(TemplateError::BadTag("a".into()))
+        );
+        assert_eq!(
+            sanitize("{mention}, hi", CAPTCHA),
+            Err(TemplateError::MissingPlaceholder("{task}"))
+        );
+        assert_eq!(
+            sanitize(&"x".repeat(MAX_LEN + 1), WELCOME),
+            Err(TemplateError::TooLong)
+        );
+    }
+
+    #[test]
+    fn render_substitutes_values() {
+        let t = sanitize("{mention}: {task} ({timeout}s)", CAPTCHA).unwrap();
+        assert_eq!(
+            render(
+                &t,
+                &[("mention", &"@bob"), ("task", &"press"), ("timeout", &60)]
+            ),
+            "@bob: press (60s)"
+        );
+    }
+}
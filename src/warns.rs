@@ -0,0 +1,251 @@
+// src/warns.rs
+
+//! Предупреждения участникам.
+//! - Вручную: `/warn [причина]`, `/unwarn`, `/warns` — ответом на сообщение.
+//! - Автоматически: правило фильтра с действием `warn`.
+//!
+//! Считаются по (чат, участник), хранятся в `Store` и сгорают через
+//! `WARN_EXPIRE_HOURS`. На `WARN_LIMIT`-м — `WARN_ACTION` (мут или бан тем же
+//! `captcha::apply_punishment`, что и после капчи), счёт начинается заново.
+
+use crate::admins;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::captcha::{apply_punishment, Punishment};
+use crate::config::{Settings, WarnAction};
+use crate::i18n::{tr, Lang};
+use crate::state::AppState;
+use crate::utils::mention;
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::utils::html::escape;
+
+/// Одно предупреждение.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub at: DateTime<Utc>,
+    /// Кто выдал; `None` — фильтр.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<u64>,
+    pub reason: String,
+}
+
+/// Итог нового предупреждения.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// Сколько их теперь (вместе с новым).
+    pub count: usize,
+    /// Порог чата; 0 — без наказания.
+    pub limit: u32,
+    /// Дошли до порога: что применили (счёт обнулён).
+    pub punished: Option<WarnAction>,
+}
+
+impl Outcome {
+    /// «2/3» или просто «2», если порога нет.
+    pub fn counter(&self) -> String {
+        match self.limit {
+            0 => self.count.to_string(),
+            n => format!("{}/{n}", self.count),
+        }
+    }
+}
+
+fn expire(s: &Settings) -> Option<ChronoDuration> {
+    (s.warn_expire_hours > 0).then(|| ChronoDuration::hours(s.warn_expire_hours as i64))
+}
+
+fn reached(count: usize, limit: u32) -> bool {
+    limit > 0 && count >= limit as usize
+}
+
+/// Выдать предупреждение; на пороге — наказать.
+pub async fn warn(
+    bot: &Bot,
+    state: &AppState,
+    chat: ChatId,
+    user: UserId,
+    by: Option<UserId>,
+    reason: &str,
+) -> Outcome {
+    let s = state.settings(chat);
+    let warning = Warning {
+        at: Utc::now(),
+        by: by.map(|u| u.0),
+        reason: reason.to_string(),
+    };
+    let count = state.update_warnings(AppState::key(chat, user), expire(&s), |list| {
+        list.push(warning);
+        let n = list.len();
+        if reached(n, s.warn_limit) {
+            list.clear();
+        }
+        n
+    });
+    let out = Outcome {
+        count,
+        limit: s.warn_limit,
+        punished: reached(count, s.warn_limit).then_some(s.warn_action),
+    };
+
+    state.metrics.warning();
+    state.record(AuditEntry::new(
+        chat,
+        by,
+        user.0.to_string(),
+        AuditAction::Warn,
+        format!("{reason} ({})", out.counter()),
+    ));
+    if let Some(action) = out.punished {
+        let p = match action {
+            WarnAction::Mute => Punishment::Mute,
+            WarnAction::Ban => Punishment::Ban(None),
+        };
+        let reason = format!("warnings: {}", out.counter());
+        apply_punishment(bot, state, chat, user, p, &reason).await;
+    }
+    out
+}
+
+/// Уведомление в чат о новом предупреждении (или о наказании за порог).
+pub fn notice(lang: Lang, who: &str, reason: &str, out: &Outcome) -> String {
+    match out.punished {
+        Some(WarnAction::Mute) => tr!(lang, "warns.muted", mention = who, count = out.count),
+        Some(WarnAction::Ban) => tr!(lang, "warns.banned", mention = who, count = out.count),
+        None => tr!(
+            lang,
+            "warns.warned",
+            mention = who,
+            count = out.counter(),
+            reason = reason
+        ),
+    }
+}
+
+/* ======================== Команды ======================== */
+
+/// `/warn [причина]`, `/unwarn`, `/warns` — ответом на сообщение участника.
+pub async fn command(
+    bot: &Bot,
+    state: &AppState,
+    msg: &Message,
+    lang: Lang,
+    cmd: &str,
+    arg: Option<&str>,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let target = msg
+        .reply_to_message()
+        .filter(|r| r.sender_chat.is_none())
+        .and_then(|r| r.from.as_ref());
+    let Some(target) = target.filter(|_| !chat.is_user()) else {
+        bot.send_message(chat, tr!(lang, "warns.reply_needed"))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        return Ok(());
+    };
+    let who = mention(bot, chat, target.id).await;
+    let settings = state.settings(chat);
+    let key = AppState::key(chat, target.id);
+
+    let reply = match cmd {
+        "warn" => {
+            if target.is_bot || admins::is_moderator(bot, state, chat, target.id).await {
+                tr!(lang, "warns.not_members").to_string()
+            } else {
+                let reason = arg.map(str::trim).filter(|r| !r.is_empty());
+                let logged = reason.unwrap_or("manual");
+                let shown = match reason {
+                    Some(r) => escape(r),
+                    None => tr!(lang, "warns.no_reason").to_string(),
+                };
+                let by = msg.from.as_ref().map(|u| u.id);
+                let out = warn(bot, state, chat, target.id, by, logged).await;
+                notice(lang, &who, &shown, &out)
+            }
+        }
+        "unwarn" => {
+            let removed = state.update_warnings(key, expire(&settings), |list| {
+                list.pop().map(|w| (w, list.len()))
+            });
+            match removed {
+                Some((w, left)) => {
+                    state.record(AuditEntry::new(
+                        chat,
+                        msg.from.as_ref().map(|u| u.id),
+                        target.id.0.to_string(),
+                        AuditAction::Unwarn,
+                        w.reason,
+                    ));
+                    tr!(lang, "warns.removed", mention = who, count = left)
+                }
+                None => tr!(lang, "warns.none", mention = who),
+            }
+        }
+        _ => {
+            let list = state.warnings(key, expire(&settings));
+            if list.is_empty() {
+                tr!(lang, "warns.none", mention = who)
+            } else {
+                let mut lines = vec![tr!(
+                    lang,
+                    "warns.list_title",
+                    mention = who,
+                    count = list.len()
+                )];
+                lines.extend(list.iter().map(|w| {
+                    format!(
+                        "• {} — {}",
+                        w.at.format("%Y-%m-%d %H:%M"),
+                        escape(&w.reason)
+                    )
+                }));
+                lines.join("\n")
+            }
+        }
+    };
+
+    bot.send_message(chat, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_and_counter() {
+        assert!(!reached(2, 3));
+        assert!(reached(3, 3));
+        assert!(!reached(10, 0));
+
+        let out = Outcome {
+            count: 2,
+            limit: 3,
+            punished: None,
+        };
+        assert_eq!(out.counter(), "2/3");
+        let out = Outcome {
+            count: 4,
+            limit: 0,
+            punished: None,
+        };
+        assert_eq!(out.counter(), "4");
+    }
+
+    #[test]
+    fn warning_json_skips_filter_author() {
+        let w = Warning {
+            at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            by: None,
+            reason: "filter".into(),
+        };
+        let json = serde_json::to_string(&w).unwrap();
+        assert!(!json.contains("by"));
+        assert_eq!(serde_json::from_str::<Warning>(&json).unwrap(), w);
+    }
+}